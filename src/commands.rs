@@ -1,12 +1,19 @@
 //! Sub-commands of `git-branchless`.
 
+pub mod absorb;
+pub mod amend;
+pub mod fold;
 pub mod gc;
 pub mod hide;
 pub mod hooks;
 pub mod init;
 pub mod r#move;
 pub mod navigation;
+pub mod obsmarkers;
 pub mod restack;
+pub mod reword;
 pub mod smartlog;
+pub mod snapshot;
+pub mod split;
 pub mod undo;
 pub mod wrap;
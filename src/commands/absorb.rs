@@ -0,0 +1,336 @@
+//! Absorb staged changes into the commits in the current stack which they
+//! belong to.
+//!
+//! Each staged hunk is blamed against the current stack (the commits between
+//! the main branch and `HEAD`). If all of the lines which the hunk changes
+//! were last touched by the same commit in the stack, then the hunk is amended
+//! into that commit. The rewritten commits' descendants are rebased in memory,
+//! and any hunks which couldn't be absorbed are left staged.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+
+use crate::commands::restack::restack_branches;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::formatting::{printable_styled_string, Glyphs};
+use crate::core::graph::{make_graph, BranchOids, HeadOid, MainBranchOid};
+use crate::core::hunks::{apply_hunks_to_tree, get_hunks, Hunk};
+use crate::core::mergebase::MergeBaseDb;
+use crate::core::rewrite::{
+    execute_rebase_plan, find_latest_rewrite_target, friendly_describe_commit, make_rebase_plan,
+    make_rewritten_committer, move_head_in_memory, sort_commits, ExecuteRebasePlanResult,
+};
+use crate::util::{
+    get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid, get_repo,
+    wrap_git_error, GitExecutable,
+};
+
+/// Find the commit which a hunk should be absorbed into.
+///
+/// Returns: The commit, and the hunk with its line numbers adjusted to match
+/// the version of the file in that commit. Returns `None` if the lines changed
+/// by the hunk weren't all last touched by the same commit in the stack.
+fn find_absorb_target(
+    blame: &git2::Blame,
+    stack_oids: &HashSet<git2::Oid>,
+    hunk: &Hunk,
+) -> Option<(git2::Oid, Hunk)> {
+    // For a hunk which only adds lines, use the lines surrounding the
+    // insertion point instead.
+    let line_numbers: Vec<usize> = if hunk.old_lines > 0 {
+        (hunk.old_start..hunk.old_start + hunk.old_lines).collect()
+    } else {
+        vec![hunk.old_start, hunk.old_start + 1]
+            .into_iter()
+            .filter(|line_number| blame.get_line(*line_number).is_some())
+            .collect()
+    };
+
+    let mut target_oid = None;
+    let mut orig_line_numbers = Vec::new();
+    for line_number in line_numbers.iter() {
+        let blame_hunk = blame.get_line(*line_number)?;
+        let commit_oid = blame_hunk.final_commit_id();
+        if !stack_oids.contains(&commit_oid) || target_oid.unwrap_or(commit_oid) != commit_oid {
+            return None;
+        }
+        target_oid = Some(commit_oid);
+        orig_line_numbers
+            .push(blame_hunk.orig_start_line() + (line_number - blame_hunk.final_start_line()));
+    }
+    let target_oid = target_oid?;
+
+    // The lines must also be adjacent in the target commit. Otherwise, a later
+    // commit removed some lines from between them.
+    if orig_line_numbers
+        .windows(2)
+        .any(|window| window[1] != window[0] + 1)
+    {
+        return None;
+    }
+
+    let old_start = if hunk.old_lines > 0 || hunk.old_start == line_numbers[0] {
+        orig_line_numbers[0]
+    } else {
+        orig_line_numbers[0] - 1
+    };
+    Some((
+        target_oid,
+        Hunk {
+            old_start,
+            ..hunk.clone()
+        },
+    ))
+}
+
+/// Move `HEAD` to the newest version of the commit which it points to, without
+/// touching the index or the working copy.
+///
+/// Relative to the new `HEAD`, only the hunks which weren't absorbed are still
+/// staged, so the index doesn't need to be updated.
+fn move_head_to_rewrite_target(
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+) -> anyhow::Result<()> {
+    if let Some(head_oid) = get_head_oid(repo)? {
+        let target_oid = find_latest_rewrite_target(repo, merge_base_db, event_log_db, head_oid)?;
+        if target_oid != head_oid {
+            move_head_in_memory(repo, event_log_db, event_tx_id, target_oid)?;
+        }
+    }
+    Ok(())
+}
+
+fn describe_hunk(path: &Path, hunk: &Hunk) -> String {
+    format!("{} {}", path.to_string_lossy(), hunk.header)
+}
+
+/// Amend the staged changes into the commits in the current stack which last
+/// touched the same lines, and restack their descendants.
+///
+/// The descendants are only rebased in memory. If that would cause a merge
+/// conflict, then nothing is changed.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn absorb(git_executable: &GitExecutable) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::detect();
+    let repo = get_repo()?;
+    let head_oid = match get_head_oid(&repo)? {
+        Some(head_oid) => head_oid,
+        None => {
+            println!("No commit is currently checked out, so there is nothing to absorb into.");
+            return Ok(1);
+        }
+    };
+    let head_commit = repo.find_commit(head_oid).map_err(wrap_git_error)?;
+
+    let mut index = repo.index().map_err(wrap_git_error)?;
+    let index_tree_oid = index.write_tree().map_err(wrap_git_error)?;
+    if index_tree_oid == head_commit.tree_id() {
+        println!("There are no staged changes to absorb.");
+        return Ok(0);
+    }
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+    let main_branch_oid = get_main_branch_oid(&repo)?;
+    let branch_oid_to_names = get_branch_oid_to_names(&repo)?;
+    let graph = make_graph(
+        &repo,
+        &merge_base_db,
+        &event_replayer,
+        event_replayer.make_default_cursor(),
+        &HeadOid(Some(head_oid)),
+        &MainBranchOid(main_branch_oid),
+        &BranchOids(branch_oid_to_names.keys().copied().collect()),
+        true,
+    )?;
+
+    // Ordered from newest to oldest.
+    let mut stack_oids = Vec::new();
+    let mut current_oid = Some(head_oid);
+    while let Some(oid) = current_oid {
+        match graph.get(&oid) {
+            Some(node) if !node.is_main && node.commit.parent_count() == 1 => {
+                stack_oids.push(oid);
+                current_oid = node.parent;
+            }
+            _ => break,
+        }
+    }
+    if stack_oids.is_empty() {
+        println!("There are no commits in the current stack to absorb changes into.");
+        return Ok(1);
+    }
+    let stack_oid_set: HashSet<git2::Oid> = stack_oids.iter().copied().collect();
+
+    let index_tree = repo.find_tree(index_tree_oid).map_err(wrap_git_error)?;
+    let head_tree = head_commit.tree().map_err(wrap_git_error)?;
+    let file_hunks = get_hunks(&repo, Some(&head_tree), &index_tree, 0)?;
+    let mut absorbed_hunks: HashMap<git2::Oid, Vec<(PathBuf, Vec<Hunk>)>> = HashMap::new();
+    let mut unabsorbed_descriptions = Vec::new();
+    for file in file_hunks.iter() {
+        let blame = match repo.blame_file(
+            &file.path,
+            Some(git2::BlameOptions::new().newest_commit(head_oid)),
+        ) {
+            Ok(blame) => Some(blame),
+            // The file may not exist in `HEAD`.
+            Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => return Err(wrap_git_error(err)),
+        };
+        for hunk in file.hunks.iter() {
+            let target = match &blame {
+                Some(blame) if !hunk.lines.is_empty() => {
+                    find_absorb_target(blame, &stack_oid_set, hunk)
+                }
+                _ => None,
+            };
+            match target {
+                Some((target_oid, target_hunk)) => {
+                    let target_files = absorbed_hunks.entry(target_oid).or_default();
+                    match target_files.iter_mut().find(|(path, _)| path == &file.path) {
+                        Some((_, hunks)) => hunks.push(target_hunk),
+                        None => target_files.push((file.path.clone(), vec![target_hunk])),
+                    }
+                    println!(
+                        "Absorbing {} into: {}",
+                        describe_hunk(&file.path, hunk),
+                        printable_styled_string(
+                            &glyphs,
+                            friendly_describe_commit(&repo, target_oid)?
+                        )?
+                    );
+                }
+                None => unabsorbed_descriptions.push(describe_hunk(&file.path, hunk)),
+            }
+        }
+    }
+    for description in unabsorbed_descriptions.iter() {
+        println!("Could not absorb {}, leaving it staged.", description);
+    }
+    if absorbed_hunks.is_empty() {
+        return Ok(1);
+    }
+
+    // Apply the hunks to each of the target commits. The line numbers of the
+    // hunks are relative to the original version of each target commit, so
+    // the absorbed commits keep their original parents for now.
+    let mut absorbed_oids: HashMap<git2::Oid, git2::Oid> = HashMap::new();
+    for target_oid in stack_oids.iter() {
+        let target_files = match absorbed_hunks.get_mut(target_oid) {
+            Some(target_files) => target_files,
+            None => continue,
+        };
+        for (_, hunks) in target_files.iter_mut() {
+            hunks.sort_by_key(|hunk| hunk.old_start);
+        }
+
+        let commit = repo.find_commit(*target_oid).map_err(wrap_git_error)?;
+        let tree = commit.tree().map_err(wrap_git_error)?;
+        let tree_oid = apply_hunks_to_tree(&repo, &tree, target_files)?;
+        let tree = repo.find_tree(tree_oid).map_err(wrap_git_error)?;
+        let message = match commit.message_raw() {
+            Some(message) => message,
+            None => anyhow::bail!(
+                "Could not decode commit message for commit: {:?}",
+                commit.id()
+            ),
+        };
+        let committer = make_rewritten_committer(&repo, &commit)?;
+        let parents: Vec<git2::Commit> = commit.parents().collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let absorbed_oid = repo
+            .commit(None, &commit.author(), &committer, message, &tree, &parents)
+            .with_context(|| "Committing absorbed changes")?;
+        absorbed_oids.insert(commit.id(), absorbed_oid);
+    }
+
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "absorb")?;
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+
+    // Rebase the descendants of the oldest target commit onto its absorbed
+    // version in a single in-memory rebase, picking the absorbed versions of
+    // the other target commits. An on-disk rebase would fail because of the
+    // hunks which are still staged, so if there's a merge conflict, give up
+    // before anything has been recorded.
+    let oldest_target_oid = match stack_oids
+        .iter()
+        .rev()
+        .find(|oid| absorbed_oids.contains_key(oid))
+    {
+        Some(oid) => *oid,
+        None => anyhow::bail!("BUG: no commits were absorbed into"),
+    };
+    let child_oids = sort_commits(
+        &graph,
+        graph[&oldest_target_oid]
+            .children
+            .iter()
+            .filter(|child_oid| graph[child_oid].is_visible)
+            .copied()
+            .collect(),
+    );
+    let mut result = ExecuteRebasePlanResult::Succeeded;
+    if !child_oids.is_empty() {
+        let mut rebase_plan = make_rebase_plan(
+            &repo,
+            &merge_base_db,
+            &graph,
+            &MainBranchOid(main_branch_oid),
+            &child_oids,
+        )?;
+        rebase_plan.replace_picked_commits(&absorbed_oids);
+        result = execute_rebase_plan(
+            &glyphs,
+            git_executable,
+            &repo,
+            event_tx_id,
+            &rebase_plan,
+            child_oids[0],
+            absorbed_oids[&oldest_target_oid],
+            false,
+            true,
+            false,
+        )?;
+        if let ExecuteRebasePlanResult::Failed { exit_code } = result {
+            println!("Could not absorb the staged changes, so no commits were changed.");
+            return Ok(exit_code);
+        }
+    }
+
+    // The rebase only recorded the rewrites of the absorbed commits, so
+    // record the rewrites of the original commits to their newest versions.
+    let mut events = Vec::new();
+    for target_oid in stack_oids.iter() {
+        if let Some(absorbed_oid) = absorbed_oids.get(target_oid) {
+            let new_oid =
+                find_latest_rewrite_target(&repo, &merge_base_db, &event_log_db, *absorbed_oid)?;
+            events.push(Event::RewriteEvent {
+                timestamp,
+                event_tx_id,
+                old_commit_oid: *target_oid,
+                new_commit_oid: new_oid,
+            });
+        }
+    }
+    event_log_db.add_events(events)?;
+    move_head_to_rewrite_target(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
+
+    let exit_code = restack_branches(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+    Ok(result.exit_code())
+}
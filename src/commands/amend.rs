@@ -0,0 +1,111 @@
+//! Amend the current commit with the changes in the working copy.
+//!
+//! Unlike `git commit --amend` followed by `git restack`, this doesn't check
+//! out any other commits: the amended commit is written directly with libgit2,
+//! and its descendants are rebased in-memory where possible. All of the
+//! resulting events are recorded in the same transaction, so that the whole
+//! operation can be undone at once.
+
+use std::time::SystemTime;
+
+use anyhow::Context;
+
+use crate::commands::restack::restack_branches;
+use crate::commands::snapshot::snapshot_working_copy;
+use crate::core::eventlog::{Event, EventLogDb};
+use crate::core::formatting::{printable_styled_string, Glyphs};
+use crate::core::mergebase::MergeBaseDb;
+use crate::core::rewrite::{
+    friendly_describe_commit, make_rewritten_committer, move_head_in_memory, restack_descendants,
+    ExecuteRebasePlanResult,
+};
+use crate::util::{get_db_conn, get_head_oid, get_repo, wrap_git_error, GitExecutable};
+
+/// Amend the `HEAD` commit with the changes to tracked files in the working
+/// copy, and restack its descendants on top of the amended commit.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn amend(git_executable: &GitExecutable) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::detect();
+    let repo = get_repo()?;
+    let head_oid = match get_head_oid(&repo)? {
+        Some(head_oid) => head_oid,
+        None => {
+            println!("No commit is currently checked out, so there is nothing to amend.");
+            return Ok(1);
+        }
+    };
+    let head_commit = repo.find_commit(head_oid).map_err(wrap_git_error)?;
+
+    let mut index = repo.index().map_err(wrap_git_error)?;
+    index
+        .update_all(["."], None)
+        .with_context(|| "Adding working copy changes to the index")?;
+    let tree_oid = index.write_tree().map_err(wrap_git_error)?;
+    if tree_oid == head_commit.tree_id() {
+        println!("There are no changes to tracked files to amend.");
+        return Ok(0);
+    }
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "amend")?;
+    snapshot_working_copy(&repo, git_executable, &mut event_log_db, event_tx_id)?;
+
+    let tree = repo.find_tree(tree_oid).map_err(wrap_git_error)?;
+    let committer = make_rewritten_committer(&repo, &head_commit)?;
+    let message = match head_commit.message_raw() {
+        Some(message) => message,
+        None => anyhow::bail!("Could not decode commit message for commit: {:?}", head_oid),
+    };
+    let parents: Vec<git2::Commit> = head_commit.parents().collect();
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+    let amended_oid = repo
+        .commit(
+            None,
+            &head_commit.author(),
+            &committer,
+            message,
+            &tree,
+            &parents,
+        )
+        .with_context(|| "Committing amended commit")?;
+    index.write().map_err(wrap_git_error)?;
+
+    move_head_in_memory(&repo, &mut event_log_db, event_tx_id, amended_oid)?;
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    event_log_db.add_events(vec![Event::RewriteEvent {
+        timestamp,
+        event_tx_id,
+        old_commit_oid: head_oid,
+        new_commit_oid: amended_oid,
+    }])?;
+    println!(
+        "Amended as: {}",
+        printable_styled_string(&glyphs, friendly_describe_commit(&repo, amended_oid)?)?
+    );
+
+    let result = restack_descendants(
+        &glyphs,
+        git_executable,
+        &repo,
+        &merge_base_db,
+        &mut event_log_db,
+        event_tx_id,
+        head_oid,
+    )?;
+    if let ExecuteRebasePlanResult::Failed { exit_code } = result {
+        return Ok(exit_code);
+    }
+
+    let exit_code = restack_branches(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+    Ok(result.exit_code())
+}
@@ -0,0 +1,257 @@
+//! Combine a linear run of adjacent commits into a single commit.
+//!
+//! The folded commit is written in memory, and each of the original commits is
+//! marked as rewritten into it, so that their descendants can be restacked on
+//! top of the folded commit and the whole operation can be undone at once.
+
+use std::convert::TryInto;
+use std::time::SystemTime;
+
+use anyhow::Context;
+
+use crate::commands::restack::restack_branches;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize};
+use crate::core::mergebase::MergeBaseDb;
+use crate::core::rewrite::{
+    friendly_describe_commit, make_current_graph, make_rewritten_committer, restack_descendants,
+    ExecuteRebasePlanResult,
+};
+use crate::util::{get_db_conn, get_repo, wrap_git_error, GitExecutable};
+
+/// Find the commits in the given revision range, ordered from oldest to
+/// newest.
+///
+/// The range is either of the form `<base>..<tip>`, which excludes `<base>`,
+/// or a single commit, which is treated as the range from that commit up to
+/// and including `HEAD`.
+///
+/// Returns: The commits, or an error message to show to the user if the range
+/// isn't a linear run of non-merge commits.
+fn resolve_fold_range<'repo>(
+    repo: &'repo git2::Repository,
+    range: &str,
+) -> anyhow::Result<Result<Vec<git2::Commit<'repo>>, String>> {
+    let revspec = match repo.revparse(range) {
+        Ok(revspec) => revspec,
+        Err(_) => return Ok(Err(format!("Commit not found: {}", range))),
+    };
+    let (base_oid, tip) = if revspec.mode().contains(git2::RevparseMode::RANGE) {
+        let (base, tip) = match (revspec.from(), revspec.to()) {
+            (Some(base), Some(tip)) => (base, tip),
+            _ => return Ok(Err(format!("Invalid commit range: {}", range))),
+        };
+        let base = base.peel_to_commit().map_err(wrap_git_error)?;
+        (
+            Some(base.id()),
+            tip.peel_to_commit().map_err(wrap_git_error)?,
+        )
+    } else {
+        let first = match revspec.from() {
+            Some(first) => first.peel_to_commit().map_err(wrap_git_error)?,
+            None => return Ok(Err(format!("Commit not found: {}", range))),
+        };
+        let tip = repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(wrap_git_error)?;
+        // Checking only the parent isn't enough, since a sibling of a commit
+        // in the stack has the same parent.
+        let is_ancestor = first.id() == tip.id()
+            || repo
+                .graph_descendant_of(tip.id(), first.id())
+                .map_err(wrap_git_error)?;
+        if !is_ancestor {
+            return Ok(Err(format!(
+                "The commits in {} do not form a linear range",
+                range
+            )));
+        }
+        (first.parent_ids().next(), tip)
+    };
+
+    // Check the ancestry up front, rather than walking all the way to the root
+    // commit to find out that the base isn't an ancestor of the tip.
+    if let Some(base_oid) = base_oid {
+        let is_ancestor = base_oid == tip.id()
+            || repo
+                .graph_descendant_of(tip.id(), base_oid)
+                .map_err(wrap_git_error)?;
+        if !is_ancestor {
+            return Ok(Err(format!(
+                "The commits in {} do not form a linear range",
+                range
+            )));
+        }
+    }
+
+    let mut commits = Vec::new();
+    let mut current = tip;
+    while Some(current.id()) != base_oid {
+        if current.parent_count() > 1 {
+            return Ok(Err(format!("Cannot fold merge commit: {}", current.id())));
+        }
+        let parent = current.parents().next();
+        commits.push(current);
+        current = match parent {
+            Some(parent) => parent,
+            None if base_oid.is_none() => break,
+            None => {
+                return Ok(Err(format!(
+                    "The commits in {} do not form a linear range",
+                    range
+                )))
+            }
+        };
+    }
+    commits.reverse();
+    Ok(Ok(commits))
+}
+
+/// Combine the messages of the folded commits.
+fn make_folded_message(commits: &[git2::Commit], first_message: bool) -> anyhow::Result<String> {
+    let messages = commits
+        .iter()
+        .map(|commit| match commit.message_raw() {
+            Some(message) => Ok(message.trim()),
+            None => anyhow::bail!(
+                "Could not decode commit message for commit: {:?}",
+                commit.id()
+            ),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let message = if first_message {
+        messages[0].to_string()
+    } else {
+        messages.join("\n\n")
+    };
+    let message = git2::message_prettify(message, None).map_err(wrap_git_error)?;
+    Ok(message)
+}
+
+/// Fold the commits in the given range into a single commit, and restack
+/// their descendants on top of it.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `range`: The commits to fold. Either a range of the form `<base>..<tip>`,
+///   or a single commit, in which case that commit and its descendants up to
+///   `HEAD` are folded.
+/// * `first_message`: Use only the first commit's message for the folded
+///   commit, rather than concatenating the messages of all folded commits.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn fold(
+    git_executable: &GitExecutable,
+    range: String,
+    first_message: bool,
+) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::detect();
+    let repo = get_repo()?;
+    let commits = match resolve_fold_range(&repo, &range)? {
+        Ok(commits) => commits,
+        Err(message) => {
+            println!("{}", message);
+            return Ok(1);
+        }
+    };
+    if commits.len() < 2 {
+        println!("There must be at least two commits to fold in: {}", range);
+        return Ok(1);
+    }
+    let first_commit = &commits[0];
+    let last_commit = &commits[commits.len() - 1];
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    {
+        let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+        let graph = make_current_graph(&repo, &merge_base_db, &event_replayer)?;
+        for commit in commits.iter() {
+            match graph.get(&commit.id()) {
+                Some(node) if node.is_main => {
+                    println!(
+                        "Commits on the main branch cannot be folded: {}",
+                        commit.id()
+                    );
+                    return Ok(1);
+                }
+                Some(node) if node.is_visible => {}
+                _ => {
+                    println!(
+                        "Commit is not visible, so it cannot be folded: {}",
+                        commit.id()
+                    );
+                    return Ok(1);
+                }
+            }
+        }
+    }
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "fold")?;
+
+    let message = make_folded_message(&commits, first_message)?;
+    let committer = make_rewritten_committer(&repo, first_commit)?;
+    let tree = last_commit.tree().map_err(wrap_git_error)?;
+    let parents: Vec<git2::Commit> = first_commit.parents().collect();
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+    let folded_oid = repo
+        .commit(
+            None,
+            &first_commit.author(),
+            &committer,
+            &message,
+            &tree,
+            &parents,
+        )
+        .with_context(|| "Committing folded commit")?;
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    event_log_db.add_events(
+        commits
+            .iter()
+            .map(|commit| Event::RewriteEvent {
+                timestamp,
+                event_tx_id,
+                old_commit_oid: commit.id(),
+                new_commit_oid: folded_oid,
+            })
+            .collect(),
+    )?;
+    println!(
+        "Folded {} into: {}",
+        Pluralize {
+            amount: commits.len().try_into()?,
+            singular: "commit",
+            plural: "commits",
+        }
+        .to_string(),
+        printable_styled_string(&glyphs, friendly_describe_commit(&repo, folded_oid)?)?
+    );
+
+    let mut result = ExecuteRebasePlanResult::Succeeded;
+    for commit in commits.iter() {
+        match restack_descendants(
+            &glyphs,
+            git_executable,
+            &repo,
+            &merge_base_db,
+            &mut event_log_db,
+            event_tx_id,
+            commit.id(),
+        )? {
+            ExecuteRebasePlanResult::Succeeded => {}
+            ExecuteRebasePlanResult::HookFailed => {
+                result = ExecuteRebasePlanResult::HookFailed;
+            }
+            ExecuteRebasePlanResult::Failed { exit_code } => return Ok(exit_code),
+        }
+    }
+
+    let exit_code = restack_branches(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+    Ok(result.exit_code())
+}
@@ -9,31 +9,107 @@
 //! garbage collection doesn't collect commits which branchless thinks are still
 //! visible.
 
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::time::SystemTime;
+
 use anyhow::Context;
 use fn_error_context::context;
 
-use crate::core::eventlog::{is_gc_ref, EventLogDb, EventReplayer};
+use crate::core::config::{
+    get_gc_compact_events_after, get_gc_merge_base_cache_size, get_gc_retain_hidden_for,
+    get_gc_retain_snapshots_for,
+};
+use crate::core::eventlog::{
+    compact_events, is_gc_ref, Event, EventCursor, EventLogDb, EventReplayer,
+};
+use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize};
 use crate::core::graph::{make_graph, BranchOids, CommitGraph, HeadOid, MainBranchOid};
 use crate::core::mergebase::MergeBaseDb;
+use crate::core::metadata::{
+    render_commit_metadata, CommitMessageProvider, CommitMetadataProvider, CommitOidProvider,
+};
 use crate::util::{
     get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid, get_repo,
 };
 
-fn find_dangling_references<'repo>(
+/// The references used to keep commits alive, as classified by garbage
+/// collection.
+struct GcReferences<'repo> {
+    /// References to commits which are no longer visible. These should be
+    /// deleted.
+    dangling: Vec<git2::Reference<'repo>>,
+
+    /// References to commits which are still visible, or which were hidden
+    /// too recently to be collected, or which store recent working copy
+    /// snapshots or recently-dropped stash entries.
+    retained: Vec<git2::Reference<'repo>>,
+}
+
+/// Determine whether the given commit was hidden after `retain_horizon`, in
+/// which case it shouldn't be collected yet.
+fn is_recently_hidden(
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    retain_horizon: SystemTime,
+    commit_oid: git2::Oid,
+) -> bool {
+    let event = match event_replayer.get_cursor_commit_latest_event(event_cursor, commit_oid) {
+        Some(event) => event,
+        None => return false,
+    };
+    let is_hidden = match event {
+        Event::HideEvent { .. } => true,
+        Event::RewriteEvent { old_commit_oid, .. } => *old_commit_oid == commit_oid,
+        Event::RefUpdateEvent { .. }
+        | Event::CommitEvent { .. }
+        | Event::UnhideEvent { .. }
+        | Event::WorkingCopySnapshotEvent { .. }
+        | Event::StashPushEvent { .. }
+        | Event::StashDropEvent { .. }
+        | Event::CommitOriginEvent { .. } => false,
+    };
+    is_hidden && event.get_timestamp() >= retain_horizon
+}
+
+fn find_gc_references<'repo>(
     repo: &'repo git2::Repository,
+    event_replayer: &EventReplayer,
+    retain_hidden_horizon: SystemTime,
+    retain_snapshots_horizon: SystemTime,
     graph: &CommitGraph,
-) -> anyhow::Result<Vec<git2::Reference<'repo>>> {
+) -> anyhow::Result<GcReferences<'repo>> {
+    let event_cursor = event_replayer.make_default_cursor();
+    let recent_snapshot_oids: HashSet<git2::Oid> = event_replayer
+        .get_events_since_cursor(event_replayer.make_cursor(0))
+        .iter()
+        .filter_map(|event| match event {
+            Event::WorkingCopySnapshotEvent { commit_oid, .. }
+            | Event::StashDropEvent { commit_oid, .. }
+                if event.get_timestamp() >= retain_snapshots_horizon =>
+            {
+                Some(*commit_oid)
+            }
+            _ => None,
+        })
+        .collect();
     let references = repo
         .references()
         .with_context(|| "Getting repo references")?;
 
-    let mut result = Vec::new();
+    let mut result = GcReferences {
+        dangling: Vec::new(),
+        retained: Vec::new(),
+    };
     for reference in references {
         let reference = reference.with_context(|| "Reading reference info")?;
         let reference_name = match reference.name() {
             Some(name) => name.to_owned(),
             None => continue,
         };
+        if !is_gc_ref(&reference_name) {
+            continue;
+        }
         let resolved_reference = reference
             .resolve()
             .with_context(|| format!("Resolving reference: {}", reference_name))?;
@@ -42,8 +118,18 @@ fn find_dangling_references<'repo>(
         // case of the reference not peeling to a valid commit. (It might be
         // a reference to a different kind of object.)
         if let Ok(commit) = resolved_reference.peel_to_commit() {
-            if is_gc_ref(&reference_name) && !graph.contains_key(&commit.id()) {
-                result.push(reference)
+            if graph.contains_key(&commit.id())
+                || is_recently_hidden(
+                    event_replayer,
+                    event_cursor,
+                    retain_hidden_horizon,
+                    commit.id(),
+                )
+                || recent_snapshot_oids.contains(&commit.id())
+            {
+                result.retained.push(reference)
+            } else {
+                result.dangling.push(reference)
             }
         }
     }
@@ -78,14 +164,28 @@ pub fn mark_commit_reachable(repo: &git2::Repository, commit_oid: git2::Oid) ->
 
 /// Run branchless's garbage collection.
 ///
-/// Frees any references to commits which are no longer visible in the smartlog.
+/// Frees any references to commits which are no longer visible in the smartlog
+/// (unless they were hidden within the last `branchless.gc.retainHiddenFor`
+/// days, or store a working copy snapshot or stash entry which was taken or
+/// dropped within the last `branchless.gc.retainSnapshotsFor` days), compacts
+/// old events in the event log, and prunes the merge-base cache.
+///
+/// Args:
+/// * `dry_run`: If `true`, only report what would be collected, without
+///   deleting any references, events, or cache entries.
+/// * `clear_cache`: If `true`, remove all entries from the merge-base cache,
+///   rather than only those which are missing or least-recently-used.
+/// * `show_statistics`: If `true`, report how many references, events, and
+///   cache entries were processed. Otherwise, only report that garbage is
+///   being collected.
 #[context("Running garbage-collection")]
-pub fn gc() -> anyhow::Result<()> {
+pub fn gc(dry_run: bool, clear_cache: bool, show_statistics: bool) -> anyhow::Result<()> {
+    let glyphs = Glyphs::detect();
     let repo = get_repo()?;
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
-    let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
     let head_oid = get_head_oid(&repo)?;
     let main_branch_oid = get_main_branch_oid(&repo)?;
     let branch_oid_to_names = get_branch_oid_to_names(&repo)?;
@@ -101,12 +201,150 @@ pub fn gc() -> anyhow::Result<()> {
         true,
     )?;
 
-    println!("branchless: collecting garbage");
-    let dangling_references = find_dangling_references(&repo, &graph)?;
-    for mut reference in dangling_references.into_iter() {
-        reference
-            .delete()
-            .with_context(|| format!("Deleting reference {:?}", reference.name()))?;
+    if dry_run {
+        println!("branchless: collecting garbage (dry run)");
+    } else {
+        println!("branchless: collecting garbage");
     }
+    let retain_hidden_horizon = SystemTime::now()
+        .checked_sub(get_gc_retain_hidden_for(&repo)?)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let retain_snapshots_horizon = SystemTime::now()
+        .checked_sub(get_gc_retain_snapshots_for(&repo)?)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let GcReferences { dangling, retained } = find_gc_references(
+        &repo,
+        &event_replayer,
+        retain_hidden_horizon,
+        retain_snapshots_horizon,
+        &graph,
+    )?;
+    let num_dangling_references = dangling.len();
+    if !dry_run {
+        for mut reference in dangling.into_iter() {
+            reference
+                .delete()
+                .with_context(|| format!("Deleting reference {:?}", reference.name()))?;
+        }
+    }
+
+    let horizon = SystemTime::now()
+        .checked_sub(get_gc_compact_events_after(&repo)?)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let events = event_log_db.get_events()?;
+    let num_events = events.len();
+    let num_compacted_events = if dry_run {
+        num_events - compact_events(events, horizon, |oid| repo.find_commit(oid).is_ok()).len()
+    } else {
+        event_log_db.compact(&repo, horizon)?
+    };
+
+    // Prune the merge-base cache only now that the commit graph has been
+    // built, since building the graph populates the cache with its own
+    // merge-base queries, which would otherwise push it back over its size
+    // limit.
+    let num_entries = merge_base_db.get_num_entries()?;
+    let num_removed_entries = match (dry_run, clear_cache) {
+        (true, true) => num_entries,
+        (true, false) => {
+            let num_missing_entries = merge_base_db.get_num_missing_entries(&repo)?;
+            num_missing_entries
+                + (num_entries - num_missing_entries)
+                    .saturating_sub(get_gc_merge_base_cache_size(&repo)?)
+        }
+        (false, true) => merge_base_db.clear()?,
+        (false, false) => {
+            merge_base_db.remove_missing_entries(&repo)?
+                + merge_base_db.evict_least_recently_used(get_gc_merge_base_cache_size(&repo)?)?
+        }
+    };
+
+    if !show_statistics {
+        return Ok(());
+    }
+
+    let pluralize_references = |amount: usize| {
+        Pluralize {
+            amount: amount.try_into().unwrap(),
+            singular: "reference",
+            plural: "references",
+        }
+        .to_string()
+    };
+    let pluralize_events = |amount: usize| {
+        Pluralize {
+            amount: amount.try_into().unwrap(),
+            singular: "event",
+            plural: "events",
+        }
+        .to_string()
+    };
+    let pluralize_entries = |amount: usize| {
+        Pluralize {
+            amount: amount.try_into().unwrap(),
+            singular: "entry",
+            plural: "entries",
+        }
+        .to_string()
+    };
+    if dry_run {
+        println!(
+            "branchless: would delete {}, keep {}",
+            pluralize_references(num_dangling_references),
+            pluralize_references(retained.len()),
+        );
+        println!(
+            "branchless: would compact {}, leaving {} in the event log",
+            pluralize_events(num_compacted_events),
+            pluralize_events(num_events - num_compacted_events),
+        );
+        if clear_cache {
+            println!(
+                "branchless: would remove {} from the merge-base cache",
+                pluralize_entries(num_removed_entries),
+            );
+        } else {
+            println!(
+                "branchless: would remove {}, leaving {} in the merge-base cache",
+                pluralize_entries(num_removed_entries),
+                pluralize_entries(num_entries - num_removed_entries),
+            );
+        }
+    } else {
+        println!(
+            "branchless: deleted {}, kept {}",
+            pluralize_references(num_dangling_references),
+            pluralize_references(retained.len()),
+        );
+        println!(
+            "branchless: compacted {}, leaving {} in the event log",
+            pluralize_events(num_compacted_events),
+            pluralize_events(num_events - num_compacted_events),
+        );
+        println!(
+            "branchless: removed {}, leaving {} in the merge-base cache",
+            pluralize_entries(num_removed_entries),
+            pluralize_entries(merge_base_db.get_num_entries()?),
+        );
+    }
+
+    let oldest_retained_commit = retained
+        .iter()
+        .filter_map(|reference| reference.peel_to_commit().ok())
+        .min_by_key(|commit| commit.time());
+    if let Some(commit) = oldest_retained_commit {
+        let commit_text = render_commit_metadata(
+            &commit,
+            &mut [
+                &mut CommitOidProvider::new(true)? as &mut dyn CommitMetadataProvider,
+                &mut CommitMessageProvider::new()?,
+            ],
+        )?;
+        println!(
+            "branchless: oldest retained commit: {}",
+            printable_styled_string(&glyphs, commit_text)?
+        );
+    }
+
     Ok(())
 }
@@ -17,16 +17,24 @@ use console::style;
 use fn_error_context::context;
 
 use crate::commands::gc::mark_commit_reachable;
+use crate::commands::obsmarkers::record_obsmarkers;
+use crate::commands::snapshot::record_stash_changes;
 use crate::core::config::{get_restack_warn_abandoned, RESTACK_WARN_ABANDONED_CONFIG_KEY};
 use crate::core::eventlog::{
-    should_ignore_ref_updates, Event, EventLogDb, EventReplayer, EventTransactionId,
+    should_ignore_ref_updates, CommitOriginType, Event, EventLogDb, EventReplayer,
+    EventTransactionId,
 };
 use crate::core::formatting::Pluralize;
 use crate::core::graph::{make_graph, BranchOids, HeadOid, MainBranchOid};
 use crate::core::mergebase::MergeBaseDb;
-use crate::core::rewrite::find_abandoned_children;
+use crate::core::obsmarkers::REMOTE_OBSMARKERS_REF_PREFIX;
+use crate::core::rewrite::{
+    find_abandoned_children, BRANCHLESS_IN_MEMORY_COMMIT_OID_ENV_VAR,
+    BRANCHLESS_SKIP_ABANDONED_WARNING_ENV_VAR,
+};
 use crate::util::{
     get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid, get_repo,
+    get_worktree_head_ref_name,
 };
 
 /// Detect if an interactive rebase has started but not completed.
@@ -126,13 +134,14 @@ pub fn hook_post_rewrite(rewrite_type: &str) -> anyhow::Result<()> {
 
     event_log_db.add_events(events)?;
 
-    let should_check_abandoned_commits = get_restack_warn_abandoned(&repo)?;
+    let should_check_abandoned_commits = get_restack_warn_abandoned(&repo)?
+        && std::env::var_os(BRANCHLESS_SKIP_ABANDONED_WARNING_ENV_VAR).is_none();
     if is_spurious_event || !should_check_abandoned_commits {
         return Ok(());
     }
 
     let merge_base_db = MergeBaseDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
     let head_oid = get_head_oid(&repo)?;
     let main_branch_oid = get_main_branch_oid(&repo)?;
     let branch_oid_to_names = get_branch_oid_to_names(&repo)?;
@@ -262,16 +271,86 @@ pub fn hook_post_checkout(
         event_tx_id,
         old_ref: Some(String::from(previous_head_ref)),
         new_ref: Some(String::from(current_head_ref)),
-        ref_name: String::from("HEAD"),
+        ref_name: get_worktree_head_ref_name(&repo)?,
         message: None,
     }])?;
     Ok(())
 }
 
+/// Parse the lines which `git cherry-pick -x` and `git revert` add to the
+/// commit message to record the source commit.
+fn parse_commit_message_origins(message: &str) -> Vec<(CommitOriginType, git2::Oid)> {
+    message
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (origin_type, rest) =
+                if let Some(rest) = line.strip_prefix("(cherry picked from commit ") {
+                    (CommitOriginType::CherryPick, rest)
+                } else if let Some(rest) = line.strip_prefix("This reverts commit ") {
+                    (CommitOriginType::Revert, rest)
+                } else {
+                    return None;
+                };
+
+            // `git2::Oid::from_str` accepts abbreviated OIDs (by padding them
+            // with zeroes), so make sure that we have a full OID.
+            let source_oid: String = rest.chars().take_while(char::is_ascii_hexdigit).collect();
+            if source_oid.len() != 40 {
+                return None;
+            }
+            let source_oid = git2::Oid::from_str(&source_oid).ok()?;
+            Some((origin_type, source_oid))
+        })
+        .collect()
+}
+
+/// Determine which commits the given commit was derived from, if it was
+/// created by a cherry-pick, revert, or merge.
+///
+/// Git has already removed `CHERRY_PICK_HEAD` or `REVERT_HEAD` by the time
+/// the `post-commit` hook runs in some cases (such as after `git revert` or
+/// `git cherry-pick --continue`), so we also check the commit message. Merge
+/// parents are read from the commit itself, since `MERGE_HEAD` has likewise
+/// been removed.
+#[context("Finding origins of commit {:?}", commit.id())]
+fn find_commit_origins(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+) -> anyhow::Result<Vec<(CommitOriginType, git2::Oid)>> {
+    let mut origins = Vec::new();
+    for (ref_name, origin_type) in &[
+        ("CHERRY_PICK_HEAD", CommitOriginType::CherryPick),
+        ("REVERT_HEAD", CommitOriginType::Revert),
+    ] {
+        if let Ok(source_oid) = repo.refname_to_id(ref_name) {
+            origins.push((*origin_type, source_oid));
+        }
+    }
+    if origins.is_empty() {
+        origins.extend(parse_commit_message_origins(
+            commit.message().unwrap_or_default(),
+        ));
+    }
+    origins.extend(
+        commit
+            .parent_ids()
+            .skip(1)
+            .map(|parent_oid| (CommitOriginType::Merge, parent_oid)),
+    );
+    Ok(origins)
+}
+
 /// Handle Git's `post-commit` hook.
 ///
 /// See the man-page for `githooks(5)`.
 pub fn hook_post_commit() -> anyhow::Result<()> {
+    // Commits created in memory are already recorded as rewrites by the
+    // operation which created them.
+    if std::env::var_os(BRANCHLESS_IN_MEMORY_COMMIT_OID_ENV_VAR).is_some() {
+        return Ok(());
+    }
+
     println!("branchless: processing commit");
 
     let now = SystemTime::now();
@@ -287,25 +366,54 @@ pub fn hook_post_commit() -> anyhow::Result<()> {
     mark_commit_reachable(&repo, commit.id())
         .with_context(|| "Marking commit as reachable for GC purposes")?;
 
+    // Commits made during a rebase are recorded as rewrites by the
+    // `post-rewrite` hook instead.
+    let commit_origins = if is_rebase_underway(&repo)? {
+        Vec::new()
+    } else {
+        find_commit_origins(&repo, &commit)?
+    };
+
     let timestamp = commit.time().seconds() as f64;
     let event_tx_id = event_log_db.make_transaction_id(now, "hook-post-commit")?;
-    event_log_db.add_events(vec![Event::CommitEvent {
+    let mut events = vec![Event::CommitEvent {
         timestamp,
         event_tx_id,
         commit_oid: commit.id(),
-    }])?;
+    }];
+    events.extend(commit_origins.into_iter().map(|(origin_type, source_oid)| {
+        Event::CommitOriginEvent {
+            timestamp,
+            event_tx_id,
+            commit_oid: commit.id(),
+            origin_type,
+            source_oid,
+        }
+    }));
+    event_log_db.add_events(events)?;
 
     Ok(())
 }
 
+/// Parse a line passed to the `reference-transaction` hook.
+///
+/// Args:
+/// * `head_ref_name`: The name to record updates to `HEAD` under. See
+///   `get_worktree_head_ref_name`.
 fn parse_reference_transaction_line(
     line: &str,
     now: SystemTime,
     event_tx_id: EventTransactionId,
+    head_ref_name: &str,
 ) -> anyhow::Result<Option<Event>> {
     match *line.split(' ').collect::<Vec<_>>().as_slice() {
         [old_value, new_value, ref_name] => {
             if !should_ignore_ref_updates(ref_name) {
+                let ref_name = if ref_name == "HEAD" {
+                    head_ref_name
+                } else {
+                    ref_name
+                };
                 let timestamp = now
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .with_context(|| "Processing timestamp")?;
@@ -344,16 +452,13 @@ pub fn hook_reference_transaction(transaction_state: &str) -> anyhow::Result<()>
     let conn = get_db_conn(&repo)?;
     let mut event_log_db = EventLogDb::new(&conn)?;
     let event_tx_id = event_log_db.make_transaction_id(now, "reference-transaction")?;
+    let head_ref_name = get_worktree_head_ref_name(&repo)?;
 
-    let events: Vec<Event> = stdin()
-        .lock()
-        .lines()
+    let lines: Vec<String> = stdin().lock().lines().map_while(Result::ok).collect();
+    let events: Vec<Event> = lines
+        .iter()
         .filter_map(|line| {
-            let line = match line {
-                Ok(line) => line,
-                Err(_) => return None,
-            };
-            match parse_reference_transaction_line(&line, now, event_tx_id) {
+            match parse_reference_transaction_line(line, now, event_tx_id, &head_ref_name) {
                 Ok(event) => event,
                 Err(err) => {
                     log::error!("Could not parse reference-transaction-line: {:?}", err);
@@ -362,6 +467,66 @@ pub fn hook_reference_transaction(transaction_state: &str) -> anyhow::Result<()>
             }
         })
         .collect();
+
+    // Updates to the stash list aren't recorded as reference updates. Instead,
+    // compare the stash list with what's been recorded so far. This only
+    // happens when `refs/stash` itself changes, since comparing requires
+    // reading the whole event log. Dropping an entry other than the most recent
+    // one doesn't update `refs/stash`, but it's reconciled by `git undo`.
+    let is_stash_updated = lines
+        .iter()
+        .any(|line| line.split(' ').nth(2) == Some("refs/stash"));
+    if is_stash_updated {
+        record_stash_changes(&repo, &mut event_log_db, Some(event_tx_id))?;
+    }
+
+    // Likewise, obsolescence markers which were fetched from a remote are
+    // ingested when their reference is updated. Git doesn't have a hook which
+    // runs after `git fetch`, but fetching into a reference updates it here.
+    for line in lines.iter() {
+        let (ref_name, new_value) = match *line.split(' ').collect::<Vec<_>>().as_slice() {
+            [_old_value, new_value, ref_name]
+                if ref_name.starts_with(REMOTE_OBSMARKERS_REF_PREFIX) =>
+            {
+                (ref_name, new_value)
+            }
+            _ => continue,
+        };
+        let commit = match new_value
+            .parse::<git2::Oid>()
+            .and_then(|oid| repo.find_commit(oid))
+        {
+            Ok(commit) => commit,
+            // The reference was deleted.
+            Err(_) => continue,
+        };
+        let new_obsmarkers =
+            record_obsmarkers(&repo, &mut event_log_db, &commit, Some(event_tx_id))?;
+        if !new_obsmarkers.obsmarkers.is_empty() {
+            println!(
+                "branchless: ingested {} from {}",
+                Pluralize {
+                    amount: new_obsmarkers.obsmarkers.len().try_into()?,
+                    singular: "obsolescence marker",
+                    plural: "obsolescence markers",
+                }
+                .to_string(),
+                ref_name
+            );
+        }
+        if !new_obsmarkers.missing_obsmarkers.is_empty() {
+            println!(
+                "branchless: skipped {} for commits rewritten into commits which haven't been fetched",
+                Pluralize {
+                    amount: new_obsmarkers.missing_obsmarkers.len().try_into()?,
+                    singular: "obsolescence marker",
+                    plural: "obsolescence markers",
+                }
+                .to_string()
+            );
+        }
+    }
+
     if events.is_empty() {
         return Ok(());
     }
@@ -392,7 +557,7 @@ mod tests {
         let timestamp = SystemTime::UNIX_EPOCH;
         let event_tx_id = crate::core::eventlog::testing::make_dummy_transaction_id(789);
         assert_eq!(
-            parse_reference_transaction_line(&line, timestamp, event_tx_id)?,
+            parse_reference_transaction_line(&line, timestamp, event_tx_id, "HEAD")?,
             Some(Event::RefUpdateEvent {
                 timestamp: 0.0,
                 event_tx_id,
@@ -403,14 +568,49 @@ mod tests {
             })
         );
 
+        let line = "123abc 456def HEAD";
+        assert_eq!(
+            parse_reference_transaction_line(&line, timestamp, event_tx_id, "worktrees/foo/HEAD")?,
+            Some(Event::RefUpdateEvent {
+                timestamp: 0.0,
+                event_tx_id,
+                old_ref: Some(String::from("123abc")),
+                new_ref: Some(String::from("456def")),
+                ref_name: String::from("worktrees/foo/HEAD"),
+                message: None,
+            })
+        );
+
         let line = "123abc 456def ORIG_HEAD";
         assert_eq!(
-            parse_reference_transaction_line(&line, timestamp, event_tx_id)?,
+            parse_reference_transaction_line(&line, timestamp, event_tx_id, "HEAD")?,
             None
         );
 
         let line = "there are not three fields here";
-        assert!(parse_reference_transaction_line(&line, timestamp, event_tx_id).is_err());
+        assert!(parse_reference_transaction_line(&line, timestamp, event_tx_id, "HEAD").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_commit_message_origins() -> anyhow::Result<()> {
+        let oid1 = "62fc20d2a290daea0d52bdc2ed2ad4be6491010e";
+        let oid2 = "96d1c37a3d4363611c49f7e52186e189a04c531f";
+        let message = format!(
+            "Revert \"create test1.txt\"\n\nThis reverts commit {}.\n\n(cherry picked from commit {})\n",
+            oid1, oid2
+        );
+        assert_eq!(
+            parse_commit_message_origins(&message),
+            vec![
+                (CommitOriginType::Revert, oid1.parse()?),
+                (CommitOriginType::CherryPick, oid2.parse()?),
+            ]
+        );
+
+        let message = "This reverts commit 62fc20d2, which was abbreviated.";
+        assert_eq!(parse_commit_message_origins(message), vec![]);
 
         Ok(())
     }
@@ -8,7 +8,9 @@ use fn_error_context::context;
 use log::warn;
 
 use crate::core::config::get_core_hooks_path;
-use crate::util::{get_repo, run_git_silent, wrap_git_error, GitExecutable, GitVersion};
+use crate::util::{
+    get_repo, get_repo_common_dir, run_git_silent, wrap_git_error, GitExecutable, GitVersion,
+};
 
 #[derive(Debug)]
 enum Hook {
@@ -21,7 +23,7 @@ enum Hook {
 
 #[context("Determining hook path")]
 fn determine_hook_path(repo: &git2::Repository, hook_type: &str) -> anyhow::Result<Hook> {
-    let multi_hooks_path = repo.path().join("hooks_multi");
+    let multi_hooks_path = get_repo_common_dir(repo)?.join("hooks_multi");
     let hook = if multi_hooks_path.exists() {
         let path = multi_hooks_path
             .join(format!("{}.d", hook_type))
@@ -195,6 +197,11 @@ fn install_aliases(
     install_alias(&mut config, "restack", "restack")?;
     install_alias(&mut config, "undo", "undo")?;
     install_alias(&mut config, "move", "move")?;
+    install_alias(&mut config, "amend", "amend")?;
+    install_alias(&mut config, "reword", "reword")?;
+    install_alias(&mut config, "split", "split")?;
+    install_alias(&mut config, "fold", "fold")?;
+    install_alias(&mut config, "absorb", "absorb")?;
 
     let version_str = run_git_silent(repo, git_executable, None, &["version"])
         .with_context(|| "Determining Git version")?;
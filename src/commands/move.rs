@@ -3,17 +3,23 @@
 //! Under the hood, this makes use of Git's advanced rebase functionality, which
 //! is also used to preserve merge commits using the `--rebase-merges` option.
 
+use std::collections::HashSet;
 use std::time::SystemTime;
 
-use crate::core::eventlog::{EventLogDb, EventReplayer};
+use crate::commands::snapshot::snapshot_working_copy;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
 use crate::core::formatting::Glyphs;
 use crate::core::graph::{make_graph, BranchOids, CommitGraph, HeadOid, MainBranchOid};
 use crate::core::mergebase::MergeBaseDb;
-use crate::core::rewrite::{execute_rebase_plan, make_rebase_plan};
+use crate::core::rewrite::{
+    clear_rebase_state, drive_rebase_on_disk, execute_rebase_plan, find_subtree_oids,
+    get_rebase_plan_label_names, is_rebase_state_current, load_rebase_state,
+    make_exact_rebase_plan, make_insert_rebase_plan, make_rebase_plan, RebaseState,
+};
 use crate::util::get_main_branch_oid;
 use crate::util::{
-    get_branch_oid_to_names, get_db_conn, get_head_oid, get_repo, resolve_commits, GitExecutable,
-    ResolveCommitsResult,
+    get_branch_oid_to_names, get_db_conn, get_head_oid, get_repo, resolve_commits, run_git,
+    wrap_git_error, GitExecutable, ResolveCommitsResult,
 };
 
 fn resolve_base_commit(graph: &CommitGraph, oid: git2::Oid) -> git2::Oid {
@@ -34,32 +40,74 @@ fn resolve_base_commit(graph: &CommitGraph, oid: git2::Oid) -> git2::Oid {
     }
 }
 
+fn is_linear_subtree(graph: &CommitGraph, oid: git2::Oid) -> bool {
+    match graph.get(&oid) {
+        None => true,
+        Some(node) => match node.children.iter().collect::<Vec<_>>().as_slice() {
+            [] => true,
+            [only_child_oid] => is_linear_subtree(graph, **only_child_oid),
+            _ => false,
+        },
+    }
+}
+
 /// Move a subtree from one place to another.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `sources`: The roots of the subtrees to move.
+/// * `dest`: The commit to move the subtrees onto.
+/// * `bases`: Commits in the subtrees to move. For each one, the whole
+///   subtree, starting from the main branch, is moved.
+/// * `force_on_disk`: Skip attempting an in-memory rebase.
+/// * `insert`: Rebase the existing children of `dest` on top of the moved
+///   subtree, rather than leaving them as siblings of it.
+/// * `exact`: The commits to move, without their descendants. Any descendants
+///   which aren't also listed are rebased onto their nearest unmoved ancestor.
+///
+/// Returns: Exit code (0 denotes successful exit).
 pub fn r#move(
     git_executable: &GitExecutable,
-    source: Option<String>,
+    sources: Vec<String>,
     dest: Option<String>,
-    base: Option<String>,
+    bases: Vec<String>,
     force_on_disk: bool,
+    insert: bool,
+    exact: Vec<String>,
 ) -> anyhow::Result<isize> {
     let repo = get_repo()?;
     let head_oid = get_head_oid(&repo)?;
-    let (source, should_resolve_base_commit) = match (source, base) {
-        (Some(_), Some(_)) => {
+    let exact_oids: HashSet<git2::Oid> = match resolve_commits(&repo, exact)? {
+        ResolveCommitsResult::Ok { commits } => commits.iter().map(|commit| commit.id()).collect(),
+        ResolveCommitsResult::CommitNotFound { commit } => {
+            println!("Commit not found: {}", commit);
+            return Ok(1);
+        }
+    };
+    if !exact_oids.is_empty() && (!sources.is_empty() || !bases.is_empty() || insert) {
+        println!("The --exact option cannot be combined with --source, --base, or --insert.");
+        return Ok(1);
+    }
+    let (sources, should_resolve_base_commit) = match (sources.is_empty(), bases.is_empty()) {
+        (false, false) => {
             println!("The --source and --base options cannot both be provided.");
             return Ok(1);
         }
-        (Some(source), None) => (source, false),
-        (None, Some(base)) => (base, true),
-        (None, None) => {
+        (false, true) => (sources, false),
+        (true, false) => (bases, true),
+        (true, true) => {
             let source_oid = head_oid
             .expect(
                 "No --source or --base argument was provided, and no OID for HEAD is available as a default",
             )
             .to_string();
-            (source_oid, false)
+            (vec![source_oid], false)
         }
     };
+    if insert && sources.len() > 1 {
+        println!("The --insert option can only be used with a single --source or --base.");
+        return Ok(1);
+    }
     let dest = match dest {
         Some(dest) => dest,
         None => head_oid
@@ -68,10 +116,16 @@ pub fn r#move(
             )
             .to_string(),
     };
-    let (source_oid, dest_oid) = match resolve_commits(&repo, vec![source, dest])? {
-        ResolveCommitsResult::Ok { commits } => match &commits.as_slice() {
-            [source_commit, dest_commit] => (source_commit.id(), dest_commit.id()),
-            _ => anyhow::bail!("Unexpected number of returns values from resolve_commits"),
+    let (source_oids, dest_oid) = match resolve_commits(&repo, [sources, vec![dest]].concat())? {
+        ResolveCommitsResult::Ok { commits } => match commits.split_last() {
+            Some((dest_commit, source_commits)) => (
+                source_commits
+                    .iter()
+                    .map(|commit| commit.id())
+                    .collect::<Vec<_>>(),
+                dest_commit.id(),
+            ),
+            None => anyhow::bail!("Unexpected number of returns values from resolve_commits"),
         },
         ResolveCommitsResult::CommitNotFound { commit } => {
             println!("Commit not found: {}", commit);
@@ -83,36 +137,93 @@ pub fn r#move(
     let branch_oid_to_names = get_branch_oid_to_names(&repo)?;
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
-    let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
     let event_cursor = event_replayer.make_default_cursor();
     let graph = make_graph(
         &repo,
         &merge_base_db,
         &event_replayer,
         event_cursor,
-        &HeadOid(Some(source_oid)),
+        &HeadOid(match source_oids.as_slice() {
+            [source_oid] if exact_oids.is_empty() => Some(*source_oid),
+            _ => head_oid,
+        }),
         &MainBranchOid(main_branch_oid),
         &BranchOids(branch_oid_to_names.keys().copied().collect()),
         true,
     )?;
 
-    let source_oid = if should_resolve_base_commit {
-        resolve_base_commit(&graph, source_oid)
+    let source_oids: Vec<git2::Oid> = if exact_oids.is_empty() {
+        source_oids
     } else {
-        source_oid
+        exact_oids.iter().copied().collect()
     };
+    if let Some(oid) = source_oids.iter().find(|oid| !graph.contains_key(oid)) {
+        println!("Commit is not visible, so it cannot be moved: {}", oid);
+        return Ok(1);
+    }
+    if exact_oids.contains(&dest_oid) {
+        println!("The destination commit cannot also be one of the commits being moved.");
+        return Ok(1);
+    }
+
+    let source_oids: Vec<git2::Oid> = if should_resolve_base_commit {
+        source_oids
+            .into_iter()
+            .map(|source_oid| resolve_base_commit(&graph, source_oid))
+            .collect()
+    } else {
+        source_oids
+    };
+    // Drop any sources which are already being moved as part of the subtree
+    // of another source, so that no commit is rebased twice.
+    let source_oids: Vec<git2::Oid> = if exact_oids.is_empty() {
+        let mut result: Vec<git2::Oid> = Vec::new();
+        for source_oid in source_oids.iter() {
+            let is_covered = source_oids.iter().any(|other_oid| {
+                other_oid != source_oid
+                    && find_subtree_oids(&graph, *other_oid).contains(source_oid)
+            });
+            if !is_covered && !result.contains(source_oid) {
+                result.push(*source_oid);
+            }
+        }
+        result
+    } else {
+        source_oids
+    };
+    let source_oid = source_oids[0];
+
+    if insert && !is_linear_subtree(&graph, source_oid) {
+        println!("The --insert option can only be used to move a linear stack of commits.");
+        return Ok(1);
+    }
 
     let glyphs = Glyphs::detect();
     let now = SystemTime::now();
     let event_tx_id = event_log_db.make_transaction_id(now, "move")?;
-    let rebase_plan = make_rebase_plan(
-        &repo,
-        &merge_base_db,
-        &graph,
-        &MainBranchOid(main_branch_oid),
-        source_oid,
-    )?;
+    snapshot_working_copy(&repo, git_executable, &mut event_log_db, event_tx_id)?;
+    let rebase_plan = if !exact_oids.is_empty() {
+        make_exact_rebase_plan(&repo, &graph, &exact_oids, dest_oid)?
+    } else if insert {
+        make_insert_rebase_plan(
+            &repo,
+            &merge_base_db,
+            &graph,
+            &MainBranchOid(main_branch_oid),
+            source_oid,
+            dest_oid,
+        )?
+    } else {
+        make_rebase_plan(
+            &repo,
+            &merge_base_db,
+            &graph,
+            &MainBranchOid(main_branch_oid),
+            &source_oids,
+        )?
+    };
     let result = execute_rebase_plan(
         &glyphs,
         git_executable,
@@ -122,6 +233,112 @@ pub fn r#move(
         source_oid,
         dest_oid,
         force_on_disk,
+        false,
+        true,
     )?;
-    Ok(result)
+    Ok(result.exit_code())
+}
+
+/// Load the state of the interrupted `git move`, clearing it if Git no longer
+/// has that rebase in progress. The user may have finished or aborted it with
+/// `git rebase` directly, and then started an unrelated rebase.
+fn load_interrupted_move(repo: &git2::Repository) -> anyhow::Result<Option<RebaseState>> {
+    let rebase_state = match load_rebase_state(repo)? {
+        Some(rebase_state) => rebase_state,
+        None => return Ok(None),
+    };
+    if !is_rebase_state_current(repo, &rebase_state)? {
+        clear_rebase_state(repo)?;
+        return Ok(None);
+    }
+    Ok(Some(rebase_state))
+}
+
+/// Resume a `git move` whose on-disk rebase was interrupted, such as by a merge
+/// conflict.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn move_continue(git_executable: &GitExecutable) -> anyhow::Result<isize> {
+    let repo = get_repo()?;
+    let rebase_state = match load_interrupted_move(&repo)? {
+        Some(rebase_state) => rebase_state,
+        None => {
+            println!("There is no interrupted move to continue.");
+            return Ok(1);
+        }
+    };
+    drive_rebase_on_disk(
+        git_executable,
+        &repo,
+        &rebase_state,
+        &["rebase", "--continue"],
+    )
+}
+
+/// Cancel a `git move` whose on-disk rebase was interrupted, restoring the
+/// commits and references to their state before the move.
+///
+/// Any commits which were created by the rebase before it was interrupted are
+/// hidden again, in the same event transaction as the original move, so that
+/// they don't linger in the smartlog.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn move_abort(git_executable: &GitExecutable) -> anyhow::Result<isize> {
+    let repo = get_repo()?;
+    let RebaseState {
+        event_tx_id,
+        rebase_plan,
+        ..
+    } = match load_interrupted_move(&repo)? {
+        Some(rebase_state) => rebase_state,
+        None => {
+            println!("There is no interrupted move to abort.");
+            return Ok(1);
+        }
+    };
+    let result = run_git(git_executable, Some(event_tx_id), &["rebase", "--abort"])?;
+    if result != 0 {
+        return Ok(result);
+    }
+
+    for label_name in get_rebase_plan_label_names(&rebase_plan) {
+        match repo.find_reference(&format!("refs/rewritten/{}", label_name)) {
+            Ok(mut reference) => reference.delete().map_err(wrap_git_error)?,
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {}
+            Err(err) => return Err(wrap_git_error(err)),
+        }
+    }
+
+    let conn = get_db_conn(&repo)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs_f64();
+    let hide_events: Vec<Event> = event_log_db
+        .get_events()?
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::CommitEvent {
+                event_tx_id: commit_event_tx_id,
+                commit_oid,
+                ..
+            } if commit_event_tx_id == event_tx_id => Some(Event::HideEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+            }),
+            _ => None,
+        })
+        .collect();
+    event_log_db.add_events(hide_events)?;
+
+    clear_rebase_state(&repo)?;
+    println!("Aborted the move.");
+    Ok(0)
 }
@@ -1,10 +1,12 @@
 //! Convenience commands to help the user move through a stack of commits.
 
 use std::collections::HashMap;
+use std::time::SystemTime;
 
 use log::warn;
 
 use crate::commands::smartlog::smartlog;
+use crate::commands::snapshot::snapshot_working_copy;
 use crate::core::eventlog::{EventLogDb, EventReplayer};
 use crate::core::formatting::{printable_styled_string, Glyphs};
 use crate::core::graph::{
@@ -19,11 +21,17 @@ use crate::util::{
 
 /// Go back a certain number of commits.
 pub fn prev(git_executable: &GitExecutable, num_commits: Option<isize>) -> anyhow::Result<isize> {
+    let repo = get_repo()?;
+    let conn = get_db_conn(&repo)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "prev")?;
+    snapshot_working_copy(&repo, git_executable, &mut event_log_db, event_tx_id)?;
+
     let exit_code = match num_commits {
-        None => run_git(git_executable, None, &["checkout", "HEAD^"])?,
+        None => run_git(git_executable, Some(event_tx_id), &["checkout", "HEAD^"])?,
         Some(num_commits) => run_git(
             git_executable,
-            None,
+            Some(event_tx_id),
             &["checkout", &format!("HEAD~{}", num_commits)],
         )?,
     };
@@ -142,8 +150,8 @@ pub fn next(
     let repo = get_repo()?;
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
-    let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
 
     let head_oid = match get_head_oid(&repo)? {
         Some(head_oid) => head_oid,
@@ -178,9 +186,11 @@ pub fn next(
         Some(current_oid) => current_oid,
     };
 
+    let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "next")?;
+    snapshot_working_copy(&repo, git_executable, &mut event_log_db, event_tx_id)?;
     let result = run_git(
         git_executable,
-        None,
+        Some(event_tx_id),
         &["checkout", &current_oid.to_string()],
     )?;
     if result != 0 {
@@ -0,0 +1,169 @@
+//! Publish and ingest obsolescence markers, so that commits which were
+//! rewritten or hidden in one clone of a repository are hidden in other clones
+//! as well. See the `core::obsmarkers` module.
+
+use std::convert::TryInto;
+use std::time::SystemTime;
+
+use crate::core::eventlog::{EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::formatting::Pluralize;
+use crate::core::obsmarkers::{
+    find_new_obsmarkers, get_obsmarkers, read_obsmarkers, write_obsmarkers, NewObsMarkers,
+    OBSMARKERS_REF_NAME,
+};
+use crate::util::{get_db_conn, get_repo, resolve_commits, ResolveCommitsResult};
+
+/// Write the obsolescence markers for the given commits to
+/// `OBSMARKERS_REF_NAME`, so that they can be pushed to a remote.
+///
+/// Args:
+/// * `hashes`: The commits to publish markers for. Revs will be resolved (you
+///   can provide an abbreviated commit hash or ref name). If empty, markers are
+///   published for all obsolete commits in the event log.
+///
+/// Returns: exit code (0 denotes successful exit).
+pub fn publish_obsmarkers(hashes: Vec<String>) -> anyhow::Result<isize> {
+    let repo = get_repo()?;
+    let conn = get_db_conn(&repo)?;
+    let event_log_db = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+
+    let commit_oids: Vec<git2::Oid> = if hashes.is_empty() {
+        let mut commit_oids: Vec<git2::Oid> = event_replayer
+            .get_cursor_active_oids(event_cursor)
+            .into_iter()
+            .collect();
+        commit_oids.sort();
+        commit_oids
+    } else {
+        match resolve_commits(&repo, hashes)? {
+            ResolveCommitsResult::Ok { commits } => {
+                commits.iter().map(|commit| commit.id()).collect()
+            }
+            ResolveCommitsResult::CommitNotFound { commit: hash } => {
+                println!("Commit not found: {}", hash);
+                return Ok(1);
+            }
+        }
+    };
+
+    let obsmarkers = get_obsmarkers(&event_replayer, event_cursor, commit_oids);
+    let num_new_obsmarkers = write_obsmarkers(&repo, &obsmarkers)?;
+    println!(
+        "Published {} to {}.",
+        Pluralize {
+            amount: num_new_obsmarkers.try_into()?,
+            singular: "new obsolescence marker",
+            plural: "new obsolescence markers",
+        }
+        .to_string(),
+        OBSMARKERS_REF_NAME
+    );
+    println!(
+        "To share them, run: git push <remote> {}",
+        OBSMARKERS_REF_NAME
+    );
+    Ok(0)
+}
+
+/// Record the obsolescence markers stored in the given commit in the event
+/// log, if they aren't already reflected there.
+///
+/// Args:
+/// * `event_tx_id`: The transaction to record the markers in. If not provided,
+///   a new transaction is created, but only if there are markers to record.
+///
+/// Returns: The markers which were recorded, and the ones which were skipped
+/// because the commit they were rewritten into hasn't been fetched.
+pub fn record_obsmarkers(
+    repo: &git2::Repository,
+    event_log_db: &mut EventLogDb,
+    commit: &git2::Commit,
+    event_tx_id: Option<EventTransactionId>,
+) -> anyhow::Result<NewObsMarkers> {
+    let event_replayer = EventReplayer::from_event_log_db(repo, event_log_db)?;
+    let obsmarkers = read_obsmarkers(repo, commit)?;
+    let new_obsmarkers = find_new_obsmarkers(
+        repo,
+        &event_replayer,
+        event_replayer.make_default_cursor(),
+        obsmarkers,
+    );
+    if new_obsmarkers.obsmarkers.is_empty() {
+        return Ok(new_obsmarkers);
+    }
+
+    let now = SystemTime::now();
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let event_tx_id = match event_tx_id {
+        Some(event_tx_id) => event_tx_id,
+        None => event_log_db.make_transaction_id(now, "ingest-obsmarkers")?,
+    };
+    let events = new_obsmarkers
+        .obsmarkers
+        .iter()
+        .map(|obsmarker| obsmarker.into_event(timestamp, event_tx_id))
+        .collect();
+    event_log_db.add_events(events)?;
+    Ok(new_obsmarkers)
+}
+
+/// Print a summary of the obsolescence markers which were ingested from the
+/// given source.
+fn print_ingested_obsmarkers(new_obsmarkers: &NewObsMarkers, source: &str) -> anyhow::Result<()> {
+    let NewObsMarkers {
+        obsmarkers,
+        missing_obsmarkers,
+    } = new_obsmarkers;
+    println!(
+        "Ingested {} from {}.",
+        Pluralize {
+            amount: obsmarkers.len().try_into()?,
+            singular: "new obsolescence marker",
+            plural: "new obsolescence markers",
+        }
+        .to_string(),
+        source
+    );
+    if !missing_obsmarkers.is_empty() {
+        println!(
+            "Skipped {} for commits rewritten into commits which haven't been fetched. Fetch them, then run: git branchless ingest-obsmarkers",
+            Pluralize {
+                amount: missing_obsmarkers.len().try_into()?,
+                singular: "obsolescence marker",
+                plural: "obsolescence markers",
+            }
+            .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Record the obsolescence markers stored in the given commit in the event
+/// log, so that the obsolete commits are hidden.
+///
+/// Args:
+/// * `source`: The commit storing the markers, such as `FETCH_HEAD` after
+///   running `git fetch <remote> refs/branchless/obsmarkers`.
+///
+/// Returns: exit code (0 denotes successful exit).
+pub fn ingest_obsmarkers(source: String) -> anyhow::Result<isize> {
+    let repo = get_repo()?;
+    let conn = get_db_conn(&repo)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+
+    let commit = match repo
+        .revparse_single(&source)
+        .and_then(|object| object.peel_to_commit())
+    {
+        Ok(commit) => commit,
+        Err(_) => {
+            println!("Could not find obsolescence markers at: {}", source);
+            return Ok(1);
+        }
+    };
+    let new_obsmarkers = record_obsmarkers(&repo, &mut event_log_db, &commit, None)?;
+    print_ingested_obsmarkers(&new_obsmarkers, &source)?;
+    Ok(0)
+}
@@ -63,13 +63,13 @@ use log::info;
 
 use crate::commands::smartlog::smartlog;
 use crate::core::config::get_restack_preserve_timestamps;
-use crate::core::eventlog::{EventLogDb, EventReplayer, EventTransactionId};
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer, EventTransactionId};
 use crate::core::graph::{make_graph, BranchOids, HeadOid, MainBranchOid};
 use crate::core::mergebase::MergeBaseDb;
 use crate::core::rewrite::{find_abandoned_children, find_rewrite_target};
 use crate::util::{
     get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid, get_repo, run_git,
-    GitExecutable,
+    wrap_git_error, GitExecutable,
 };
 
 #[context("Restacking commits")]
@@ -80,7 +80,7 @@ fn restack_commits(
     event_log_db: &EventLogDb,
     event_tx_id: EventTransactionId,
 ) -> anyhow::Result<isize> {
-    let event_replayer = EventReplayer::from_event_log_db(event_log_db)?;
+    let event_replayer = EventReplayer::from_event_log_db(repo, event_log_db)?;
     let head_oid = get_head_oid(repo)?;
     let main_branch_oid = get_main_branch_oid(repo)?;
     let branch_oid_to_names = get_branch_oid_to_names(repo)?;
@@ -151,15 +151,21 @@ fn restack_commits(
     Ok(0)
 }
 
+/// Move each branch which points to a rewritten commit to the newest version
+/// of that commit.
+///
+/// All the branch moves are computed from a single commit graph and then
+/// applied in one reference transaction. Since Git's `reference-transaction`
+/// hook isn't invoked for transactions made with libgit2, the corresponding
+/// events are added to the event log directly.
 #[context("Restacking branches")]
-fn restack_branches(
+pub fn restack_branches(
     repo: &git2::Repository,
-    git_executable: &GitExecutable,
     merge_base_db: &MergeBaseDb,
-    event_log_db: &EventLogDb,
+    event_log_db: &mut EventLogDb,
     event_tx_id: EventTransactionId,
 ) -> anyhow::Result<isize> {
-    let event_replayer = EventReplayer::from_event_log_db(event_log_db)?;
+    let event_replayer = EventReplayer::from_event_log_db(repo, event_log_db)?;
     let head_oid = get_head_oid(repo)?;
     let main_branch_oid = get_main_branch_oid(repo)?;
     let branch_oid_to_names = get_branch_oid_to_names(repo)?;
@@ -174,6 +180,8 @@ fn restack_branches(
         true,
     )?;
 
+    let mut branch_moves: Vec<(String, git2::Oid, git2::Oid)> = Vec::new();
+    let mut skipped_head_branch = false;
     for branch_info in repo
         .branches(Some(git2::BranchType::Local))
         .with_context(|| "Iterating over local branches")?
@@ -199,31 +207,66 @@ fn restack_branches(
             event_replayer.make_default_cursor(),
             branch_target,
         ) {
-            Some(new_oid) => new_oid.to_string(),
+            Some(new_oid) => new_oid,
             None => continue,
         };
-        let branch_name = match branch
-            .name()
-            .with_context(|| "Converting branch name to string")?
-        {
-            Some(branch_name) => branch_name,
+        let ref_name = match branch.get().name() {
+            Some(ref_name) => ref_name,
             None => anyhow::bail!("Invalid UTF-8 branch name: {:?}", branch.name_bytes()?),
         };
-        let args = ["branch", "-f", branch_name, &new_oid];
-        let result = run_git(git_executable, Some(event_tx_id), &args)?;
-        if result != 0 {
-            return Ok(result);
-        } else {
-            return restack_branches(
-                repo,
-                git_executable,
-                merge_base_db,
-                event_log_db,
-                event_tx_id,
+        if branch.is_head() {
+            // Moving the branch underneath the working copy would make it
+            // appear to have uncommitted changes.
+            println!(
+                "branchless: cannot move checked-out branch {}, check out another commit and run 'git restack' again",
+                ref_name.strip_prefix("refs/heads/").unwrap_or(ref_name)
             );
+            skipped_head_branch = true;
+            continue;
         }
+        branch_moves.push((ref_name.to_owned(), branch_target, new_oid));
     }
 
+    if !branch_moves.is_empty() {
+        let mut transaction = repo.transaction().map_err(wrap_git_error)?;
+        for (ref_name, _old_oid, new_oid) in branch_moves.iter() {
+            transaction.lock_ref(ref_name).map_err(wrap_git_error)?;
+            transaction
+                .set_target(ref_name, *new_oid, None, "branchless: restack")
+                .map_err(wrap_git_error)?;
+        }
+        transaction.commit().map_err(wrap_git_error)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs_f64();
+        event_log_db.add_events(
+            branch_moves
+                .iter()
+                .map(|(ref_name, old_oid, new_oid)| Event::RefUpdateEvent {
+                    timestamp,
+                    event_tx_id,
+                    ref_name: ref_name.clone(),
+                    old_ref: Some(old_oid.to_string()),
+                    new_ref: Some(new_oid.to_string()),
+                    message: None,
+                })
+                .collect(),
+        )?;
+
+        for (ref_name, old_oid, new_oid) in branch_moves {
+            println!(
+                "branchless: moved branch {} from {} to {}",
+                ref_name.strip_prefix("refs/heads/").unwrap_or(&ref_name),
+                old_oid,
+                new_oid
+            );
+        }
+    }
+
+    if skipped_head_branch {
+        return Ok(1);
+    }
     println!("branchless: no more abandoned branches to restack");
     Ok(0)
 }
@@ -241,7 +284,7 @@ pub fn restack(git_executable: &GitExecutable) -> anyhow::Result<isize> {
     let repo = get_repo()?;
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
-    let event_log_db = EventLogDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
     let event_tx_id = event_log_db.make_transaction_id(SystemTime::now(), "restack")?;
     let head_oid = get_head_oid(&repo)?;
 
@@ -256,13 +299,7 @@ pub fn restack(git_executable: &GitExecutable) -> anyhow::Result<isize> {
         return Ok(result);
     }
 
-    let result = restack_branches(
-        &repo,
-        &git_executable,
-        &merge_base_db,
-        &event_log_db,
-        event_tx_id,
-    )?;
+    let result = restack_branches(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
     if result != 0 {
         return Ok(result);
     }
@@ -0,0 +1,195 @@
+//! Edit the message of any commit in a stack.
+//!
+//! The commit is rewritten in memory with its new message, and its descendants
+//! are rebased on top of it. Since none of the commits' contents change, the
+//! rebase can't produce merge conflicts, and the working copy is left
+//! untouched.
+
+use std::process::Command;
+use std::time::SystemTime;
+
+use anyhow::Context;
+
+use crate::commands::restack::restack_branches;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Glyphs};
+use crate::core::mergebase::MergeBaseDb;
+use crate::core::rewrite::{
+    friendly_describe_commit, make_current_graph, make_rewritten_committer, move_head_in_memory,
+    restack_descendants, ExecuteRebasePlanResult,
+};
+use crate::util::{
+    get_db_conn, get_head_oid, get_repo, resolve_commits, run_git_silent, wrap_git_error,
+    GitExecutable, ResolveCommitsResult,
+};
+
+/// The name of the file in the `.git` directory which the commit message is
+/// written to for editing.
+const EDIT_MESSAGE_FILE_NAME: &str = "BRANCHLESS_EDITMSG";
+
+/// Open the user's editor to edit the given commit message.
+///
+/// Returns: The edited message, with comments and surrounding whitespace
+/// removed.
+pub fn edit_message(
+    repo: &git2::Repository,
+    git_executable: &GitExecutable,
+    message: &str,
+) -> anyhow::Result<String> {
+    let editor = run_git_silent(repo, git_executable, None, &["var", "GIT_EDITOR"])?;
+    let editor = editor.trim();
+
+    let message_path = repo.path().join(EDIT_MESSAGE_FILE_NAME);
+    std::fs::write(
+        &message_path,
+        format!(
+            "{}
+# Please enter the new commit message. Lines starting
+# with '#' will be ignored, and an empty message aborts the command.
+",
+            message
+        ),
+    )
+    .with_context(|| format!("Writing commit message to: {:?}", &message_path))?;
+
+    // Git runs the editor through the shell, since it may contain arguments.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(&message_path)
+        .status()
+        .with_context(|| format!("Running editor: {:?}", editor))?;
+    if !status.success() {
+        anyhow::bail!("Editor {:?} exited with status: {}", editor, status);
+    }
+
+    let message = std::fs::read_to_string(&message_path)
+        .with_context(|| format!("Reading commit message from: {:?}", &message_path))?;
+    let message = git2::message_prettify(message, Some(b'#')).map_err(wrap_git_error)?;
+    Ok(message)
+}
+
+/// Change the message of the given commit, and restack its descendants on top
+/// of the reworded commit.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `hash`: The commit to reword. Defaults to `HEAD`.
+/// * `message`: The new commit message. If not provided, the user's editor is
+///   opened to edit the existing message.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn reword(
+    git_executable: &GitExecutable,
+    hash: Option<String>,
+    message: Option<String>,
+) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::detect();
+    let repo = get_repo()?;
+    let hash = hash.unwrap_or_else(|| "HEAD".to_string());
+    let commit = match resolve_commits(&repo, vec![hash])? {
+        ResolveCommitsResult::Ok { mut commits } => commits.remove(0),
+        ResolveCommitsResult::CommitNotFound { commit } => {
+            println!("Commit not found: {}", commit);
+            return Ok(1);
+        }
+    };
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    {
+        let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+        let graph = make_current_graph(&repo, &merge_base_db, &event_replayer)?;
+        match graph.get(&commit.id()) {
+            Some(node) if node.is_main => {
+                println!(
+                    "Commits on the main branch cannot be reworded: {}",
+                    commit.id()
+                );
+                return Ok(1);
+            }
+            Some(_) => {}
+            None => {
+                println!(
+                    "Commit is not visible, so it cannot be reworded: {}",
+                    commit.id()
+                );
+                return Ok(1);
+            }
+        }
+    }
+    let old_message = match commit.message_raw() {
+        Some(message) => message,
+        None => anyhow::bail!(
+            "Could not decode commit message for commit: {:?}",
+            commit.id()
+        ),
+    };
+
+    let new_message = match message {
+        Some(message) => git2::message_prettify(message, None).map_err(wrap_git_error)?,
+        None => edit_message(&repo, git_executable, old_message)?,
+    };
+    if new_message.is_empty() {
+        println!("Aborting reword due to empty commit message.");
+        return Ok(1);
+    }
+    if new_message == old_message {
+        println!("The commit message is unchanged, so there is nothing to reword.");
+        return Ok(0);
+    }
+
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "reword")?;
+
+    let committer = make_rewritten_committer(&repo, &commit)?;
+    let tree = commit.tree().map_err(wrap_git_error)?;
+    let parents: Vec<git2::Commit> = commit.parents().collect();
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+    let reworded_oid = repo
+        .commit(
+            None,
+            &commit.author(),
+            &committer,
+            &new_message,
+            &tree,
+            &parents,
+        )
+        .with_context(|| "Committing reworded commit")?;
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    event_log_db.add_events(vec![Event::RewriteEvent {
+        timestamp,
+        event_tx_id,
+        old_commit_oid: commit.id(),
+        new_commit_oid: reworded_oid,
+    }])?;
+    if get_head_oid(&repo)? == Some(commit.id()) {
+        move_head_in_memory(&repo, &mut event_log_db, event_tx_id, reworded_oid)?;
+    }
+    println!(
+        "Reworded as: {}",
+        printable_styled_string(&glyphs, friendly_describe_commit(&repo, reworded_oid)?)?
+    );
+
+    let result = restack_descendants(
+        &glyphs,
+        git_executable,
+        &repo,
+        &merge_base_db,
+        &mut event_log_db,
+        event_tx_id,
+        commit.id(),
+    )?;
+    if let ExecuteRebasePlanResult::Failed { exit_code } = result {
+        return Ok(exit_code);
+    }
+
+    let exit_code = restack_branches(&repo, &merge_base_db, &mut event_log_db, event_tx_id)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+    Ok(result.exit_code())
+}
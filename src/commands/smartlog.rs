@@ -4,9 +4,10 @@
 //! log; see the `eventlog` module.
 
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::time::SystemTime;
 
-use cursive::theme::Effect;
+use cursive::theme::{BaseColor, Effect};
 use cursive::utils::markup::StyledString;
 use fn_error_context::context;
 
@@ -17,11 +18,13 @@ use crate::core::graph::{make_graph, BranchOids, CommitGraph, HeadOid, MainBranc
 use crate::core::mergebase::MergeBaseDb;
 use crate::core::metadata::{
     render_commit_metadata, BranchesProvider, CommitMessageProvider, CommitMetadataProvider,
-    CommitOidProvider, DifferentialRevisionProvider, HiddenExplanationProvider,
-    RelativeTimeProvider,
+    CommitOidProvider, CommitOriginProvider, DifferentialRevisionProvider,
+    HiddenExplanationProvider, RelativeTimeProvider,
 };
+use crate::core::stash::{get_stash_entries, StashEntry};
 use crate::util::{
-    get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid, get_repo,
+    get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid,
+    get_other_worktree_head_oids, get_repo,
 };
 
 /// Split fully-independent subgraphs into multiple graphs.
@@ -77,11 +80,37 @@ fn split_commit_graph_by_roots(
     root_commit_oids
 }
 
+/// A child to render underneath a commit in the smartlog.
+enum SmartlogChild<'a> {
+    /// An entry in the stash list which was made on top of the commit.
+    Stash(&'a StashEntry),
+
+    /// A child commit.
+    Commit(git2::Oid),
+}
+
+fn render_stash_entry(glyphs: &Glyphs, stash_entry: &StashEntry) -> StyledString {
+    StyledStringBuilder::new()
+        .append_plain(glyphs.commit_stash)
+        .append_plain(" ")
+        .append_styled(
+            &stash_entry.commit_oid.to_string()[..8],
+            BaseColor::Yellow.dark(),
+        )
+        .append_plain(format!(
+            " stash@{{{}}}: {}",
+            stash_entry.index, stash_entry.message
+        ))
+        .build()
+}
+
 #[context("Getting child smartlog output for OID {:?}", &current_oid)]
 fn get_child_output(
     glyphs: &Glyphs,
     graph: &CommitGraph,
     root_oids: &[git2::Oid],
+    stash_entries: &[StashEntry],
+    other_worktree_head_oids: &HashSet<git2::Oid>,
     commit_metadata_providers: &mut [&mut dyn CommitMetadataProvider],
     head_oid: &HeadOid,
     current_oid: git2::Oid,
@@ -95,6 +124,9 @@ fn get_child_output(
 
     let text = render_commit_metadata(&current_node.commit, commit_metadata_providers)?;
     let cursor = match (current_node.is_main, current_node.is_visible, is_head) {
+        (_, _, false) if other_worktree_head_oids.contains(&current_oid) => {
+            glyphs.commit_other_worktree_head
+        }
         (false, false, false) => glyphs.commit_hidden,
         (false, false, true) => glyphs.commit_hidden_head,
         (false, true, false) => glyphs.commit_visible,
@@ -125,11 +157,35 @@ fn get_child_output(
         .copied()
         .collect();
     children.sort_by_key(|child_oid| (graph[child_oid].commit.time(), child_oid.to_string()));
-    for (child_idx, child_oid) in children.iter().enumerate() {
-        if root_oids.contains(child_oid) {
-            // Will be rendered by the parent.
-            continue;
-        }
+
+    // Stash entries are rendered before any child commits, from least recent
+    // to most recent.
+    let children: Vec<SmartlogChild> = stash_entries
+        .iter()
+        .rev()
+        .filter(|stash_entry| stash_entry.base_oid == Some(current_oid))
+        .map(SmartlogChild::Stash)
+        .chain(children.into_iter().map(SmartlogChild::Commit))
+        .collect();
+    for (child_idx, child) in children.iter().enumerate() {
+        let child_output = match child {
+            SmartlogChild::Stash(stash_entry) => vec![render_stash_entry(glyphs, stash_entry)],
+            SmartlogChild::Commit(child_oid) if root_oids.contains(child_oid) => {
+                // Will be rendered by the parent.
+                continue;
+            }
+            SmartlogChild::Commit(child_oid) => get_child_output(
+                glyphs,
+                graph,
+                root_oids,
+                stash_entries,
+                other_worktree_head_oids,
+                commit_metadata_providers,
+                head_oid,
+                *child_oid,
+                None,
+            )?,
+        };
 
         if child_idx == children.len() - 1 {
             let line = match last_child_line_char {
@@ -147,15 +203,6 @@ fn get_child_output(
             )))
         }
 
-        let child_output = get_child_output(
-            glyphs,
-            graph,
-            root_oids,
-            commit_metadata_providers,
-            head_oid,
-            *child_oid,
-            None,
-        )?;
         for child_line in child_output {
             let line = if child_idx == children.len() - 1 {
                 match last_child_line_char {
@@ -186,6 +233,8 @@ fn get_child_output(
 fn get_output(
     glyphs: &Glyphs,
     graph: &CommitGraph,
+    stash_entries: &[StashEntry],
+    other_worktree_head_oids: &HashSet<git2::Oid>,
     commit_metadata_providers: &mut [&mut dyn CommitMetadataProvider],
     head_oid: &HeadOid,
     root_oids: &[git2::Oid],
@@ -236,6 +285,8 @@ fn get_output(
             glyphs,
             graph,
             root_oids,
+            stash_entries,
+            other_worktree_head_oids,
             commit_metadata_providers,
             head_oid,
             *root_oid,
@@ -248,11 +299,18 @@ fn get_output(
 }
 
 /// Render the smartlog graph and write it to the provided stream.
+///
+/// Entries in `stash_entries` are rendered underneath the commit they were
+/// made on top of, if that commit is in the graph. Commits in
+/// `other_worktree_head_oids` are marked as being checked out in another
+/// worktree.
 pub fn render_graph(
     glyphs: &Glyphs,
     repo: &git2::Repository,
     merge_base_db: &MergeBaseDb,
     graph: &CommitGraph,
+    stash_entries: &[StashEntry],
+    other_worktree_head_oids: &HashSet<git2::Oid>,
     head_oid: &HeadOid,
     commit_metadata_providers: &mut [&mut dyn CommitMetadataProvider],
 ) -> anyhow::Result<Vec<StyledString>> {
@@ -260,6 +318,8 @@ pub fn render_graph(
     let lines = get_output(
         glyphs,
         graph,
+        stash_entries,
+        other_worktree_head_oids,
         commit_metadata_providers,
         head_oid,
         &root_oids,
@@ -274,10 +334,11 @@ pub fn smartlog() -> anyhow::Result<()> {
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
     let event_log_db = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
     let head_oid = get_head_oid(&repo)?;
     let main_branch_oid = get_main_branch_oid(&repo)?;
     let branch_oid_to_names = get_branch_oid_to_names(&repo)?;
+    let other_worktree_head_oids = get_other_worktree_head_oids(&repo)?;
     let graph = make_graph(
         &repo,
         &merge_base_db,
@@ -285,15 +346,24 @@ pub fn smartlog() -> anyhow::Result<()> {
         event_replayer.make_default_cursor(),
         &HeadOid(head_oid),
         &MainBranchOid(main_branch_oid),
-        &BranchOids(branch_oid_to_names.keys().cloned().collect()),
+        &BranchOids(
+            branch_oid_to_names
+                .keys()
+                .chain(other_worktree_head_oids.iter())
+                .cloned()
+                .collect(),
+        ),
         true,
     )?;
 
+    let stash_entries = get_stash_entries(&repo)?;
     let lines = render_graph(
         &glyphs,
         &repo,
         &merge_base_db,
         &graph,
+        &stash_entries,
+        &other_worktree_head_oids,
         &HeadOid(head_oid),
         &mut [
             &mut CommitOidProvider::new(true)?,
@@ -303,6 +373,7 @@ pub fn smartlog() -> anyhow::Result<()> {
                 &event_replayer,
                 event_replayer.make_default_cursor(),
             )?,
+            &mut CommitOriginProvider::new(&event_replayer, event_replayer.make_default_cursor())?,
             &mut BranchesProvider::new(&repo, &branch_oid_to_names)?,
             &mut DifferentialRevisionProvider::new(&repo)?,
             &mut CommitMessageProvider::new()?,
@@ -0,0 +1,281 @@
+//! Record the state of the working copy before operations which move `HEAD`.
+//!
+//! Commands such as `git next` or `git move` check out other commits, which may
+//! carry uncommitted changes along with them (or fail because of them). To make
+//! sure that `git undo` can put the working copy back the way it was, we take a
+//! snapshot of the index and working copy beforehand, and record it in the
+//! event log.
+//!
+//! Changes to the stash list are recorded here as well, so that `git undo` can
+//! restore dropped stash entries.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use fn_error_context::context;
+
+use crate::commands::gc::mark_commit_reachable;
+use crate::core::eventlog::{Event, EventLogDb, EventTransactionId};
+use crate::core::hunks::make_index_entry;
+use crate::core::stash::{find_stash_changes, get_stash_entries, StashChange};
+use crate::util::{run_git_silent, wrap_git_error, GitExecutable};
+
+/// Write the contents of the file at `path` in the working copy as a blob.
+///
+/// Returns: The file mode and the OID of the blob.
+#[context("Writing working copy file {:?} as a blob", path)]
+fn make_blob_from_work_dir_file(
+    repo: &git2::Repository,
+    path: &Path,
+) -> anyhow::Result<(u32, git2::Oid)> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(path)?;
+        let oid = repo
+            .blob(target.to_string_lossy().as_bytes())
+            .map_err(wrap_git_error)?;
+        return Ok((0o120000, oid));
+    }
+
+    let oid = repo.blob_path(path).map_err(wrap_git_error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return Ok((0o100755, oid));
+        }
+    }
+    Ok((0o100644, oid))
+}
+
+/// Make a tree containing the untracked files in the working copy, excluding
+/// ignored files, in the same way as `git stash --include-untracked`.
+///
+/// Returns: The OID of the tree, or `None` if there are no untracked files.
+#[context("Making tree of untracked files")]
+fn make_untracked_tree(
+    repo: &git2::Repository,
+    work_dir: &Path,
+) -> anyhow::Result<Option<git2::Oid>> {
+    let mut status_options = git2::StatusOptions::new();
+    status_options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut status_options))
+        .with_context(|| "Reading working copy status")?;
+
+    let mut index = git2::Index::new().map_err(wrap_git_error)?;
+    for entry in statuses.iter().filter(|entry| entry.status().is_wt_new()) {
+        let path = match entry.path() {
+            Some(path) => PathBuf::from(path),
+            None => anyhow::bail!("Invalid UTF-8 path: {:?}", entry.path_bytes()),
+        };
+        let (mode, oid) = make_blob_from_work_dir_file(repo, &work_dir.join(&path))?;
+        index
+            .add(&make_index_entry(&path, mode, oid)?)
+            .map_err(wrap_git_error)?;
+    }
+    if index.is_empty() {
+        return Ok(None);
+    }
+    let tree_oid = index.write_tree_to(repo).map_err(wrap_git_error)?;
+    Ok(Some(tree_oid))
+}
+
+/// Add the untracked files in `untracked_tree_oid` to a snapshot, in the same
+/// format as `git stash --include-untracked`: the untracked files are stored
+/// in a parentless commit, which becomes the third parent of the snapshot
+/// commit.
+///
+/// Args:
+/// * `stash_oid`: The snapshot of the tracked files made with `git stash
+///   create`, or `None` if there were no changes to tracked files.
+/// * `untracked_tree_oid`: The tree of untracked files (see
+///   `make_untracked_tree`).
+///
+/// Returns: The OID of the new snapshot commit, or `None` if there's no `HEAD`
+/// commit to base the snapshot on.
+#[context("Adding untracked files to working copy snapshot")]
+fn add_untracked_files_to_snapshot(
+    repo: &git2::Repository,
+    stash_oid: Option<git2::Oid>,
+    untracked_tree_oid: git2::Oid,
+) -> anyhow::Result<Option<git2::Oid>> {
+    let head_commit = match repo.head() {
+        Ok(head) => head.peel_to_commit().map_err(wrap_git_error)?,
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+        Err(err) => return Err(wrap_git_error(err)),
+    };
+    let signature = repo.signature().map_err(wrap_git_error)?;
+    let head_description = format!(
+        "{}: {} {}",
+        repo.head()
+            .ok()
+            .and_then(|head| head.shorthand().map(String::from))
+            .unwrap_or_else(|| "(no branch)".to_string()),
+        &head_commit.id().to_string()[..7],
+        head_commit.summary().unwrap_or_default(),
+    );
+
+    let (message, tree, index_commit) = match stash_oid {
+        Some(stash_oid) => {
+            let stash_commit = repo.find_commit(stash_oid).map_err(wrap_git_error)?;
+            let message = stash_commit.message().unwrap_or_default().to_string();
+            let tree = stash_commit.tree().map_err(wrap_git_error)?;
+            let index_commit = stash_commit.parent(1).map_err(wrap_git_error)?;
+            (message, tree, index_commit)
+        }
+        None => {
+            let tree = head_commit.tree().map_err(wrap_git_error)?;
+            let index_oid = repo
+                .commit(
+                    None,
+                    &signature,
+                    &signature,
+                    &format!("index on {}\n", head_description),
+                    &tree,
+                    &[&head_commit],
+                )
+                .map_err(wrap_git_error)?;
+            let index_commit = repo.find_commit(index_oid).map_err(wrap_git_error)?;
+            (format!("WIP on {}\n", head_description), tree, index_commit)
+        }
+    };
+
+    let untracked_tree = repo.find_tree(untracked_tree_oid).map_err(wrap_git_error)?;
+    let untracked_oid = repo
+        .commit(
+            None,
+            &signature,
+            &signature,
+            &format!("untracked files on {}\n", head_description),
+            &untracked_tree,
+            &[],
+        )
+        .map_err(wrap_git_error)?;
+    let untracked_commit = repo.find_commit(untracked_oid).map_err(wrap_git_error)?;
+
+    let snapshot_oid = repo
+        .commit(
+            None,
+            &signature,
+            &signature,
+            &message,
+            &tree,
+            &[&head_commit, &index_commit, &untracked_commit],
+        )
+        .map_err(wrap_git_error)?;
+    Ok(Some(snapshot_oid))
+}
+
+/// Take a snapshot of the index and working copy, if there are any uncommitted
+/// changes.
+///
+/// The snapshot is created with `git stash create`, which stores the changes in
+/// the same format as `git stash`, but without modifying the working copy or
+/// the stash list. Untracked files which aren't ignored are included in the
+/// snapshot as well, as with `git stash --include-untracked`, so that they can
+/// be restored with `git stash apply`.
+///
+/// Args:
+/// * `repo`: The Git repository.
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `event_log_db`: The database to record the snapshot event in.
+/// * `event_tx_id`: The transaction of the operation which is about to move
+///   `HEAD`.
+///
+/// Returns: The OID of the snapshot commit, or `None` if there were no changes
+/// to snapshot.
+#[context("Taking working copy snapshot")]
+pub fn snapshot_working_copy(
+    repo: &git2::Repository,
+    git_executable: &GitExecutable,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+) -> anyhow::Result<Option<git2::Oid>> {
+    let work_dir = match repo.workdir().and_then(|work_dir| work_dir.to_str()) {
+        Some(work_dir) => work_dir,
+        None => return Ok(None),
+    };
+
+    // `git stash create` prints nothing if there are no changes (or if there's
+    // no `HEAD` commit yet).
+    let output = run_git_silent(
+        repo,
+        git_executable,
+        None,
+        &["--work-tree", work_dir, "stash", "create"],
+    )?;
+    let stash_oid = match output.trim() {
+        "" => None,
+        stash_oid => Some(
+            git2::Oid::from_str(stash_oid)
+                .with_context(|| format!("Parsing snapshot commit OID: {:?}", stash_oid))?,
+        ),
+    };
+    let commit_oid = match (stash_oid, make_untracked_tree(repo, Path::new(work_dir))?) {
+        (None, None) => return Ok(None),
+        (Some(stash_oid), None) => stash_oid,
+        (stash_oid, Some(untracked_tree_oid)) => {
+            match add_untracked_files_to_snapshot(repo, stash_oid, untracked_tree_oid)? {
+                Some(commit_oid) => commit_oid,
+                None => return Ok(None),
+            }
+        }
+    };
+    mark_commit_reachable(repo, commit_oid)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs_f64();
+    event_log_db.add_events(vec![Event::WorkingCopySnapshotEvent {
+        timestamp,
+        event_tx_id,
+        commit_oid,
+    }])?;
+    Ok(Some(commit_oid))
+}
+
+/// Record any changes to the stash list since it was last observed.
+///
+/// Newly-pushed stash commits are marked as reachable, so that they can still
+/// be restored by `git undo` after they've been dropped.
+///
+/// Args:
+/// * `repo`: The Git repository.
+/// * `event_log_db`: The database to record the stash events in.
+/// * `event_tx_id`: The transaction to record the stash events under. If
+///   `None`, a new transaction is created, but only if there are any changes to
+///   record.
+#[context("Recording changes to the stash list")]
+pub fn record_stash_changes(
+    repo: &git2::Repository,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: Option<EventTransactionId>,
+) -> anyhow::Result<()> {
+    let stash_entries = get_stash_entries(repo)?;
+    let events = event_log_db.get_events()?;
+    let stash_changes = find_stash_changes(&events, &stash_entries);
+    if stash_changes.is_empty() {
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    let event_tx_id = match event_tx_id {
+        Some(event_tx_id) => event_tx_id,
+        None => event_log_db.make_transaction_id(now, "stash")?,
+    };
+    let mut stash_events = Vec::new();
+    for stash_change in stash_changes {
+        if let StashChange::Pushed { commit_oid, .. } = stash_change {
+            mark_commit_reachable(repo, commit_oid)?;
+        }
+        stash_events.push(stash_change.into_event(timestamp, event_tx_id));
+    }
+    event_log_db.add_events(stash_events)?;
+    Ok(())
+}
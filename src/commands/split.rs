@@ -0,0 +1,467 @@
+//! Split a commit into two commits.
+//!
+//! The user selects which hunks of the commit should go into the first commit,
+//! and the remaining hunks go into the second commit. Both commits are created
+//! in memory, and the original commit's descendants are restacked on top of
+//! the second commit, so the working copy is left untouched.
+
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use cursive::event::Key;
+use cursive::utils::markup::StyledString;
+use cursive::views::{Dialog, LinearLayout, ScrollView, TextView};
+use cursive::{Cursive, CursiveRunnable, CursiveRunner};
+
+use crate::commands::restack::restack_branches;
+use crate::commands::reword::edit_message;
+use crate::core::eventlog::{Event, EventLogDb, EventReplayer};
+use crate::core::formatting::{printable_styled_string, Glyphs, StyledStringBuilder};
+use crate::core::hunks::{apply_hunks, get_hunks, FileHunks};
+use crate::core::mergebase::MergeBaseDb;
+use crate::core::rewrite::{
+    friendly_describe_commit, make_current_graph, make_rewritten_committer, move_head_in_memory,
+    restack_descendants, ExecuteRebasePlanResult,
+};
+use crate::core::tui::{with_siv, SingletonView};
+use crate::declare_views;
+use crate::util::{
+    get_db_conn, get_head_oid, get_repo, resolve_commits, wrap_git_error, GitExecutable,
+    ResolveCommitsResult,
+};
+
+/// Render the list of hunks, with a checkbox indicating whether each hunk is
+/// selected for the first commit.
+fn render_hunks(
+    file_hunks: &[FileHunks],
+    cursor: (usize, usize),
+    selected: &HashSet<(usize, usize)>,
+) -> Vec<StyledString> {
+    let mut lines = Vec::new();
+    for (file_idx, file) in file_hunks.iter().enumerate() {
+        lines.push(StyledString::plain(
+            file.path.to_string_lossy().into_owned(),
+        ));
+        for (hunk_idx, hunk) in file.hunks.iter().enumerate() {
+            let key = (file_idx, hunk_idx);
+            lines.push(StyledString::plain(format!(
+                "{} [{}] {}",
+                if key == cursor { ">" } else { " " },
+                if selected.contains(&key) { "x" } else { " " },
+                hunk.header
+            )));
+            for line in hunk.lines.iter() {
+                let content = String::from_utf8_lossy(&line.content);
+                lines.push(StyledString::plain(format!(
+                    "      {}{}",
+                    line.origin,
+                    content.trim_end_matches('\n')
+                )));
+            }
+        }
+    }
+    lines
+}
+
+/// Interactively select which hunks should go into the first commit.
+///
+/// Returns: The set of selected `(file index, hunk index)` pairs, or `None` if
+/// the user quit without confirming their selection.
+fn select_hunks(
+    mut siv: CursiveRunner<CursiveRunnable>,
+    file_hunks: &[FileHunks],
+) -> anyhow::Result<Option<HashSet<(usize, usize)>>> {
+    #[derive(Clone, Debug)]
+    enum Message {
+        Init,
+        Next,
+        Previous,
+        Toggle,
+        Help,
+        Quit,
+        ConfirmAndQuit,
+    }
+    let (main_tx, main_rx): (Sender<Message>, Receiver<Message>) = channel();
+
+    [
+        ('j'.into(), Message::Next),
+        (Key::Down.into(), Message::Next),
+        ('k'.into(), Message::Previous),
+        (Key::Up.into(), Message::Previous),
+        (' '.into(), Message::Toggle),
+        ('h'.into(), Message::Help),
+        ('H'.into(), Message::Help),
+        ('?'.into(), Message::Help),
+        ('q'.into(), Message::Quit),
+        ('Q'.into(), Message::Quit),
+        (Key::Enter.into(), Message::ConfirmAndQuit),
+    ]
+    .iter()
+    .cloned()
+    .for_each(|(event, message): (cursive::event::Event, Message)| {
+        siv.add_global_callback(event, {
+            let main_tx = main_tx.clone();
+            move |_siv| main_tx.send(message.clone()).unwrap()
+        });
+    });
+
+    let all_hunks: Vec<(usize, usize)> = file_hunks
+        .iter()
+        .enumerate()
+        .flat_map(|(file_idx, file)| {
+            (0..file.hunks.len()).map(move |hunk_idx| (file_idx, hunk_idx))
+        })
+        .collect();
+    let mut cursor_idx = 0;
+    let mut selected: HashSet<(usize, usize)> = HashSet::new();
+    main_tx.send(Message::Init)?;
+    while siv.is_running() {
+        let message = main_rx.try_recv();
+        if message.is_err() {
+            // For tests: only pump the Cursive event loop if we have no events
+            // of our own to process. See `select_past_event`.
+            siv.step();
+        }
+
+        declare_views! {
+            HunksView => ScrollView<TextView>,
+            InfoView => TextView,
+        }
+
+        let redraw = |siv: &mut Cursive, cursor_idx: usize, selected: &HashSet<(usize, usize)>| {
+            let lines = render_hunks(file_hunks, all_hunks[cursor_idx], selected);
+            HunksView::find(siv)
+                .get_inner_mut()
+                .set_content(StyledStringBuilder::from_lines(lines));
+            InfoView::find(siv).set_content(format!(
+                "Select the changes to keep in the first commit ({} of {} selected). Press 'h' for help, 'q' to quit.",
+                selected.len(),
+                all_hunks.len()
+            ));
+        };
+
+        match message {
+            Err(TryRecvError::Disconnected) => break,
+
+            Err(TryRecvError::Empty) => continue,
+
+            Ok(Message::Init) => {
+                let hunks_view: HunksView = ScrollView::new(TextView::new("")).into();
+                let info_view: InfoView = TextView::new("").into();
+                siv.add_layer(LinearLayout::vertical().child(hunks_view).child(info_view));
+                redraw(&mut siv, cursor_idx, &selected);
+            }
+
+            Ok(Message::Next) => {
+                if cursor_idx + 1 < all_hunks.len() {
+                    cursor_idx += 1;
+                }
+                redraw(&mut siv, cursor_idx, &selected);
+            }
+
+            Ok(Message::Previous) => {
+                cursor_idx = cursor_idx.saturating_sub(1);
+                redraw(&mut siv, cursor_idx, &selected);
+            }
+
+            Ok(Message::Toggle) => {
+                let key = all_hunks[cursor_idx];
+                if !selected.remove(&key) {
+                    selected.insert(key);
+                }
+                redraw(&mut siv, cursor_idx, &selected);
+            }
+
+            Ok(Message::Help) => {
+                siv.add_layer(
+                        Dialog::new()
+                            .title("How to use")
+                            .content(TextView::new(
+"Use `git split` to split a commit into two commits.
+
+h/?: Show this help.
+q: Quit without splitting the commit.
+j/k or <down>/<up>: Move to the next/previous hunk.
+<space>: Select/deselect the current hunk for the first commit.
+<enter>: Split the commit. The selected hunks go into the first commit, and the rest go into the second commit.
+",
+                            ))
+                            .dismiss_button("Close"),
+                    );
+            }
+
+            Ok(Message::Quit) => siv.quit(),
+
+            Ok(Message::ConfirmAndQuit) => {
+                siv.quit();
+                return Ok(Some(selected));
+            }
+        };
+
+        if message.is_ok() {
+            siv.refresh();
+        }
+    }
+
+    Ok(None)
+}
+
+/// Split the given commit into two commits, the first containing the selected
+/// hunks and the second containing the remaining hunks.
+///
+/// Args:
+/// * `first_message`: The message for the first commit. If not provided, the user's
+///   editor is opened to edit the original commit's message. The second commit
+///   keeps the original message.
+///
+/// Returns: Exit code (0 denotes successful exit).
+fn split_commit(
+    glyphs: &Glyphs,
+    git_executable: &GitExecutable,
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_log_db: &mut EventLogDb,
+    commit: &git2::Commit,
+    file_hunks: &[FileHunks],
+    selected: &HashSet<(usize, usize)>,
+    first_message: Option<String>,
+) -> anyhow::Result<isize> {
+    let num_hunks: usize = file_hunks.iter().map(|file| file.hunks.len()).sum();
+    if selected.is_empty() || selected.len() == num_hunks {
+        println!("Select some, but not all, of the changes to put into the first commit.");
+        return Ok(1);
+    }
+
+    let message = match commit.message_raw() {
+        Some(message) => message,
+        None => anyhow::bail!(
+            "Could not decode commit message for commit: {:?}",
+            commit.id()
+        ),
+    };
+    let first_message = match first_message {
+        Some(first_message) => {
+            git2::message_prettify(first_message, None).map_err(wrap_git_error)?
+        }
+        None => edit_message(repo, git_executable, message)?,
+    };
+    if first_message.is_empty() {
+        println!("Aborting split due to empty commit message.");
+        return Ok(1);
+    }
+
+    let now = SystemTime::now();
+    let event_tx_id = event_log_db.make_transaction_id(now, "split")?;
+    let committer = make_rewritten_committer(repo, commit)?;
+    let parents: Vec<git2::Commit> = commit.parents().collect();
+    let parent_tree = match parents.first() {
+        Some(parent) => Some(parent.tree().map_err(wrap_git_error)?),
+        None => None,
+    };
+    let first_tree_oid = apply_hunks(
+        repo,
+        parent_tree.as_ref(),
+        file_hunks,
+        |file_idx, hunk_idx| selected.contains(&(file_idx, hunk_idx)),
+    )?;
+    let first_tree = repo.find_tree(first_tree_oid).map_err(wrap_git_error)?;
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+    let first_oid = repo
+        .commit(
+            None,
+            &commit.author(),
+            &committer,
+            &first_message,
+            &first_tree,
+            &parents,
+        )
+        .with_context(|| "Committing first split commit")?;
+    let first_commit = repo.find_commit(first_oid).map_err(wrap_git_error)?;
+
+    let tree = commit.tree().map_err(wrap_git_error)?;
+    let second_oid = repo
+        .commit(
+            None,
+            &commit.author(),
+            &committer,
+            message,
+            &tree,
+            &[&first_commit],
+        )
+        .with_context(|| "Committing second split commit")?;
+
+    let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+    event_log_db.add_events(vec![
+        Event::CommitEvent {
+            timestamp,
+            event_tx_id,
+            commit_oid: first_oid,
+        },
+        Event::RewriteEvent {
+            timestamp,
+            event_tx_id,
+            old_commit_oid: commit.id(),
+            new_commit_oid: second_oid,
+        },
+    ])?;
+    if get_head_oid(repo)? == Some(commit.id()) {
+        move_head_in_memory(repo, event_log_db, event_tx_id, second_oid)?;
+    }
+    println!(
+        "Split into: {}",
+        printable_styled_string(glyphs, friendly_describe_commit(repo, first_oid)?)?
+    );
+    println!(
+        "       and: {}",
+        printable_styled_string(glyphs, friendly_describe_commit(repo, second_oid)?)?
+    );
+
+    let result = restack_descendants(
+        glyphs,
+        git_executable,
+        repo,
+        merge_base_db,
+        event_log_db,
+        event_tx_id,
+        commit.id(),
+    )?;
+    if let ExecuteRebasePlanResult::Failed { exit_code } = result {
+        return Ok(exit_code);
+    }
+
+    let exit_code = restack_branches(repo, merge_base_db, event_log_db, event_tx_id)?;
+    if exit_code != 0 {
+        return Ok(exit_code);
+    }
+    Ok(result.exit_code())
+}
+
+/// Interactively split the given commit into two commits, and restack its
+/// descendants on top of the second commit.
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `hash`: The commit to split. Defaults to `HEAD`.
+/// * `first_message`: The message for the first commit. If not provided, the
+///   user's editor is opened to edit the original commit's message.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn split(
+    git_executable: &GitExecutable,
+    hash: Option<String>,
+    first_message: Option<String>,
+) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::detect();
+    let repo = get_repo()?;
+    let hash = hash.unwrap_or_else(|| "HEAD".to_string());
+    let commit = match resolve_commits(&repo, vec![hash])? {
+        ResolveCommitsResult::Ok { mut commits } => commits.remove(0),
+        ResolveCommitsResult::CommitNotFound { commit } => {
+            println!("Commit not found: {}", commit);
+            return Ok(1);
+        }
+    };
+    if commit.parent_count() > 1 {
+        println!("Cannot split merge commit: {}", commit.id());
+        return Ok(1);
+    }
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    {
+        let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+        let graph = make_current_graph(&repo, &merge_base_db, &event_replayer)?;
+        match graph.get(&commit.id()) {
+            Some(node) if node.is_main => {
+                println!(
+                    "Commits on the main branch cannot be split: {}",
+                    commit.id()
+                );
+                return Ok(1);
+            }
+            Some(_) => {}
+            None => {
+                println!(
+                    "Commit is not visible, so it cannot be split: {}",
+                    commit.id()
+                );
+                return Ok(1);
+            }
+        }
+    }
+
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree().map_err(wrap_git_error)?),
+        None => None,
+    };
+    let tree = commit.tree().map_err(wrap_git_error)?;
+    let file_hunks = get_hunks(&repo, parent_tree.as_ref(), &tree, 3)?;
+    if file_hunks.is_empty() {
+        println!("The commit has no changes to split.");
+        return Ok(1);
+    }
+
+    let selected = match with_siv(|siv| select_hunks(siv, &file_hunks))? {
+        Some(selected) => selected,
+        None => return Ok(0),
+    };
+
+    split_commit(
+        &glyphs,
+        git_executable,
+        &repo,
+        &merge_base_db,
+        &mut event_log_db,
+        &commit,
+        &file_hunks,
+        &selected,
+        first_message,
+    )
+}
+
+#[allow(missing_docs)]
+pub mod testing {
+    use std::collections::HashSet;
+
+    use cursive::{CursiveRunnable, CursiveRunner};
+
+    use crate::core::eventlog::EventLogDb;
+    use crate::core::formatting::Glyphs;
+    use crate::core::hunks::FileHunks;
+    use crate::core::mergebase::MergeBaseDb;
+    use crate::util::GitExecutable;
+
+    pub fn select_hunks(
+        siv: CursiveRunner<CursiveRunnable>,
+        file_hunks: &[FileHunks],
+    ) -> anyhow::Result<Option<HashSet<(usize, usize)>>> {
+        super::select_hunks(siv, file_hunks)
+    }
+
+    pub fn split_commit(
+        glyphs: &Glyphs,
+        git_executable: &GitExecutable,
+        repo: &git2::Repository,
+        merge_base_db: &MergeBaseDb,
+        event_log_db: &mut EventLogDb,
+        commit: &git2::Commit,
+        file_hunks: &[FileHunks],
+        selected: &HashSet<(usize, usize)>,
+        first_message: Option<String>,
+    ) -> anyhow::Result<isize> {
+        super::split_commit(
+            glyphs,
+            git_executable,
+            repo,
+            merge_base_db,
+            event_log_db,
+            commit,
+            file_hunks,
+            selected,
+            first_message,
+        )
+    }
+}
@@ -3,6 +3,7 @@
 //! This is accomplished by finding the events that have happened since a certain
 //! time and inverting them.
 
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::io::{stdin, stdout, BufRead, BufReader, Read, Write};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
@@ -13,16 +14,23 @@ use cursive::event::Key;
 use cursive::utils::markup::StyledString;
 use cursive::views::{Dialog, EditView, LinearLayout, OnEventView, ScrollView, TextView};
 use cursive::{Cursive, CursiveRunnable, CursiveRunner};
+use fn_error_context::context;
 
 use crate::commands::smartlog::render_graph;
-use crate::core::eventlog::{Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId};
+use crate::commands::snapshot::{record_stash_changes, snapshot_working_copy};
+use crate::core::eventlog::{
+    is_other_worktree_head_ref, CommitOriginType, CommitVisibility, Event, EventCursor, EventLogDb,
+    EventReplayer, EventTransactionId, MAIN_WORKTREE_HEAD_REF_NAME,
+};
 use crate::core::formatting::{printable_styled_string, Glyphs, Pluralize, StyledStringBuilder};
 use crate::core::graph::{make_graph, BranchOids, HeadOid, MainBranchOid};
 use crate::core::mergebase::MergeBaseDb;
 use crate::core::metadata::{
     render_commit_metadata, BranchesProvider, CommitMessageProvider, CommitOidProvider,
-    DifferentialRevisionProvider, HiddenExplanationProvider, RelativeTimeProvider,
+    CommitOriginProvider, DifferentialRevisionProvider, HiddenExplanationProvider,
+    RelativeTimeProvider,
 };
+use crate::core::stash::get_stash_entries;
 use crate::core::tui::{with_siv, SingletonView};
 use crate::declare_views;
 use crate::util::{get_db_conn, get_repo, run_git, GitExecutable};
@@ -37,6 +45,10 @@ fn render_cursor_smartlog(
     let head_oid = event_replayer.get_cursor_head_oid(event_cursor);
     let main_branch_oid = event_replayer.get_cursor_main_branch_oid(event_cursor, repo)?;
     let branch_oid_to_names = event_replayer.get_cursor_branch_oid_to_names(event_cursor, repo)?;
+    let other_worktree_head_oids: HashSet<git2::Oid> = event_replayer
+        .get_cursor_worktree_head_oids(event_cursor)
+        .into_values()
+        .collect();
     let graph = make_graph(
         repo,
         merge_base_db,
@@ -44,7 +56,13 @@ fn render_cursor_smartlog(
         event_cursor,
         &HeadOid(head_oid),
         &MainBranchOid(main_branch_oid),
-        &BranchOids(branch_oid_to_names.keys().copied().collect()),
+        &BranchOids(
+            branch_oid_to_names
+                .keys()
+                .chain(other_worktree_head_oids.iter())
+                .copied()
+                .collect(),
+        ),
         true,
     )?;
     let result = render_graph(
@@ -52,11 +70,14 @@ fn render_cursor_smartlog(
         repo,
         merge_base_db,
         &graph,
+        &[],
+        &other_worktree_head_oids,
         &HeadOid(head_oid),
         &mut [
             &mut CommitOidProvider::new(true)?,
             &mut RelativeTimeProvider::new(&repo, SystemTime::now())?,
             &mut HiddenExplanationProvider::new(&graph, &event_replayer, event_cursor)?,
+            &mut CommitOriginProvider::new(event_replayer, event_cursor)?,
             &mut BranchesProvider::new(&repo, &branch_oid_to_names)?,
             &mut DifferentialRevisionProvider::new(&repo)?,
             &mut CommitMessageProvider::new()?,
@@ -66,28 +87,109 @@ fn render_cursor_smartlog(
 }
 
 fn render_ref_name(ref_name: &str) -> String {
+    if ref_name == MAIN_WORKTREE_HEAD_REF_NAME {
+        return "HEAD of main worktree".to_string();
+    }
+    if let Some(worktree_name) = ref_name
+        .strip_prefix("worktrees/")
+        .and_then(|ref_name| ref_name.strip_suffix("/HEAD"))
+    {
+        return format!("HEAD of worktree {}", worktree_name);
+    }
     match ref_name.strip_prefix("refs/heads/") {
         Some(branch_name) => format!("branch {}", branch_name),
         None => format!("ref {}", ref_name),
     }
 }
 
-fn describe_event(repo: &git2::Repository, event: &Event) -> anyhow::Result<Vec<StyledString>> {
-    let render_commit = |oid: git2::Oid| -> anyhow::Result<StyledString> {
-        match repo.find_commit(oid) {
-            Ok(commit) => render_commit_metadata(
-                &commit,
-                &mut [
-                    &mut CommitOidProvider::new(true)?,
-                    &mut CommitMessageProvider::new()?,
-                ],
-            ),
-            Err(_) => Ok(StyledString::plain(format!(
-                "<unavailable: {} (possibly GC'ed)>",
-                oid.to_string()
-            ))),
+fn render_ref_target(ref_target: &Option<String>) -> String {
+    match ref_target {
+        None => "(none)".to_string(),
+        Some(ref_target) => match git2::Oid::from_str(ref_target) {
+            Ok(oid) => oid.to_string()[..8].to_string(),
+            Err(_) => ref_target.clone(),
+        },
+    }
+}
+
+/// Summarize the net effect of the given events on each reference.
+///
+/// Returns: A line for each reference which will point somewhere else after
+/// the events have been applied, in the order that the references are first
+/// updated.
+fn describe_ref_moves(events: &[Event]) -> Vec<String> {
+    let mut ref_moves: Vec<(&str, &Option<String>, &Option<String>)> = Vec::new();
+    for event in events {
+        if let Event::RefUpdateEvent {
+            timestamp: _,
+            event_tx_id: _,
+            ref_name,
+            old_ref,
+            new_ref,
+            message: _,
+        } = event
+        {
+            match ref_moves
+                .iter_mut()
+                .find(|(existing_ref_name, _, _)| existing_ref_name == ref_name)
+            {
+                Some((_, _, existing_new_ref)) => *existing_new_ref = new_ref,
+                None => ref_moves.push((ref_name, old_ref, new_ref)),
+            }
         }
-    };
+    }
+
+    ref_moves
+        .into_iter()
+        .filter(|(_, old_ref, new_ref)| old_ref != new_ref)
+        .map(|(ref_name, old_ref, new_ref)| {
+            let ref_name = match ref_name {
+                "HEAD" => "HEAD".to_string(),
+                ref_name => render_ref_name(ref_name),
+            };
+            format!(
+                "{}: {} -> {}",
+                ref_name,
+                render_ref_target(old_ref),
+                render_ref_target(new_ref)
+            )
+        })
+        .collect()
+}
+
+/// Determine whether there are changes in the working copy or index, including
+/// untracked files, which might be clobbered by moving `HEAD` or restoring a
+/// snapshot.
+#[context("Checking for uncommitted changes")]
+fn has_uncommitted_changes(repo: &git2::Repository) -> anyhow::Result<bool> {
+    let mut status_options = git2::StatusOptions::new();
+    status_options
+        .include_untracked(true)
+        .include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut status_options))
+        .with_context(|| "Reading working copy status")?;
+    Ok(!statuses.is_empty())
+}
+
+fn render_commit(repo: &git2::Repository, oid: git2::Oid) -> anyhow::Result<StyledString> {
+    match repo.find_commit(oid) {
+        Ok(commit) => render_commit_metadata(
+            &commit,
+            &mut [
+                &mut CommitOidProvider::new(true)?,
+                &mut CommitMessageProvider::new()?,
+            ],
+        ),
+        Err(_) => Ok(StyledString::plain(format!(
+            "<unavailable: {} (possibly GC'ed)>",
+            oid
+        ))),
+    }
+}
+
+fn describe_event(repo: &git2::Repository, event: &Event) -> anyhow::Result<Vec<StyledString>> {
+    let render_commit = |oid: git2::Oid| render_commit(repo, oid);
     let result = match event {
         Event::CommitEvent {
             timestamp: _,
@@ -270,6 +372,74 @@ fn describe_event(repo: &git2::Repository, event: &Event) -> anyhow::Result<Vec<
                     .build(),
             ]
         }
+
+        Event::WorkingCopySnapshotEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+        } => {
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Snapshot working copy as ")
+                    .append(render_commit(*commit_oid)?)
+                    .build(),
+                StyledString::new(),
+            ]
+        }
+
+        Event::StashPushEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+            message: _,
+        } => {
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Push stash ")
+                    .append(render_commit(*commit_oid)?)
+                    .build(),
+                StyledString::new(),
+            ]
+        }
+
+        Event::StashDropEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+            message: _,
+        } => {
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain("Drop stash ")
+                    .append(render_commit(*commit_oid)?)
+                    .build(),
+                StyledString::new(),
+            ]
+        }
+
+        Event::CommitOriginEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+            origin_type,
+            source_oid,
+        } => {
+            let (action, preposition) = match origin_type {
+                CommitOriginType::CherryPick => ("Cherry-pick commit ", "as "),
+                CommitOriginType::Revert => ("Revert commit ", "as "),
+                CommitOriginType::Merge => ("Merge commit ", "into "),
+            };
+            vec![
+                StyledStringBuilder::new()
+                    .append_plain(action)
+                    .append(render_commit(*source_oid)?)
+                    .build(),
+                StyledStringBuilder::new()
+                    .append_plain(format!("{:>width$}", preposition, width = action.len()))
+                    .append(render_commit(*commit_oid)?)
+                    .build(),
+            ]
+        }
     };
     Ok(result)
 }
@@ -298,20 +468,240 @@ fn describe_events_numbered(
     Ok(lines)
 }
 
+/// Describe how the repository would change if it were restored from the state
+/// at `from_cursor` to the state at `to_cursor`.
+///
+/// Returns: A line for each change to `HEAD`, to a branch, or to the visibility
+/// of a commit.
+fn describe_cursor_diff(
+    repo: &git2::Repository,
+    event_replayer: &EventReplayer,
+    from_cursor: EventCursor,
+    to_cursor: EventCursor,
+) -> anyhow::Result<Vec<StyledString>> {
+    let render_oid = |oid: Option<git2::Oid>| -> anyhow::Result<StyledString> {
+        match oid {
+            Some(oid) => render_commit(repo, oid),
+            None => Ok(StyledString::plain("(none)")),
+        }
+    };
+    let mut lines = vec![StyledString::plain(
+        "Reverting to this state would make these changes:",
+    )];
+
+    let from_head_oid = event_replayer.get_cursor_head_oid(from_cursor);
+    let to_head_oid = event_replayer.get_cursor_head_oid(to_cursor);
+    if from_head_oid != to_head_oid {
+        lines.push(
+            StyledStringBuilder::new()
+                .append_plain("Move HEAD from ")
+                .append(render_oid(from_head_oid)?)
+                .append_plain(" to ")
+                .append(render_oid(to_head_oid)?)
+                .build(),
+        );
+    }
+
+    let branch_name_to_oid = |cursor: EventCursor| -> anyhow::Result<HashMap<String, git2::Oid>> {
+        let branch_oid_to_names = event_replayer.get_cursor_branch_oid_to_names(cursor, repo)?;
+        Ok(branch_oid_to_names
+            .into_iter()
+            .flat_map(|(oid, names)| names.into_iter().map(move |name| (name, oid)))
+            .collect())
+    };
+    let from_branches = branch_name_to_oid(from_cursor)?;
+    let to_branches = branch_name_to_oid(to_cursor)?;
+    let mut branch_names: Vec<&String> = from_branches.keys().chain(to_branches.keys()).collect();
+    branch_names.sort();
+    branch_names.dedup();
+    for branch_name in branch_names {
+        let from_oid = from_branches.get(branch_name).copied();
+        let to_oid = to_branches.get(branch_name).copied();
+        if from_oid != to_oid {
+            lines.push(
+                StyledStringBuilder::new()
+                    .append_plain(format!("Move branch {} from ", branch_name))
+                    .append(render_oid(from_oid)?)
+                    .append_plain(" to ")
+                    .append(render_oid(to_oid)?)
+                    .build(),
+            );
+        }
+    }
+
+    let is_visible = |cursor: EventCursor, oid: git2::Oid| {
+        matches!(
+            event_replayer.get_cursor_commit_visibility(cursor, oid),
+            Some(CommitVisibility::Visible)
+        )
+    };
+    let mut oids: Vec<git2::Oid> = event_replayer
+        .get_cursor_active_oids(from_cursor)
+        .union(&event_replayer.get_cursor_active_oids(to_cursor))
+        .copied()
+        .collect();
+    oids.sort();
+    for oid in oids {
+        let action = match (is_visible(from_cursor, oid), is_visible(to_cursor, oid)) {
+            (false, true) => "Show commit ",
+            (true, false) => "Hide commit ",
+            (true, true) | (false, false) => continue,
+        };
+        lines.push(
+            StyledStringBuilder::new()
+                .append_plain(action)
+                .append(render_commit(repo, oid)?)
+                .build(),
+        );
+    }
+
+    if lines.len() == 1 {
+        lines = vec![StyledString::plain(
+            "Reverting to this state would make no changes.",
+        )];
+    }
+    Ok(lines)
+}
+
+/// Determine whether the given transaction matches a search query.
+///
+/// The query is matched case-insensitively against the transaction message,
+/// the description of each event (which includes commit messages and
+/// abbreviated OIDs), and the full ref names and OIDs involved in each event.
+fn transaction_matches_query(
+    repo: &git2::Repository,
+    transaction_message: Option<&str>,
+    events: &[Event],
+    query: &str,
+) -> anyhow::Result<bool> {
+    let query = query.to_lowercase();
+    let mut haystacks: Vec<String> = transaction_message.into_iter().map(String::from).collect();
+    for event in events {
+        for line in describe_event(repo, event)? {
+            haystacks.push(line.source().to_string());
+        }
+        match event {
+            Event::RewriteEvent {
+                timestamp: _,
+                event_tx_id: _,
+                old_commit_oid,
+                new_commit_oid,
+            } => {
+                haystacks.push(old_commit_oid.to_string());
+                haystacks.push(new_commit_oid.to_string());
+            }
+            Event::CommitOriginEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                origin_type: _,
+                source_oid,
+            } => {
+                haystacks.push(commit_oid.to_string());
+                haystacks.push(source_oid.to_string());
+            }
+            Event::RefUpdateEvent {
+                timestamp: _,
+                event_tx_id: _,
+                ref_name,
+                old_ref,
+                new_ref,
+                message: _,
+            } => {
+                haystacks.push(ref_name.clone());
+                haystacks.extend(old_ref.iter().cloned());
+                haystacks.extend(new_ref.iter().cloned());
+            }
+            Event::CommitEvent { commit_oid, .. }
+            | Event::HideEvent { commit_oid, .. }
+            | Event::UnhideEvent { commit_oid, .. }
+            | Event::WorkingCopySnapshotEvent { commit_oid, .. }
+            | Event::StashPushEvent { commit_oid, .. }
+            | Event::StashDropEvent { commit_oid, .. } => {
+                haystacks.push(commit_oid.to_string());
+            }
+        }
+    }
+    Ok(haystacks
+        .iter()
+        .any(|haystack| haystack.to_lowercase().contains(&query)))
+}
+
+/// Find the nearest transaction in the given direction which satisfies the
+/// predicate.
+///
+/// Args:
+/// * `event_replayer`: The event replayer.
+/// * `cursor`: The cursor to start searching from. The transaction immediately
+///   before this cursor is not considered.
+/// * `direction`: `1` to search forwards in time, or `-1` to search backwards.
+/// * `predicate`: Called with the events of each candidate transaction.
+///
+/// Returns: A cursor pointing to immediately after the matching transaction,
+/// or `None` if no transaction matched.
+fn find_transaction(
+    event_replayer: &EventReplayer,
+    cursor: EventCursor,
+    direction: isize,
+    mut predicate: impl FnMut(&[Event]) -> anyhow::Result<bool>,
+) -> anyhow::Result<Option<EventCursor>> {
+    let mut cursor = cursor;
+    loop {
+        let next_cursor = event_replayer.advance_cursor_by_transaction(cursor, direction);
+        if next_cursor == cursor {
+            return Ok(None);
+        }
+        cursor = next_cursor;
+        if let Some((_event_id, events)) = event_replayer.get_tx_events_before_cursor(cursor) {
+            if predicate(events)? {
+                return Ok(Some(cursor));
+            }
+        }
+    }
+}
+
+fn get_transaction_message<'a>(
+    transaction_messages: &'a HashMap<EventTransactionId, String>,
+    events: &[Event],
+) -> Option<&'a str> {
+    events
+        .first()
+        .and_then(|event| transaction_messages.get(&event.get_event_tx_id()))
+        .map(|message| message.as_str())
+}
+
+fn matches_transaction_filter(
+    transaction_messages: &HashMap<EventTransactionId, String>,
+    transaction_filter: &[String],
+    events: &[Event],
+) -> bool {
+    match get_transaction_message(transaction_messages, events) {
+        Some(message) => transaction_filter.iter().any(|filter| filter == message),
+        None => false,
+    }
+}
+
 fn select_past_event(
     mut siv: CursiveRunner<CursiveRunnable>,
     glyphs: &Glyphs,
     repo: &git2::Repository,
     merge_base_db: &MergeBaseDb,
     event_replayer: &mut EventReplayer,
+    transaction_messages: &HashMap<EventTransactionId, String>,
 ) -> anyhow::Result<Option<EventCursor>> {
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Debug)]
     enum Message {
         Init,
         Next,
         Previous,
         GoToEvent,
         SetEventReplayerCursor { event_id: isize },
+        Search,
+        SetSearchQuery { query: String, is_submitted: bool },
+        CancelSearch,
+        FilterTransactions,
+        SetTransactionFilter { filter: String },
+        ToggleDiff,
         Help,
         Quit,
         SelectEventIdAndQuit,
@@ -330,6 +720,11 @@ fn select_past_event(
         ('?'.into(), Message::Help),
         ('g'.into(), Message::GoToEvent),
         ('G'.into(), Message::GoToEvent),
+        ('/'.into(), Message::Search),
+        ('d'.into(), Message::ToggleDiff),
+        ('D'.into(), Message::ToggleDiff),
+        ('t'.into(), Message::FilterTransactions),
+        ('T'.into(), Message::FilterTransactions),
         ('q'.into(), Message::Quit),
         ('Q'.into(), Message::Quit),
         (
@@ -342,11 +737,14 @@ fn select_past_event(
     .for_each(|(event, message): (cursive::event::Event, Message)| {
         siv.add_global_callback(event, {
             let main_tx = main_tx.clone();
-            move |_siv| main_tx.send(message).unwrap()
+            move |_siv| main_tx.send(message.clone()).unwrap()
         });
     });
 
     let mut cursor = event_replayer.make_default_cursor();
+    let mut search_start_cursor = cursor;
+    let mut transaction_filter: Vec<String> = Vec::new();
+    let mut show_diff = false;
     let now = SystemTime::now();
     main_tx.send(Message::Init)?;
     while siv.is_running() {
@@ -362,11 +760,14 @@ fn select_past_event(
         declare_views! {
             SmartlogView => ScrollView<TextView>,
             InfoView => TextView,
+            DiffView => TextView,
         }
 
         let redraw = |siv: &mut Cursive,
                       event_replayer: &mut EventReplayer,
-                      event_cursor: EventCursor|
+                      event_cursor: EventCursor,
+                      transaction_filter: &[String],
+                      show_diff: bool|
          -> anyhow::Result<()> {
             let smartlog = render_cursor_smartlog(
                 &glyphs,
@@ -401,21 +802,45 @@ fn select_past_event(
                     } else {
                         String::new()
                     };
+                    let transaction_message =
+                        match get_transaction_message(transaction_messages, events) {
+                            Some(message) => format!(" [{}]", message),
+                            None => String::new(),
+                        };
+                    let filter_description = if transaction_filter.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" Showing only: {}.", transaction_filter.join(", "))
+                    };
                     vec![
                         StyledStringBuilder::new()
                             .append_plain("Repo after transaction ")
                             .append_plain(events[0].get_event_tx_id().to_string())
+                            .append_plain(transaction_message)
                             .append_plain(" (event ")
                             .append_plain(event_id.to_string())
                             .append_plain(")")
                             .append_plain(relative_time)
                             .append_plain(". Press 'h' for help, 'q' to quit.")
+                            .append_plain(filter_description)
                             .build(),
                         event_description,
                     ]
                 }
             };
             InfoView::find(siv).set_content(StyledStringBuilder::from_lines(info_view_contents));
+
+            let diff_view_contents = if show_diff {
+                describe_cursor_diff(
+                    repo,
+                    event_replayer,
+                    event_replayer.make_default_cursor(),
+                    event_cursor,
+                )?
+            } else {
+                Vec::new()
+            };
+            DiffView::find(siv).set_content(StyledStringBuilder::from_lines(diff_view_contents));
             Ok(())
         };
 
@@ -431,27 +856,55 @@ fn select_past_event(
             Ok(Message::Init) => {
                 let smartlog_view: SmartlogView = ScrollView::new(TextView::new("")).into();
                 let info_view: InfoView = TextView::new("").into();
+                let diff_view: DiffView = TextView::new("").into();
                 siv.add_layer(
                     LinearLayout::vertical()
                         .child(smartlog_view)
-                        .child(info_view),
+                        .child(info_view)
+                        .child(diff_view),
                 );
-                redraw(&mut siv, event_replayer, cursor)?;
+            }
+
+            Ok(Message::Next) if !transaction_filter.is_empty() => {
+                let matches_filter = |events: &[Event]| {
+                    Ok(matches_transaction_filter(
+                        transaction_messages,
+                        &transaction_filter,
+                        events,
+                    ))
+                };
+                if let Some(next_cursor) =
+                    find_transaction(event_replayer, cursor, 1, matches_filter)?
+                {
+                    cursor = next_cursor;
+                }
+            }
+
+            Ok(Message::Previous) if !transaction_filter.is_empty() => {
+                let matches_filter = |events: &[Event]| {
+                    Ok(matches_transaction_filter(
+                        transaction_messages,
+                        &transaction_filter,
+                        events,
+                    ))
+                };
+                if let Some(previous_cursor) =
+                    find_transaction(event_replayer, cursor, -1, matches_filter)?
+                {
+                    cursor = previous_cursor;
+                }
             }
 
             Ok(Message::Next) => {
                 cursor = event_replayer.advance_cursor_by_transaction(cursor, 1);
-                redraw(&mut siv, event_replayer, cursor)?;
             }
 
             Ok(Message::Previous) => {
                 cursor = event_replayer.advance_cursor_by_transaction(cursor, -1);
-                redraw(&mut siv, event_replayer, cursor)?;
             }
 
             Ok(Message::SetEventReplayerCursor { event_id }) => {
                 cursor = event_replayer.make_cursor(event_id);
-                redraw(&mut siv, event_replayer, cursor)?;
             }
 
             Ok(Message::GoToEvent) => {
@@ -484,6 +937,111 @@ fn select_past_event(
                 );
             }
 
+            Ok(Message::Search) => {
+                search_start_cursor = cursor;
+                let main_tx_on_edit = main_tx.clone();
+                let main_tx_on_submit = main_tx.clone();
+                let main_tx_on_cancel = main_tx.clone();
+                siv.add_layer(
+                    OnEventView::new(
+                        Dialog::new()
+                            .title("Search for transaction")
+                            .content(
+                                EditView::new()
+                                    .on_edit(move |_siv, text, _cursor| {
+                                        main_tx_on_edit
+                                            .send(Message::SetSearchQuery {
+                                                query: text.to_string(),
+                                                is_submitted: false,
+                                            })
+                                            .unwrap();
+                                    })
+                                    .on_submit(move |siv, text| {
+                                        main_tx_on_submit
+                                            .send(Message::SetSearchQuery {
+                                                query: text.to_string(),
+                                                is_submitted: true,
+                                            })
+                                            .unwrap();
+                                        siv.pop_layer();
+                                    }),
+                            )
+                            .dismiss_button("Cancel"),
+                    )
+                    .on_event(Key::Esc, move |siv| {
+                        main_tx_on_cancel.send(Message::CancelSearch).unwrap();
+                        siv.pop_layer();
+                    }),
+                );
+            }
+
+            Ok(Message::SetSearchQuery {
+                ref query,
+                is_submitted,
+            }) => {
+                let found_cursor = if query.is_empty() {
+                    None
+                } else {
+                    find_transaction(event_replayer, search_start_cursor, -1, |events| {
+                        transaction_matches_query(
+                            repo,
+                            get_transaction_message(transaction_messages, events),
+                            events,
+                            query,
+                        )
+                    })?
+                };
+                cursor = found_cursor.unwrap_or(search_start_cursor);
+                if found_cursor.is_none() && is_submitted && !query.is_empty() {
+                    siv.add_layer(Dialog::info(format!(
+                        "No earlier transaction matches: {}",
+                        query
+                    )));
+                }
+            }
+
+            Ok(Message::CancelSearch) => {
+                cursor = search_start_cursor;
+            }
+
+            Ok(Message::FilterTransactions) => {
+                let main_tx = main_tx.clone();
+                siv.add_layer(
+                    OnEventView::new(
+                        Dialog::new()
+                            .title("Show only transactions of type (e.g. move, restack, rebase)")
+                            .content(
+                                EditView::new()
+                                    .content(transaction_filter.join(", "))
+                                    .on_submit(move |siv, text| {
+                                        main_tx
+                                            .send(Message::SetTransactionFilter {
+                                                filter: text.to_string(),
+                                            })
+                                            .unwrap();
+                                        siv.pop_layer();
+                                    }),
+                            )
+                            .dismiss_button("Cancel"),
+                    )
+                    .on_event(Key::Esc, |siv| {
+                        siv.pop_layer();
+                    }),
+                );
+            }
+
+            Ok(Message::SetTransactionFilter { ref filter }) => {
+                transaction_filter = filter
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|message| !message.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+
+            Ok(Message::ToggleDiff) => {
+                show_diff = !show_diff;
+            }
+
             Ok(Message::Help) => {
                 siv.add_layer(
                         Dialog::new()
@@ -495,6 +1053,9 @@ h/?: Show this help.
 q: Quit.
 p/n or <left>/<right>: View next/previous state.
 g: Go to a provided event ID.
+/: Search backwards for a transaction by message, ref name, commit message, or commit hash.
+t: Only show transactions of the given types when using p/n (e.g. `move, restack, rebase`).
+d: Show/hide the changes that reverting to the given state would make.
 <enter>: Revert the repository to the given state (requires confirmation).
 
 You can also copy a commit hash from the past and manually run `git unhide` or `git rebase` on it.
@@ -513,6 +1074,13 @@ You can also copy a commit hash from the past and manually run `git unhide` or `
         };
 
         if message.is_ok() {
+            redraw(
+                &mut siv,
+                event_replayer,
+                cursor,
+                &transaction_filter,
+                show_diff,
+            )?;
             siv.refresh();
         }
     }
@@ -520,11 +1088,16 @@ You can also copy a commit hash from the past and manually run `git unhide` or `
     Ok(None)
 }
 
+/// Compute the event which undoes the given event.
+///
+/// Returns `None` for events which aren't undone by applying an inverse event,
+/// such as working copy snapshots. Those are restored separately; see
+/// `find_snapshot_to_restore`.
 fn inverse_event(
     event: Event,
     now: SystemTime,
     event_tx_id: EventTransactionId,
-) -> anyhow::Result<Event> {
+) -> anyhow::Result<Option<Event>> {
     let timestamp = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
     let inverse_event = match event {
         Event::CommitEvent {
@@ -579,8 +1152,55 @@ fn inverse_event(
             new_ref: old_ref,
             message: None,
         },
+
+        Event::StashPushEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+            message,
+        } => Event::StashDropEvent {
+            timestamp,
+            event_tx_id,
+            commit_oid,
+            message,
+        },
+
+        Event::StashDropEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+            message,
+        } => Event::StashPushEvent {
+            timestamp,
+            event_tx_id,
+            commit_oid,
+            message,
+        },
+
+        // Snapshots aren't undone by an event. Instead, the working copy is
+        // restored from the snapshot found by `find_snapshot_to_restore`.
+        Event::WorkingCopySnapshotEvent { .. } => return Ok(None),
+
+        // Undoing the accompanying `CommitEvent` hides the commit, which is
+        // all that's needed.
+        Event::CommitOriginEvent { .. } => return Ok(None),
     };
-    Ok(inverse_event)
+    Ok(Some(inverse_event))
+}
+
+/// Find the working copy snapshot to restore when undoing the given events.
+///
+/// This is the earliest snapshot taken after the undo point, since it reflects
+/// the state of the working copy at that point in time.
+fn find_snapshot_to_restore(events: &[Event]) -> Option<git2::Oid> {
+    events.iter().find_map(|event| match event {
+        Event::WorkingCopySnapshotEvent {
+            timestamp: _,
+            event_tx_id: _,
+            commit_oid,
+        } => Some(*commit_oid),
+        _ => None,
+    })
 }
 
 fn optimize_inverse_events(events: Vec<Event>) -> Vec<Event> {
@@ -612,11 +1232,191 @@ fn undo_events(
     event_log_db: &mut EventLogDb,
     event_replayer: &EventReplayer,
     event_cursor: EventCursor,
+    stash: bool,
+) -> anyhow::Result<isize> {
+    let events_since_cursor = event_replayer.get_events_since_cursor(event_cursor);
+    invert_events(
+        in_,
+        out,
+        glyphs,
+        repo,
+        git_executable,
+        event_log_db,
+        events_since_cursor,
+        stash,
+    )
+}
+
+/// An entity which an event affects. Two events conflict if they affect the
+/// same entity.
+#[derive(Debug, PartialEq)]
+enum EventTarget<'a> {
+    Ref(&'a str),
+    Commit(git2::Oid),
+}
+
+fn get_event_targets(event: &Event) -> Vec<EventTarget<'_>> {
+    match event {
+        Event::RefUpdateEvent { ref_name, .. } => vec![EventTarget::Ref(ref_name)],
+        Event::RewriteEvent {
+            old_commit_oid,
+            new_commit_oid,
+            ..
+        } => vec![
+            EventTarget::Commit(*old_commit_oid),
+            EventTarget::Commit(*new_commit_oid),
+        ],
+        Event::CommitEvent { commit_oid, .. }
+        | Event::HideEvent { commit_oid, .. }
+        | Event::UnhideEvent { commit_oid, .. }
+        | Event::WorkingCopySnapshotEvent { commit_oid, .. }
+        | Event::StashPushEvent { commit_oid, .. }
+        | Event::StashDropEvent { commit_oid, .. }
+        | Event::CommitOriginEvent { commit_oid, .. } => {
+            vec![EventTarget::Commit(*commit_oid)]
+        }
+    }
+}
+
+/// Undo only the events in the given transaction, leaving the events of all
+/// other transactions in place.
+///
+/// If a later transaction updated one of the same references, then the undo is
+/// refused, since inverting the event would clobber the later update. If a
+/// later transaction affected one of the same commits, then the events for that
+/// commit are skipped with a warning, and the rest are undone. Working copy
+/// snapshots are never restored by a selective undo.
+fn undo_transaction(
+    in_: &mut impl Read,
+    out: &mut impl Write,
+    glyphs: &Glyphs,
+    repo: &git2::Repository,
+    git_executable: &GitExecutable,
+    event_log_db: &mut EventLogDb,
+    event_replayer: &EventReplayer,
+    transaction_id: EventTransactionId,
+    stash: bool,
+) -> anyhow::Result<isize> {
+    let all_events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+    let first_event_index = match all_events
+        .iter()
+        .position(|event| event.get_event_tx_id() == transaction_id)
+    {
+        Some(first_event_index) => first_event_index,
+        None => {
+            writeln!(
+                out,
+                "There are no events in transaction {}, exiting.",
+                transaction_id.to_string()
+            )?;
+            return Ok(1);
+        }
+    };
+    let (transaction_events, later_events): (Vec<&Event>, Vec<&Event>) = all_events
+        [first_event_index..]
+        .iter()
+        .partition(|event| event.get_event_tx_id() == transaction_id);
+
+    let find_conflicting_event = |target: &EventTarget| -> Option<&Event> {
+        later_events
+            .iter()
+            .copied()
+            .find(|later_event| get_event_targets(later_event).contains(target))
+    };
+
+    let mut ref_conflicts = Vec::new();
+    let mut events_to_invert = Vec::new();
+    for event in transaction_events {
+        if let Event::WorkingCopySnapshotEvent { .. } | Event::CommitOriginEvent { .. } = event {
+            continue;
+        }
+
+        let mut is_skipped = false;
+        for target in get_event_targets(event) {
+            let conflicting_event = match find_conflicting_event(&target) {
+                Some(conflicting_event) => conflicting_event,
+                None => continue,
+            };
+            let conflicting_tx_id = conflicting_event.get_event_tx_id().to_string();
+            match target {
+                EventTarget::Ref(ref_name) => {
+                    let ref_name = match ref_name {
+                        "HEAD" => "HEAD".to_string(),
+                        ref_name => render_ref_name(ref_name),
+                    };
+                    ref_conflicts.push(format!(
+                        "{} (changed again in transaction {})",
+                        ref_name, conflicting_tx_id
+                    ));
+                }
+                EventTarget::Commit(commit_oid) => {
+                    let line = StyledStringBuilder::new()
+                        .append_plain("Skipping changes to commit ")
+                        .append(render_commit(repo, commit_oid)?)
+                        .append_plain(format!(
+                            " (changed again in transaction {})",
+                            conflicting_tx_id
+                        ))
+                        .build();
+                    writeln!(out, "{}", printable_styled_string(glyphs, line)?)?;
+                    is_skipped = true;
+                }
+            }
+        }
+        if !is_skipped {
+            events_to_invert.push(event.clone());
+        }
+    }
+
+    if !ref_conflicts.is_empty() {
+        writeln!(
+            out,
+            "Cannot undo transaction {} by itself, because later transactions also changed:",
+            transaction_id.to_string()
+        )?;
+        for ref_conflict in ref_conflicts {
+            writeln!(out, "  {}", ref_conflict)?;
+        }
+        writeln!(
+            out,
+            "Run `git undo` without --transaction to undo those transactions as well."
+        )?;
+        return Ok(1);
+    }
+
+    invert_events(
+        in_,
+        out,
+        glyphs,
+        repo,
+        git_executable,
+        event_log_db,
+        &events_to_invert,
+        stash,
+    )
+}
+
+/// Apply the inverse of the given events, after confirming with the user.
+///
+/// Args:
+/// * `events`: The events to invert, ordered from least recent to most recent.
+/// * `stash`: Whether to stash any uncommitted changes before applying the
+///   inverse events, rather than refusing to proceed.
+///
+/// Returns: The exit code.
+fn invert_events(
+    in_: &mut impl Read,
+    out: &mut impl Write,
+    glyphs: &Glyphs,
+    repo: &git2::Repository,
+    git_executable: &GitExecutable,
+    event_log_db: &mut EventLogDb,
+    events: &[Event],
+    stash: bool,
 ) -> anyhow::Result<isize> {
     let now = SystemTime::now();
-    let event_tx_id = event_log_db.make_transaction_id(now, "undo")?;
-    let inverse_events: Vec<Event> = event_replayer
-        .get_events_since_cursor(event_cursor)
+    let snapshot_oid = find_snapshot_to_restore(events);
+    let inverse_events: Vec<Event> = events
         .iter()
         .rev()
         .filter(|event| {
@@ -632,8 +1432,21 @@ fn undo_events(
                 } if ref_name == "HEAD"
             )
         })
-        .map(|event| inverse_event(event.clone(), now, event_tx_id))
-        .collect::<anyhow::Result<Vec<Event>>>()?;
+        // Another worktree's `HEAD` can't be moved from this one.
+        .filter(|event| {
+            !matches!(
+                event,
+                Event::RefUpdateEvent { ref_name, .. } if is_other_worktree_head_ref(ref_name)
+            )
+        })
+        // The inverse events are moved into the undo transaction once it's
+        // been created below, so that a refused or aborted undo doesn't
+        // leave an empty transaction behind.
+        .map(|event| inverse_event(event.clone(), now, event.get_event_tx_id()))
+        .collect::<anyhow::Result<Vec<Option<Event>>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
     let mut inverse_events = optimize_inverse_events(inverse_events);
 
     // Move any checkout operations to be first. Otherwise, we have the risk
@@ -645,16 +1458,44 @@ fn undo_events(
         _ => 1,
     });
 
-    if inverse_events.is_empty() {
+    if inverse_events.is_empty() && snapshot_oid.is_none() {
         writeln!(out, "No undo actions to apply, exiting.")?;
         return Ok(0);
     }
 
+    let is_working_copy_dirty = has_uncommitted_changes(repo)?;
+    if is_working_copy_dirty && !stash {
+        writeln!(
+            out,
+            "\
+The working copy has uncommitted changes, which could be overwritten by this undo.
+Commit or stash your changes first, or re-run with --stash to stash them automatically."
+        )?;
+        return Ok(1);
+    }
+
     writeln!(out, "Will apply these actions:")?;
     let events = describe_events_numbered(&repo, &inverse_events)?;
     for line in events {
         writeln!(out, "{}", printable_styled_string(&glyphs, line)?)?;
     }
+    if let Some(snapshot_oid) = snapshot_oid {
+        let line = StyledStringBuilder::new()
+            .append_plain(format!(
+                "{}. Restore working copy from snapshot ",
+                inverse_events.len() + 1
+            ))
+            .append(render_commit(repo, snapshot_oid)?)
+            .build();
+        writeln!(out, "{}", printable_styled_string(glyphs, line)?)?;
+    }
+    let ref_moves = describe_ref_moves(&inverse_events);
+    if !ref_moves.is_empty() {
+        writeln!(out, "These references will move:")?;
+        for ref_move in ref_moves {
+            writeln!(out, "  {}", ref_move)?;
+        }
+    }
 
     let confirmed = {
         write!(out, "Confirm? [yN] ")?;
@@ -681,6 +1522,43 @@ fn undo_events(
     }
     .to_string();
 
+    if is_working_copy_dirty {
+        let exit_code = run_git(
+            git_executable,
+            None,
+            &[
+                "stash",
+                "push",
+                "--include-untracked",
+                "--message",
+                "branchless: stash before undo",
+            ],
+        )?;
+        if exit_code != 0 {
+            writeln!(out, "Failed to stash working copy changes, aborting.")?;
+            return Ok(exit_code);
+        }
+    }
+
+    let event_tx_id = event_log_db.make_transaction_id(now, "undo")?;
+    let inverse_events: Vec<Event> = inverse_events
+        .into_iter()
+        .map(|event| event.with_event_tx_id(event_tx_id))
+        .collect();
+
+    // Save the current state of the working copy, so that this undo can itself
+    // be undone.
+    snapshot_working_copy(repo, git_executable, event_log_db, event_tx_id)?;
+    if snapshot_oid.is_some() {
+        // The snapshot will be applied on top of the restored `HEAD`, so any
+        // uncommitted changes (which were just saved) need to be discarded
+        // first.
+        let exit_code = run_git(git_executable, Some(event_tx_id), &["reset", "--hard"])?;
+        if exit_code != 0 {
+            anyhow::bail!("Could not discard working copy changes before restoring snapshot");
+        }
+    }
+
     for event in inverse_events.into_iter() {
         match event {
             Event::RefUpdateEvent {
@@ -759,25 +1637,154 @@ fn undo_events(
             | Event::RewriteEvent { .. } => {
                 event_log_db.add_events(vec![event])?;
             }
+            Event::WorkingCopySnapshotEvent { .. } => {
+                // Snapshots are restored below, after `HEAD` has been moved.
+            }
+            Event::CommitOriginEvent { .. } => {
+                // Not produced by `inverse_event`.
+            }
+            Event::StashPushEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                message,
+            } => {
+                // Restore a dropped stash entry. The resulting change to the
+                // stash list is recorded below.
+                let stash_entries = get_stash_entries(repo)?;
+                if stash_entries
+                    .iter()
+                    .any(|stash_entry| stash_entry.commit_oid == commit_oid)
+                {
+                    writeln!(
+                        out,
+                        "Stash entry {} already exists, not restoring it.",
+                        commit_oid
+                    )?;
+                } else {
+                    let exit_code = run_git(
+                        git_executable,
+                        Some(event_tx_id),
+                        &[
+                            "stash",
+                            "store",
+                            "--message",
+                            &message,
+                            &commit_oid.to_string(),
+                        ],
+                    )
+                    .with_context(|| "Restoring stash entry")?;
+                    if exit_code != 0 {
+                        writeln!(out, "Failed to restore stash entry, aborting.")?;
+                        return Ok(exit_code);
+                    }
+                }
+            }
+            Event::StashDropEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                message: _,
+            } => {
+                let stash_entries = get_stash_entries(repo)?;
+                match stash_entries
+                    .iter()
+                    .find(|stash_entry| stash_entry.commit_oid == commit_oid)
+                {
+                    Some(stash_entry) => {
+                        let exit_code = run_git(
+                            git_executable,
+                            Some(event_tx_id),
+                            &["stash", "drop", &format!("stash@{{{}}}", stash_entry.index)],
+                        )
+                        .with_context(|| "Dropping stash entry")?;
+                        if exit_code != 0 {
+                            writeln!(out, "Failed to drop stash entry, aborting.")?;
+                            return Ok(exit_code);
+                        }
+                    }
+                    None => {
+                        writeln!(
+                            out,
+                            "Stash entry {} did not exist, not dropping it.",
+                            commit_oid
+                        )?;
+                    }
+                }
+            }
         }
     }
+    record_stash_changes(repo, event_log_db, Some(event_tx_id))?;
 
     writeln!(out, "Applied {}.", num_inverse_events)?;
+
+    if let Some(snapshot_oid) = snapshot_oid {
+        let snapshot_oid = snapshot_oid.to_string();
+        let exit_code = run_git(
+            git_executable,
+            Some(event_tx_id),
+            &["stash", "apply", "--index", &snapshot_oid],
+        )?;
+        if exit_code != 0 {
+            writeln!(
+                out,
+                "Failed to restore working copy snapshot. You can try to restore it manually with: git stash apply {}",
+                snapshot_oid
+            )?;
+            return Ok(exit_code);
+        }
+        writeln!(out, "Restored working copy from snapshot {}.", snapshot_oid)?;
+    }
     Ok(0)
 }
 
 /// Restore the repository to a previous state interactively.
-pub fn undo(git_executable: &GitExecutable) -> anyhow::Result<isize> {
+///
+/// Args:
+/// * `git_executable`: The path to the `git` executable on disk.
+/// * `transaction_id`: If provided, undo only the events in this transaction,
+///   rather than selecting a previous state interactively.
+/// * `stash`: Whether to stash any uncommitted changes before undoing, rather
+///   than refusing to proceed.
+pub fn undo(
+    git_executable: &GitExecutable,
+    transaction_id: Option<EventTransactionId>,
+    stash: bool,
+) -> anyhow::Result<isize> {
     let glyphs = Glyphs::detect();
     let repo = get_repo()?;
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
     let mut event_log_db = EventLogDb::new(&conn)?;
-    let mut event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    record_stash_changes(&repo, &mut event_log_db, None)?;
+    let mut event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+
+    if let Some(transaction_id) = transaction_id {
+        let result = undo_transaction(
+            &mut stdin(),
+            &mut stdout().lock(),
+            &glyphs,
+            &repo,
+            git_executable,
+            &mut event_log_db,
+            &event_replayer,
+            transaction_id,
+            stash,
+        )?;
+        return Ok(result);
+    }
+    let transaction_messages = event_log_db.get_transaction_messages()?;
 
     let event_cursor = {
         let result = with_siv(|siv| {
-            select_past_event(siv, &glyphs, &repo, &merge_base_db, &mut event_replayer)
+            select_past_event(
+                siv,
+                &glyphs,
+                &repo,
+                &merge_base_db,
+                &mut event_replayer,
+                &transaction_messages,
+            )
         })?;
         match result {
             Some(event_cursor) => event_cursor,
@@ -794,17 +1801,19 @@ pub fn undo(git_executable: &GitExecutable) -> anyhow::Result<isize> {
         &mut event_log_db,
         &event_replayer,
         event_cursor,
+        stash,
     )?;
     Ok(result)
 }
 
 #[allow(missing_docs)]
 pub mod testing {
+    use std::collections::HashMap;
     use std::io::{Read, Write};
 
     use cursive::{CursiveRunnable, CursiveRunner};
 
-    use crate::core::eventlog::{EventCursor, EventLogDb, EventReplayer};
+    use crate::core::eventlog::{EventCursor, EventLogDb, EventReplayer, EventTransactionId};
     use crate::core::formatting::Glyphs;
     use crate::core::mergebase::MergeBaseDb;
     use crate::util::GitExecutable;
@@ -815,8 +1824,40 @@ pub mod testing {
         repo: &git2::Repository,
         merge_base_db: &MergeBaseDb,
         event_replayer: &mut EventReplayer,
+        transaction_messages: &HashMap<EventTransactionId, String>,
     ) -> anyhow::Result<Option<EventCursor>> {
-        super::select_past_event(siv, glyphs, repo, merge_base_db, event_replayer)
+        super::select_past_event(
+            siv,
+            glyphs,
+            repo,
+            merge_base_db,
+            event_replayer,
+            transaction_messages,
+        )
+    }
+
+    pub fn undo_transaction(
+        in_: &mut impl Read,
+        out: &mut impl Write,
+        glyphs: &Glyphs,
+        repo: &git2::Repository,
+        git_executable: &GitExecutable,
+        event_log_db: &mut EventLogDb,
+        event_replayer: &EventReplayer,
+        transaction_id: EventTransactionId,
+        stash: bool,
+    ) -> anyhow::Result<isize> {
+        super::undo_transaction(
+            in_,
+            out,
+            glyphs,
+            repo,
+            git_executable,
+            event_log_db,
+            event_replayer,
+            transaction_id,
+            stash,
+        )
     }
 
     pub fn undo_events(
@@ -828,6 +1869,7 @@ pub mod testing {
         event_log_db: &mut EventLogDb,
         event_replayer: &EventReplayer,
         event_cursor: EventCursor,
+        stash: bool,
     ) -> anyhow::Result<isize> {
         super::undo_events(
             in_,
@@ -838,6 +1880,7 @@ pub mod testing {
             event_log_db,
             event_replayer,
             event_cursor,
+            stash,
         )
     }
 }
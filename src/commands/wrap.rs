@@ -67,7 +67,7 @@ mod tests {
             let repo = git.get_repo()?;
             let conn = get_db_conn(&repo)?;
             let event_log_db = EventLogDb::new(&conn)?;
-            let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+            let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
             let events: Vec<Event> = get_event_replayer_events(&event_replayer)
                 .iter()
                 .map(|event| redact_event_timestamp(event.clone()))
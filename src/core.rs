@@ -4,7 +4,10 @@ pub mod config;
 pub mod eventlog;
 pub mod formatting;
 pub mod graph;
+pub mod hunks;
 pub mod mergebase;
 pub mod metadata;
+pub mod obsmarkers;
 pub mod rewrite;
+pub mod stash;
 pub mod tui;
@@ -1,9 +1,12 @@
 //! Accesses repo-specific configuration.
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 use fn_error_context::context;
 
+use crate::util::get_repo_common_dir;
+
 #[context("Getting repo configuration")]
 fn get_config(repo: &git2::Repository) -> anyhow::Result<git2::Config> {
     let result = repo.config()?;
@@ -12,9 +15,10 @@ fn get_config(repo: &git2::Repository) -> anyhow::Result<git2::Config> {
 
 /// Get the path where Git hooks are stored on disk.
 pub fn get_core_hooks_path(repo: &git2::Repository) -> anyhow::Result<PathBuf> {
-    let result = get_config(repo)?
-        .get_path("core.hooksPath")
-        .unwrap_or_else(|_err| repo.path().join("hooks"));
+    let result = match get_config(repo)?.get_path("core.hooksPath") {
+        Ok(hooks_path) => hooks_path,
+        Err(_) => get_repo_common_dir(repo)?.join("hooks"),
+    };
     Ok(result)
 }
 
@@ -38,6 +42,42 @@ pub fn get_restack_preserve_timestamps(repo: &git2::Repository) -> anyhow::Resul
         .or(Ok(false))
 }
 
+/// Config key for `get_in_memory_hooks_policy`.
+pub const IN_MEMORY_HOOKS_CONFIG_KEY: &str = "branchless.inMemory.hooks";
+
+/// Which Git hooks to run for commits created by in-memory operations, such as
+/// in-memory rebases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InMemoryHooksPolicy {
+    /// Run no hooks.
+    None,
+
+    /// Run the `post-rewrite` hook once for the whole operation.
+    PostRewrite,
+
+    /// Run the `post-commit` hook for each created commit, and then the
+    /// `post-rewrite` hook once for the whole operation.
+    All,
+}
+
+/// Get the policy for which hooks to run during in-memory operations.
+pub fn get_in_memory_hooks_policy(repo: &git2::Repository) -> anyhow::Result<InMemoryHooksPolicy> {
+    let value = match get_config(repo)?.get_string(IN_MEMORY_HOOKS_CONFIG_KEY) {
+        Ok(value) => value,
+        Err(_) => return Ok(InMemoryHooksPolicy::PostRewrite),
+    };
+    match value.as_str() {
+        "none" => Ok(InMemoryHooksPolicy::None),
+        "post-rewrite" => Ok(InMemoryHooksPolicy::PostRewrite),
+        "all" => Ok(InMemoryHooksPolicy::All),
+        value => anyhow::bail!(
+            "Invalid value for {}: {:?} (expected one of: none, post-rewrite, all)",
+            IN_MEMORY_HOOKS_CONFIG_KEY,
+            value
+        ),
+    }
+}
+
 /// Config key for `get_restack_warn_abandoned`.
 pub const RESTACK_WARN_ABANDONED_CONFIG_KEY: &str = "branchless.restack.warnAbandoned";
 
@@ -69,3 +109,45 @@ pub fn get_commit_metadata_relative_time(repo: &git2::Repository) -> anyhow::Res
         .get_bool("branchless.commitMetadata.relativeTime")
         .or(Ok(true))
 }
+
+fn days_to_duration(days: i64) -> Duration {
+    let days: u64 = if days < 0 { 0 } else { days as u64 };
+    Duration::from_secs(days * 24 * 60 * 60)
+}
+
+/// How long to wait before compacting events in the event log during garbage
+/// collection. Configured as a number of days.
+pub fn get_gc_compact_events_after(repo: &git2::Repository) -> anyhow::Result<Duration> {
+    let days = get_config(repo)?
+        .get_i64("branchless.gc.compactEventsAfter")
+        .unwrap_or(30);
+    Ok(days_to_duration(days))
+}
+
+/// How long to keep hidden commits alive before allowing them to be collected
+/// by Git's garbage collection. Configured as a number of days.
+pub fn get_gc_retain_hidden_for(repo: &git2::Repository) -> anyhow::Result<Duration> {
+    let days = get_config(repo)?
+        .get_i64("branchless.gc.retainHiddenFor")
+        .unwrap_or(0);
+    Ok(days_to_duration(days))
+}
+
+/// How long to keep working copy snapshots alive before allowing them to be
+/// collected by Git's garbage collection. Configured as a number of days.
+pub fn get_gc_retain_snapshots_for(repo: &git2::Repository) -> anyhow::Result<Duration> {
+    let days = get_config(repo)?
+        .get_i64("branchless.gc.retainSnapshotsFor")
+        .unwrap_or(30);
+    Ok(days_to_duration(days))
+}
+
+/// The maximum number of entries to keep in the merge-base cache after garbage
+/// collection. The least-recently-used entries are evicted first.
+pub fn get_gc_merge_base_cache_size(repo: &git2::Repository) -> anyhow::Result<usize> {
+    let size = get_config(repo)?
+        .get_i64("branchless.gc.mergeBaseCacheSize")
+        .unwrap_or(10000);
+    let size = if size < 0 { 0 } else { size as usize };
+    Ok(size)
+}
@@ -16,7 +16,10 @@ use fn_error_context::context;
 use log::warn;
 
 use crate::core::config::get_main_branch_name;
-use crate::util::{get_main_branch_oid, wrap_git_error};
+use crate::core::obsmarkers::OBSMARKERS_REF_NAME;
+use crate::util::{
+    get_main_branch_oid, get_worktree_head_ref_name, retry_on_db_busy, wrap_git_error,
+};
 
 /// When this environment variable is set, we reuse the ID for the transaction
 /// which the caller has already started.
@@ -49,7 +52,7 @@ struct Row {
 ///
 /// Unlike in a database, there is no specific guarantee that an event
 /// transaction is an atomic unit of work.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct EventTransactionId(isize);
 
 impl ToString for EventTransactionId {
@@ -169,6 +172,99 @@ pub enum Event {
         /// The OID of the commit that was unhidden.
         commit_oid: git2::Oid,
     },
+
+    /// Indicates that the state of the working copy was recorded before an
+    /// operation which moved `HEAD`.
+    ///
+    /// The snapshot is stored as a commit in the same format as `git stash`.
+    /// It's never shown in the smartlog, but can be restored by `git undo`.
+    WorkingCopySnapshotEvent {
+        /// The timestamp of the event.
+        timestamp: f64,
+
+        /// The transaction ID of the event.
+        event_tx_id: EventTransactionId,
+
+        /// The OID of the commit storing the snapshot.
+        commit_oid: git2::Oid,
+    },
+
+    /// Indicates that an entry was added to the stash list (e.g. with `git
+    /// stash` or `git stash store`).
+    StashPushEvent {
+        /// The timestamp of the event.
+        timestamp: f64,
+
+        /// The transaction ID of the event.
+        event_tx_id: EventTransactionId,
+
+        /// The OID of the stash commit.
+        commit_oid: git2::Oid,
+
+        /// The message of the stash entry, as shown in `git stash list`.
+        message: String,
+    },
+
+    /// Indicates that an entry was removed from the stash list (e.g. with `git
+    /// stash drop` or `git stash pop`).
+    StashDropEvent {
+        /// The timestamp of the event.
+        timestamp: f64,
+
+        /// The transaction ID of the event.
+        event_tx_id: EventTransactionId,
+
+        /// The OID of the stash commit.
+        commit_oid: git2::Oid,
+
+        /// The message of the stash entry, as shown in `git stash list`.
+        message: String,
+    },
+
+    /// Indicates that a commit was derived from another commit by a
+    /// cherry-pick, revert, or merge. This is recorded alongside the
+    /// `CommitEvent` for the new commit.
+    CommitOriginEvent {
+        /// The timestamp of the event.
+        timestamp: f64,
+
+        /// The transaction ID of the event.
+        event_tx_id: EventTransactionId,
+
+        /// The OID of the new commit.
+        commit_oid: git2::Oid,
+
+        /// How the new commit was derived from the source commit.
+        origin_type: CommitOriginType,
+
+        /// The OID of the commit which was cherry-picked, reverted, or merged.
+        source_oid: git2::Oid,
+    },
+}
+
+/// The way in which a commit was derived from another commit. See
+/// `Event::CommitOriginEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitOriginType {
+    /// The commit was created by `git cherry-pick`.
+    CherryPick,
+
+    /// The commit was created by `git revert`.
+    Revert,
+
+    /// The commit is a merge commit, and the source commit is one of the
+    /// commits which was merged in (i.e. a parent other than the first).
+    Merge,
+}
+
+impl CommitOriginType {
+    fn to_event_type(self) -> &'static str {
+        match self {
+            CommitOriginType::CherryPick => "cherry-pick",
+            CommitOriginType::Revert => "revert",
+            CommitOriginType::Merge => "merge",
+        }
+    }
 }
 
 impl Event {
@@ -180,6 +276,10 @@ impl Event {
             Event::CommitEvent { timestamp, .. } => timestamp,
             Event::HideEvent { timestamp, .. } => timestamp,
             Event::UnhideEvent { timestamp, .. } => timestamp,
+            Event::WorkingCopySnapshotEvent { timestamp, .. } => timestamp,
+            Event::StashPushEvent { timestamp, .. } => timestamp,
+            Event::StashDropEvent { timestamp, .. } => timestamp,
+            Event::CommitOriginEvent { timestamp, .. } => timestamp,
         };
         SystemTime::UNIX_EPOCH + Duration::from_secs_f64(*timestamp)
     }
@@ -192,7 +292,27 @@ impl Event {
             Event::CommitEvent { event_tx_id, .. } => *event_tx_id,
             Event::HideEvent { event_tx_id, .. } => *event_tx_id,
             Event::UnhideEvent { event_tx_id, .. } => *event_tx_id,
+            Event::WorkingCopySnapshotEvent { event_tx_id, .. } => *event_tx_id,
+            Event::StashPushEvent { event_tx_id, .. } => *event_tx_id,
+            Event::StashDropEvent { event_tx_id, .. } => *event_tx_id,
+            Event::CommitOriginEvent { event_tx_id, .. } => *event_tx_id,
+        }
+    }
+
+    /// Move this event into the given event transaction.
+    pub fn with_event_tx_id(mut self, new_event_tx_id: EventTransactionId) -> Self {
+        match &mut self {
+            Event::RewriteEvent { event_tx_id, .. }
+            | Event::RefUpdateEvent { event_tx_id, .. }
+            | Event::CommitEvent { event_tx_id, .. }
+            | Event::HideEvent { event_tx_id, .. }
+            | Event::UnhideEvent { event_tx_id, .. }
+            | Event::WorkingCopySnapshotEvent { event_tx_id, .. }
+            | Event::StashPushEvent { event_tx_id, .. }
+            | Event::StashDropEvent { event_tx_id, .. }
+            | Event::CommitOriginEvent { event_tx_id, .. } => *event_tx_id = new_event_tx_id,
         }
+        self
     }
 }
 
@@ -272,6 +392,66 @@ impl From<Event> for Row {
                 ref_name: None,
                 message: None,
             },
+
+            Event::WorkingCopySnapshotEvent {
+                timestamp,
+                event_tx_id: EventTransactionId(event_tx_id),
+                commit_oid,
+            } => Row {
+                timestamp,
+                event_tx_id,
+                type_: String::from("snapshot"),
+                ref1: Some(commit_oid.to_string()),
+                ref2: None,
+                ref_name: None,
+                message: None,
+            },
+
+            Event::StashPushEvent {
+                timestamp,
+                event_tx_id: EventTransactionId(event_tx_id),
+                commit_oid,
+                message,
+            } => Row {
+                timestamp,
+                event_tx_id,
+                type_: String::from("stash-push"),
+                ref1: Some(commit_oid.to_string()),
+                ref2: None,
+                ref_name: None,
+                message: Some(message),
+            },
+
+            Event::StashDropEvent {
+                timestamp,
+                event_tx_id: EventTransactionId(event_tx_id),
+                commit_oid,
+                message,
+            } => Row {
+                timestamp,
+                event_tx_id,
+                type_: String::from("stash-drop"),
+                ref1: Some(commit_oid.to_string()),
+                ref2: None,
+                ref_name: None,
+                message: Some(message),
+            },
+
+            Event::CommitOriginEvent {
+                timestamp,
+                event_tx_id: EventTransactionId(event_tx_id),
+                commit_oid,
+                origin_type,
+                source_oid,
+            } => Row {
+                timestamp,
+                event_tx_id,
+                type_: String::from(origin_type.to_event_type()),
+                ref1: Some(commit_oid.to_string()),
+                ref2: Some(source_oid.to_string()),
+                ref_name: None,
+                message: None,
+            },
         }
     }
 }
@@ -358,6 +538,52 @@ impl TryFrom<Row> for Event {
                 }
             }
 
+            "snapshot" => {
+                let commit_oid = get_oid(&ref1, "commit OID")?;
+                Event::WorkingCopySnapshotEvent {
+                    timestamp,
+                    event_tx_id,
+                    commit_oid,
+                }
+            }
+
+            "stash-push" => {
+                let commit_oid = get_oid(&ref1, "commit OID")?;
+                Event::StashPushEvent {
+                    timestamp,
+                    event_tx_id,
+                    commit_oid,
+                    message: message.unwrap_or_default(),
+                }
+            }
+
+            "stash-drop" => {
+                let commit_oid = get_oid(&ref1, "commit OID")?;
+                Event::StashDropEvent {
+                    timestamp,
+                    event_tx_id,
+                    commit_oid,
+                    message: message.unwrap_or_default(),
+                }
+            }
+
+            "cherry-pick" | "revert" | "merge" => {
+                let commit_oid = get_oid(&ref1, "commit OID")?;
+                let source_oid = get_oid(&ref2, "source commit OID")?;
+                let origin_type = match type_.as_str() {
+                    "cherry-pick" => CommitOriginType::CherryPick,
+                    "revert" => CommitOriginType::Revert,
+                    _ => CommitOriginType::Merge,
+                };
+                Event::CommitOriginEvent {
+                    timestamp,
+                    event_tx_id,
+                    commit_oid,
+                    origin_type,
+                    source_oid,
+                }
+            }
+
             other => anyhow::bail!("Unknown event type {}", other),
         };
         Ok(event)
@@ -408,6 +634,41 @@ CREATE TABLE IF NOT EXISTS event_transactions (
     Ok(())
 }
 
+fn insert_event(conn: &rusqlite::Connection, event: Event) -> anyhow::Result<()> {
+    let Row {
+        timestamp,
+        type_,
+        event_tx_id,
+        ref1,
+        ref2,
+        ref_name,
+        message,
+    } = Row::from(event);
+    conn.execute_named(
+        "
+INSERT INTO event_log VALUES (
+    :timestamp,
+    :type,
+    :event_tx_id,
+    :old_ref,
+    :new_ref,
+    :ref_name,
+    :message
+)
+            ",
+        rusqlite::named_params! {
+            ":timestamp": timestamp,
+            ":type": &type_,
+            ":event_tx_id": event_tx_id,
+            ":old_ref": &ref1,
+            ":new_ref": &ref2,
+            ":ref_name": &ref_name,
+            ":message": &message,
+        },
+    )?;
+    Ok(())
+}
+
 impl<'conn> EventLogDb<'conn> {
     /// Constructor.
     #[context("Constructing `EventLogDb`")]
@@ -422,42 +683,27 @@ impl<'conn> EventLogDb<'conn> {
     /// * events: The events to add.
     #[context("Adding events to event-log")]
     pub fn add_events(&mut self, events: Vec<Event>) -> anyhow::Result<()> {
-        let tx = self.conn.unchecked_transaction()?;
-        for event in events {
-            let Row {
-                timestamp,
-                type_,
-                event_tx_id,
-                ref1,
-                ref2,
-                ref_name,
-                message,
-            } = Row::from(event);
-            tx.execute_named(
-                "
-INSERT INTO event_log VALUES (
-    :timestamp,
-    :type,
-    :event_tx_id,
-    :old_ref,
-    :new_ref,
-    :ref_name,
-    :message
-)
-            ",
-                rusqlite::named_params! {
-                    ":timestamp": timestamp,
-                    ":type": &type_,
-                    ":event_tx_id": event_tx_id,
-                    ":old_ref": &ref1,
-                    ":new_ref": &ref2,
-                    ":ref_name": &ref_name,
-                    ":message": &message,
-                },
-            )?;
-        }
-        tx.commit()?;
-        Ok(())
+        retry_on_db_busy(|| {
+            let tx = self.begin_write_transaction()?;
+            for event in events.iter() {
+                insert_event(&tx, event.clone())?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Start a transaction which takes the database's write lock immediately.
+    ///
+    /// A deferred transaction which starts by reading and later writes may
+    /// fail with a busy error if another process writes in the meantime, so
+    /// transactions which write should use this instead.
+    fn begin_write_transaction(&self) -> anyhow::Result<rusqlite::Transaction<'_>> {
+        let tx = rusqlite::Transaction::new_unchecked(
+            self.conn,
+            rusqlite::TransactionBehavior::Immediate,
+        )?;
+        Ok(tx)
     }
 
     /// Get all the events in the database.
@@ -502,6 +748,34 @@ ORDER BY rowid ASC
         rows.into_iter().map(Event::try_from).collect()
     }
 
+    /// Get the message associated with each event transaction, such as
+    /// `move` or `hook-post-commit`.
+    ///
+    /// Returns: A mapping from event transaction ID to its message.
+    #[context("Querying event transaction messages from `EventLogDb`")]
+    pub fn get_transaction_messages(&self) -> anyhow::Result<HashMap<EventTransactionId, String>> {
+        let mut stmt = self.conn.prepare(
+            "
+SELECT event_tx_id, message
+FROM event_transactions
+",
+        )?;
+        let rows: rusqlite::Result<Vec<(isize, Option<String>)>> = stmt
+            .query_map(rusqlite::params![], |row| {
+                let event_tx_id: isize = row.get("event_tx_id")?;
+                let message: Option<String> = row.get("message")?;
+                Ok((event_tx_id, message))
+            })?
+            .collect();
+        let messages = rows?
+            .into_iter()
+            .filter_map(|(event_tx_id, message)| {
+                message.map(|message| (EventTransactionId(event_tx_id), message))
+            })
+            .collect();
+        Ok(messages)
+    }
+
     /// Create a new event transaction ID to be used to insert subsequent
     /// `Event`s into the database.
     #[context("Creating a new `EventTransactionId`")]
@@ -516,14 +790,13 @@ ORDER BY rowid ASC
             }
         }
 
-        let tx = self.conn.unchecked_transaction()?;
-
         let timestamp = now
             .duration_since(SystemTime::UNIX_EPOCH)
             .with_context(|| format!("Calculating event transaction timestamp: {:?}", &now))?
             .as_secs_f64();
-        self.conn
-            .execute_named(
+        let event_tx_id = retry_on_db_busy(|| {
+            let tx = self.begin_write_transaction()?;
+            tx.execute_named(
                 "
             INSERT INTO event_transactions
             (timestamp, message)
@@ -543,13 +816,179 @@ ORDER BY rowid ASC
                 )
             })?;
 
-        // Ensure that we query `last_insert_rowid` in a transaction, in case
-        // there's another thread in this process making queries with the same
-        // SQLite connection.
-        let event_tx_id: isize = self.conn.last_insert_rowid().try_into()?;
-        tx.commit()?;
+            // Ensure that we query `last_insert_rowid` in a transaction, in case
+            // there's another thread in this process making queries with the same
+            // SQLite connection.
+            let event_tx_id: isize = tx.last_insert_rowid().try_into()?;
+            tx.commit()?;
+            Ok(event_tx_id)
+        })?;
         Ok(EventTransactionId(event_tx_id))
     }
+
+    /// Compact the events which happened before `horizon`, as per
+    /// `compact_events`.
+    ///
+    /// Args:
+    /// * `repo`: The Git repository. Used to determine which commits no longer
+    /// exist.
+    /// * `horizon`: Only events which happened before this time are compacted.
+    ///
+    /// Returns: The number of events which were removed from the event log.
+    #[context("Compacting events in `EventLogDb`")]
+    pub fn compact(
+        &mut self,
+        repo: &git2::Repository,
+        horizon: SystemTime,
+    ) -> anyhow::Result<usize> {
+        let tx = self.begin_write_transaction()?;
+        let events = self.get_events()?;
+        let num_events = events.len();
+        let events = compact_events(events, horizon, |oid| repo.find_commit(oid).is_ok());
+        let num_removed_events = num_events - events.len();
+        if num_removed_events == 0 {
+            return Ok(0);
+        }
+
+        // Rows are returned in `rowid` order, so re-inserting the compacted
+        // events into an empty table preserves their relative order.
+        tx.execute("DELETE FROM event_log", rusqlite::params![])
+            .context("Deleting old events")?;
+        for event in events {
+            insert_event(&tx, event)?;
+        }
+
+        let horizon_timestamp = horizon
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .with_context(|| format!("Calculating compaction horizon: {:?}", &horizon))?
+            .as_secs_f64();
+        tx.execute_named(
+            "
+DELETE FROM event_transactions
+WHERE timestamp < :horizon
+AND event_tx_id NOT IN (SELECT event_tx_id FROM event_log)
+",
+            rusqlite::named_params! {
+                ":horizon": horizon_timestamp,
+            },
+        )
+        .context("Deleting empty event transactions")?;
+
+        tx.commit()?;
+        Ok(num_removed_events)
+    }
+}
+
+/// Remove redundant events which happened before `horizon`.
+///
+/// For the events which happened before `horizon`, updates to references
+/// which we ignore are dropped, and each chain of `RefUpdateEvent`s on the same
+/// reference is collapsed into its last event, which is changed to move the
+/// reference from where the first event in the chain found it. Events for
+/// commits which no longer exist are dropped, except that a `RewriteEvent` is
+/// kept as long as either its old or new commit exists.
+///
+/// Replaying the compacted events with an `EventReplayer` produces the same
+/// visibility for each existing commit, and the same reference locations, as
+/// replaying the original events.
+///
+/// Args:
+/// * `events`: The events to compact, ordered from oldest to newest.
+/// * `horizon`: Only events which happened before this time are compacted.
+/// * `commit_exists`: Whether or not the given commit still exists.
+///
+/// Returns: The compacted events, ordered from oldest to newest.
+pub fn compact_events(
+    events: Vec<Event>,
+    horizon: SystemTime,
+    commit_exists: impl Fn(git2::Oid) -> bool,
+) -> Vec<Event> {
+    let is_compactable = |event: &Event| event.get_timestamp() < horizon;
+
+    // Map from ref name to the original referent of its chain of updates,
+    // and the index of the last update in the chain.
+    let mut ref_chains: HashMap<String, (Option<String>, usize)> = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        if let Event::RefUpdateEvent {
+            ref_name, old_ref, ..
+        } = event
+        {
+            if is_compactable(event) {
+                ref_chains
+                    .entry(ref_name.clone())
+                    .or_insert_with(|| (old_ref.clone(), i))
+                    .1 = i;
+            }
+        }
+    }
+
+    events
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, event)| {
+            if !is_compactable(&event) {
+                return Some(event);
+            }
+
+            match event {
+                Event::RefUpdateEvent {
+                    timestamp,
+                    event_tx_id,
+                    ref_name,
+                    old_ref: _,
+                    new_ref,
+                    message,
+                } => {
+                    if should_ignore_ref_updates(&ref_name) {
+                        return None;
+                    }
+                    let (chain_old_ref, last_index) = &ref_chains[&ref_name];
+                    if i != *last_index {
+                        return None;
+                    }
+                    match (chain_old_ref, new_ref) {
+                        // The reference didn't exist before the chain, and it
+                        // was deleted by the end of it.
+                        (None, None) => None,
+                        (chain_old_ref, new_ref) => Some(Event::RefUpdateEvent {
+                            timestamp,
+                            event_tx_id,
+                            old_ref: chain_old_ref.clone(),
+                            new_ref,
+                            ref_name,
+                            message,
+                        }),
+                    }
+                }
+
+                Event::RewriteEvent {
+                    old_commit_oid,
+                    new_commit_oid,
+                    ..
+                } => {
+                    if commit_exists(old_commit_oid) || commit_exists(new_commit_oid) {
+                        Some(event)
+                    } else {
+                        None
+                    }
+                }
+
+                Event::CommitEvent { commit_oid, .. }
+                | Event::HideEvent { commit_oid, .. }
+                | Event::UnhideEvent { commit_oid, .. }
+                | Event::WorkingCopySnapshotEvent { commit_oid, .. }
+                | Event::StashPushEvent { commit_oid, .. }
+                | Event::StashDropEvent { commit_oid, .. }
+                | Event::CommitOriginEvent { commit_oid, .. } => {
+                    if commit_exists(commit_oid) {
+                        Some(event)
+                    } else {
+                        None
+                    }
+                }
+            }
+        })
+        .collect()
 }
 
 /// Determine whether a given reference is used to keep a commit alive.
@@ -559,9 +998,22 @@ ORDER BY rowid ASC
 ///
 /// Returns: Whether or not the given reference is used internally to keep the
 /// commit alive, so that it's not collected by Git's garbage collection
-/// mechanism.
+/// mechanism. (The published obsolescence markers live in the same namespace,
+/// but aren't such a reference.)
 pub fn is_gc_ref(ref_name: &str) -> bool {
-    ref_name.starts_with("refs/branchless/")
+    ref_name.starts_with("refs/branchless/") && ref_name != OBSMARKERS_REF_NAME
+}
+
+/// The name under which updates to the main worktree's `HEAD` are replayed when
+/// the current worktree is a linked worktree. This is the same name that Git
+/// uses to refer to it.
+pub const MAIN_WORKTREE_HEAD_REF_NAME: &str = "main-worktree/HEAD";
+
+/// Determine whether the given reference, as replayed by `EventReplayer`, is
+/// the `HEAD` of a worktree other than the current one.
+pub fn is_other_worktree_head_ref(ref_name: &str) -> bool {
+    ref_name == MAIN_WORKTREE_HEAD_REF_NAME
+        || (ref_name.starts_with("worktrees/") && ref_name.ends_with("/HEAD"))
 }
 
 /// Determines whether or not updates to the given reference should be ignored.
@@ -571,13 +1023,21 @@ pub fn is_gc_ref(ref_name: &str) -> bool {
 ///
 /// Returns: Whether or not updates to the given reference should be ignored.
 pub fn should_ignore_ref_updates(ref_name: &str) -> bool {
-    if is_gc_ref(ref_name) {
+    if is_gc_ref(ref_name) || ref_name == OBSMARKERS_REF_NAME {
         return true;
     }
 
     matches!(
         ref_name,
-        "ORIG_HEAD" | "CHERRY_PICK" | "REBASE_HEAD" | "CHERRY_PICK_HEAD" | "FETCH_HEAD"
+        "ORIG_HEAD"
+            | "CHERRY_PICK"
+            | "REBASE_HEAD"
+            | "CHERRY_PICK_HEAD"
+            | "FETCH_HEAD"
+            // Updates to the stash list are recorded as `StashPushEvent`s and
+            // `StashDropEvent`s instead, since dropping an entry other than the
+            // most recent one doesn't update the ref at all.
+            | "refs/stash"
     )
 }
 
@@ -640,26 +1100,45 @@ pub struct EventReplayer {
     /// If an entry is not present, it was either never observed, or it most
     /// recently changed to point to the zero hash (i.e. it was deleted).
     ref_locations: HashMap<String, String>,
+
+    /// The commits which each commit was derived from, along with the ID of
+    /// the event which recorded it.
+    commit_origins: HashMap<git2::Oid, Vec<(isize, CommitOriginType, git2::Oid)>>,
+
+    /// The name under which updates to the current worktree's `HEAD` are
+    /// recorded. See `get_worktree_head_ref_name`.
+    head_ref_name: String,
 }
 
 impl EventReplayer {
-    fn new() -> Self {
+    fn new(head_ref_name: String) -> Self {
         EventReplayer {
             id_counter: 0,
             events: vec![],
             commit_history: HashMap::new(),
             ref_locations: HashMap::new(),
+            commit_origins: HashMap::new(),
+            head_ref_name,
         }
     }
 
     /// Construct the replayer from all the events in the database.
     ///
+    /// Updates to the current worktree's `HEAD` are replayed as updates to
+    /// `HEAD`, and updates to the `HEAD` of any other worktree are replayed
+    /// under the name that Git uses for it (`main-worktree/HEAD` or
+    /// `worktrees/<name>/HEAD`).
+    ///
     /// Args:
+    /// * `repo`: The Git repository, used to determine the current worktree.
     /// * `event_log_db`: The database to query events from.
     ///
     /// Returns: The constructed replayer.
-    pub fn from_event_log_db(event_log_db: &EventLogDb) -> anyhow::Result<Self> {
-        let mut result = EventReplayer::new();
+    pub fn from_event_log_db(
+        repo: &git2::Repository,
+        event_log_db: &EventLogDb,
+    ) -> anyhow::Result<Self> {
+        let mut result = EventReplayer::new(get_worktree_head_ref_name(repo)?);
         for event in event_log_db.get_events()? {
             result.process_event(&event);
         }
@@ -682,7 +1161,7 @@ impl EventReplayer {
             }
         }
 
-        let event = match self.fix_event_git_v2_31(event.clone()) {
+        let event = match self.fix_event_git_v2_31(self.fix_event_worktree(event.clone())) {
             None => {
                 return;
             }
@@ -775,9 +1254,62 @@ impl EventReplayer {
                     event: event.clone(),
                     event_classification: EventClassification::Show,
                 }),
+
+            // Snapshot and stash commits are internal bookkeeping, rather than
+            // commits which the user is working on, so they don't affect
+            // visibility.
+            Event::WorkingCopySnapshotEvent { .. }
+            | Event::StashPushEvent { .. }
+            | Event::StashDropEvent { .. } => {}
+
+            // The accompanying `CommitEvent` determines the visibility of the
+            // commit.
+            Event::CommitOriginEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                origin_type,
+                source_oid,
+            } => self.commit_origins.entry(*commit_oid).or_default().push((
+                id,
+                *origin_type,
+                *source_oid,
+            )),
         };
     }
 
+    /// Rename updates to worktree `HEAD`s relative to the current worktree. See
+    /// `from_event_log_db`.
+    fn fix_event_worktree(&self, event: Event) -> Event {
+        match event {
+            Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name,
+                old_ref,
+                new_ref,
+                message,
+            } => {
+                let ref_name = if ref_name == self.head_ref_name {
+                    "HEAD".to_string()
+                } else if ref_name == "HEAD" {
+                    MAIN_WORKTREE_HEAD_REF_NAME.to_string()
+                } else {
+                    ref_name
+                };
+                Event::RefUpdateEvent {
+                    timestamp,
+                    event_tx_id,
+                    ref_name,
+                    old_ref,
+                    new_ref,
+                    message,
+                }
+            }
+            event => event,
+        }
+    }
+
     /// See https://github.com/arxanas/git-branchless/issues/7.
     fn fix_event_git_v2_31(&self, event: Event) -> Option<Event> {
         let event = match event {
@@ -885,6 +1417,29 @@ impl EventReplayer {
         Some(&event_info.event)
     }
 
+    /// Get the commits which the given commit was derived from (by a
+    /// cherry-pick, revert, or merge), as of the cursor's point in time.
+    ///
+    /// Args:
+    /// * `oid`: The OID of the commit to check.
+    ///
+    /// Returns: The way in which the commit was derived and the OID of the
+    /// source commit, for each recorded source commit.
+    pub fn get_cursor_commit_origins(
+        &self,
+        cursor: EventCursor,
+        oid: git2::Oid,
+    ) -> Vec<(CommitOriginType, git2::Oid)> {
+        match self.commit_origins.get(&oid) {
+            None => vec![],
+            Some(origins) => origins
+                .iter()
+                .filter(|(id, _origin_type, _source_oid)| *id < cursor.event_id)
+                .map(|(_id, origin_type, source_oid)| (*origin_type, *source_oid))
+                .collect(),
+        }
+    }
+
     /// Get the OIDs which have activity according to the repository history.
     ///
     /// Returns: The set of OIDs referring to commits which are thought to be
@@ -1016,6 +1571,21 @@ impl EventReplayer {
     /// was never observed.
     pub fn get_cursor_head_oid(&self, cursor: EventCursor) -> Option<git2::Oid> {
         let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+
+        // Commits made in other worktrees don't move the current worktree's
+        // `HEAD`.
+        let other_worktree_head_oids: HashSet<git2::Oid> = self.events[0..cursor_event_id]
+            .iter()
+            .filter_map(|event| match event {
+                Event::RefUpdateEvent {
+                    ref_name,
+                    new_ref: Some(new_ref),
+                    ..
+                } if is_other_worktree_head_ref(ref_name) => git2::Oid::from_str(new_ref).ok(),
+                _ => None,
+            })
+            .collect();
+
         self.events[0..cursor_event_id]
             .iter()
             .rev()
@@ -1041,15 +1611,53 @@ impl EventReplayer {
                     // Not strictly necessary, but helps to compensate in case
                     // the user is not running Git v2.29 or above, and therefore
                     // doesn't have the corresponding `RefUpdateEvent`.
-                    Event::CommitEvent { commit_oid, .. } => Some(*commit_oid),
+                    Event::CommitEvent { commit_oid, .. }
+                        if !other_worktree_head_oids.contains(commit_oid) =>
+                    {
+                        Some(*commit_oid)
+                    }
 
-                    Event::RewriteEvent { .. }
+                    Event::CommitEvent { .. }
+                    | Event::RewriteEvent { .. }
                     | Event::HideEvent { .. }
-                    | Event::UnhideEvent { .. } => None,
+                    | Event::UnhideEvent { .. }
+                    | Event::WorkingCopySnapshotEvent { .. }
+                    | Event::StashPushEvent { .. }
+                    | Event::StashDropEvent { .. }
+                    | Event::CommitOriginEvent { .. } => None,
                 }
             })
     }
 
+    /// Get the OIDs of the `HEAD`s of worktrees other than the current one at
+    /// the cursor's point in time.
+    ///
+    /// Returns: A mapping from the name of each worktree's `HEAD` reference
+    /// (see `from_event_log_db`) to the OID it pointed to at that time.
+    /// Worktrees whose `HEAD` was never observed are omitted.
+    pub fn get_cursor_worktree_head_oids(&self, cursor: EventCursor) -> HashMap<String, git2::Oid> {
+        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        let mut result = HashMap::new();
+        for event in self.events[0..cursor_event_id].iter() {
+            if let Event::RefUpdateEvent {
+                ref_name, new_ref, ..
+            } = event
+            {
+                if !is_other_worktree_head_ref(ref_name) {
+                    continue;
+                }
+                match new_ref
+                    .as_ref()
+                    .and_then(|new_ref| git2::Oid::from_str(new_ref).ok())
+                {
+                    Some(oid) => result.insert(ref_name.clone(), oid),
+                    None => result.remove(ref_name),
+                };
+            }
+        }
+        result
+    }
+
     fn get_cursor_branch_oid(
         &self,
         cursor: EventCursor,
@@ -1202,6 +1810,15 @@ impl EventReplayer {
         }
     }
 
+    /// Get all the events that happened before the event cursor.
+    ///
+    /// Returns: An ordered list of events that happened before the event
+    /// cursor, from least recent to most recent.
+    pub fn get_events_before_cursor(&self, cursor: EventCursor) -> &[Event] {
+        let cursor_event_id: usize = cursor.event_id.try_into().unwrap();
+        &self.events[..cursor_event_id]
+    }
+
     /// Get all the events that have happened since the event cursor.
     ///
     /// Returns: An ordered list of events that have happened since the event
@@ -1239,6 +1856,18 @@ pub mod testing {
             Event::UnhideEvent {
                 ref mut timestamp, ..
             } => *timestamp = 0.0,
+            Event::WorkingCopySnapshotEvent {
+                ref mut timestamp, ..
+            } => *timestamp = 0.0,
+            Event::StashPushEvent {
+                ref mut timestamp, ..
+            } => *timestamp = 0.0,
+            Event::StashDropEvent {
+                ref mut timestamp, ..
+            } => *timestamp = 0.0,
+            Event::CommitOriginEvent {
+                ref mut timestamp, ..
+            } => *timestamp = 0.0,
         }
         event
     }
@@ -1265,7 +1894,7 @@ mod tests {
             event_tx_id,
             commit_oid: git2::Oid::from_str("abc")?,
         };
-        let mut replayer = EventReplayer::new();
+        let mut replayer = EventReplayer::new("HEAD".to_string());
         replayer.process_event(&meaningful_event);
         replayer.process_event(&Event::RefUpdateEvent {
             timestamp: 0.0,
@@ -1338,9 +1967,113 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_compact_events() -> anyhow::Result<()> {
+        let event_tx_id = make_dummy_transaction_id(123);
+        let existing_oid = git2::Oid::from_str("abc")?;
+        let missing_oid = git2::Oid::from_str("def")?;
+        let make_ref_update_event =
+            |timestamp: f64, old_ref: &str, new_ref: Option<&str>| Event::RefUpdateEvent {
+                timestamp,
+                event_tx_id,
+                ref_name: String::from("refs/heads/foo"),
+                old_ref: Some(String::from(old_ref)),
+                new_ref: new_ref.map(String::from),
+                message: None,
+            };
+        let events = vec![
+            make_ref_update_event(1.0, "1", Some("2")),
+            Event::CommitEvent {
+                timestamp: 2.0,
+                event_tx_id,
+                commit_oid: existing_oid,
+            },
+            Event::HideEvent {
+                timestamp: 3.0,
+                event_tx_id,
+                commit_oid: missing_oid,
+            },
+            Event::RefUpdateEvent {
+                timestamp: 4.0,
+                event_tx_id,
+                ref_name: String::from("ORIG_HEAD"),
+                old_ref: None,
+                new_ref: Some(String::from("1")),
+                message: None,
+            },
+            make_ref_update_event(5.0, "2", Some("3")),
+            make_ref_update_event(10.0, "3", Some("4")),
+            Event::UnhideEvent {
+                timestamp: 11.0,
+                event_tx_id,
+                commit_oid: missing_oid,
+            },
+        ];
+
+        let horizon = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        let events = compact_events(events, horizon, |oid| oid == existing_oid);
+        assert_eq!(
+            events,
+            vec![
+                Event::CommitEvent {
+                    timestamp: 2.0,
+                    event_tx_id,
+                    commit_oid: existing_oid,
+                },
+                make_ref_update_event(5.0, "1", Some("3")),
+                make_ref_update_event(10.0, "3", Some("4")),
+                Event::UnhideEvent {
+                    timestamp: 11.0,
+                    event_tx_id,
+                    commit_oid: missing_oid,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_worktree_head_events() -> anyhow::Result<()> {
+        let event_tx_id = make_dummy_transaction_id(123);
+        let main_oid = git2::Oid::from_str("1")?;
+        let worktree_oid = git2::Oid::from_str("2")?;
+        let other_worktree_oid = git2::Oid::from_str("3")?;
+        let make_head_event = |ref_name: &str, oid: git2::Oid| Event::RefUpdateEvent {
+            timestamp: 0.0,
+            event_tx_id,
+            ref_name: ref_name.to_string(),
+            old_ref: None,
+            new_ref: Some(oid.to_string()),
+            message: None,
+        };
+
+        let mut replayer = EventReplayer::new("worktrees/foo/HEAD".to_string());
+        replayer.process_event(&make_head_event("HEAD", main_oid));
+        replayer.process_event(&make_head_event("worktrees/foo/HEAD", worktree_oid));
+        replayer.process_event(&make_head_event("worktrees/bar/HEAD", other_worktree_oid));
+        replayer.process_event(&Event::CommitEvent {
+            timestamp: 0.0,
+            event_tx_id,
+            commit_oid: other_worktree_oid,
+        });
+
+        let cursor = replayer.make_default_cursor();
+        assert_eq!(replayer.get_cursor_head_oid(cursor), Some(worktree_oid));
+        assert_eq!(
+            replayer.get_cursor_worktree_head_oids(cursor),
+            vec![
+                (MAIN_WORKTREE_HEAD_REF_NAME.to_string(), main_oid),
+                ("worktrees/bar/HEAD".to_string(), other_worktree_oid),
+            ]
+            .into_iter()
+            .collect()
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_advance_cursor_by_transaction() -> anyhow::Result<()> {
-        let mut event_replayer = EventReplayer::new();
+        let mut event_replayer = EventReplayer::new("HEAD".to_string());
         for (timestamp, event_tx_id) in (0..).zip(&[1, 1, 2, 2, 3, 4]) {
             let timestamp: f64 = timestamp.try_into()?;
             event_replayer.process_event(&Event::UnhideEvent {
@@ -84,6 +84,12 @@ pub struct Glyphs {
     /// currently checked out. (This is an unusual situation.)
     pub commit_main_hidden_head: &'static str,
 
+    /// Cursor for an entry in the stash list.
+    pub commit_stash: &'static str,
+
+    /// Cursor for a commit which is checked out in another worktree.
+    pub commit_other_worktree_head: &'static str,
+
     /// Bullet-point character for a list of newline-separated items.
     pub bullet_point: &'static str,
 }
@@ -114,6 +120,8 @@ impl Glyphs {
             commit_main_head: "@",
             commit_main_hidden: "X",
             commit_main_hidden_head: "%",
+            commit_stash: "s",
+            commit_other_worktree_head: "w",
             bullet_point: "-",
         }
     }
@@ -134,6 +142,8 @@ impl Glyphs {
             commit_main_head: "◆",
             commit_main_hidden: "✕",
             commit_main_hidden_head: "❖",
+            commit_stash: "▣",
+            commit_other_worktree_head: "◎",
             bullet_point: "•",
         }
     }
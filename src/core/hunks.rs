@@ -0,0 +1,391 @@
+//! Select and apply individual hunks of the diff between two trees.
+//!
+//! libgit2 can only apply a diff to the working copy or the repository's
+//! index, so in order to produce a tree with only some of the hunks applied
+//! without touching either of those, the selected hunks are applied to the
+//! file contents by hand.
+
+use std::convert::{TryFrom, TryInto};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use fn_error_context::context;
+
+use crate::util::wrap_git_error;
+
+/// A line in a hunk.
+#[derive(Clone, Debug)]
+pub struct HunkLine {
+    /// The kind of line, as reported by libgit2: `' '` for context, `'+'` for
+    /// an added line, and `'-'` for a removed line.
+    pub origin: char,
+
+    /// The contents of the line, including its trailing newline (if any).
+    pub content: Vec<u8>,
+}
+
+/// A contiguous set of changes to a file.
+#[derive(Clone, Debug)]
+pub struct Hunk {
+    /// The `@@ -a,b +c,d @@` header of the hunk, or a description of the
+    /// change if it can't be displayed line-by-line (such as for binary files).
+    pub header: String,
+
+    /// The lines in the hunk. Empty if the hunk covers the whole file.
+    pub lines: Vec<HunkLine>,
+
+    /// The 1-based line number in the old file where the hunk starts. If the
+    /// hunk only adds lines, this is instead the line after which they're
+    /// added (0 if they're added at the start of the file).
+    pub old_start: usize,
+
+    /// The number of lines in the old file covered by the hunk.
+    pub old_lines: usize,
+}
+
+/// The changes to a single file.
+#[derive(Clone, Debug)]
+pub struct FileHunks {
+    /// The path to the file, relative to the root of the repository.
+    pub path: PathBuf,
+
+    /// The hunks which make up the changes to the file. There's always at
+    /// least one hunk. If a change can't be split up, such as a change to a
+    /// binary file, then there's exactly one hunk, which covers the whole file.
+    pub hunks: Vec<Hunk>,
+
+    old_oid: Option<git2::Oid>,
+    new_oid: Option<git2::Oid>,
+    old_mode: u32,
+    new_mode: u32,
+}
+
+/// Get the changes between two trees, split into hunks.
+///
+/// Args:
+/// * `old_tree`: The tree to compare against. If `None`, compares against the
+///   empty tree.
+/// * `new_tree`: The tree with the changes.
+/// * `context_lines`: The number of unchanged lines to include around each
+///   change. Changes which are closer together than this are combined into the
+///   same hunk.
+#[context("Getting hunks between trees")]
+pub fn get_hunks(
+    repo: &git2::Repository,
+    old_tree: Option<&git2::Tree>,
+    new_tree: &git2::Tree,
+    context_lines: u32,
+) -> anyhow::Result<Vec<FileHunks>> {
+    let diff = repo
+        .diff_tree_to_tree(
+            old_tree,
+            Some(new_tree),
+            Some(git2::DiffOptions::new().context_lines(context_lines)),
+        )
+        .map_err(wrap_git_error)?;
+    let mut result = Vec::new();
+    for (delta_idx, delta) in diff.deltas().enumerate() {
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path.to_owned(),
+            None => anyhow::bail!("Could not get path for diff delta: {:?}", delta),
+        };
+        let is_added = delta.status() == git2::Delta::Added;
+        let is_deleted = delta.status() == git2::Delta::Deleted;
+
+        let mut hunks = Vec::new();
+        if let Some(patch) = git2::Patch::from_diff(&diff, delta_idx).map_err(wrap_git_error)? {
+            for hunk_idx in 0..patch.num_hunks() {
+                let (hunk, num_lines) = patch.hunk(hunk_idx).map_err(wrap_git_error)?;
+                let lines = (0..num_lines)
+                    .map(|line_idx| {
+                        let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+                        Ok(HunkLine {
+                            origin: line.origin(),
+                            content: line.content().to_vec(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, git2::Error>>()
+                    .map_err(wrap_git_error)?
+                    .into_iter()
+                    // Skip the "no newline at end of file" markers, since the
+                    // line contents already reflect that.
+                    .filter(|line| matches!(line.origin, ' ' | '+' | '-'))
+                    .collect();
+                hunks.push(Hunk {
+                    header: String::from_utf8_lossy(hunk.header())
+                        .trim_end()
+                        .to_string(),
+                    lines,
+                    old_start: hunk.old_start().try_into()?,
+                    old_lines: hunk.old_lines().try_into()?,
+                });
+            }
+        }
+        if hunks.is_empty() {
+            let header = if delta.flags().is_binary() {
+                "Binary file changed"
+            } else if is_added {
+                "Empty file added"
+            } else if is_deleted {
+                "Empty file deleted"
+            } else {
+                "File mode changed"
+            };
+            hunks.push(Hunk {
+                header: header.to_string(),
+                lines: Vec::new(),
+                old_start: 0,
+                old_lines: 0,
+            });
+        }
+
+        result.push(FileHunks {
+            path,
+            hunks,
+            old_oid: if is_added {
+                None
+            } else {
+                Some(delta.old_file().id())
+            },
+            new_oid: if is_deleted {
+                None
+            } else {
+                Some(delta.new_file().id())
+            },
+            old_mode: u32::try_from(i32::from(delta.old_file().mode()))?,
+            new_mode: u32::try_from(i32::from(delta.new_file().mode()))?,
+        });
+    }
+    Ok(result)
+}
+
+/// Apply the selected hunks of a file to its old contents. The hunks must be
+/// sorted by `old_start` and must not overlap.
+pub fn apply_file_hunks(
+    old_content: &[u8],
+    hunks: &[Hunk],
+    is_hunk_selected: impl Fn(usize) -> bool,
+) -> anyhow::Result<Vec<u8>> {
+    let old_lines: Vec<&[u8]> = old_content.split_inclusive(|c| *c == b'\n').collect();
+    let mut result = Vec::new();
+    let mut old_idx = 0;
+    for (hunk_idx, hunk) in hunks.iter().enumerate() {
+        if !is_hunk_selected(hunk_idx) {
+            continue;
+        }
+
+        // For a hunk which only adds lines, `old_start` is the line after which
+        // they're added, rather than the first line which the hunk replaces.
+        let start = if hunk.old_lines == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start - 1
+        };
+        if start < old_idx || start > old_lines.len() {
+            anyhow::bail!(
+                "BUG: hunk {:?} is out of order or out of bounds",
+                hunk.header
+            );
+        }
+        for line in &old_lines[old_idx..start] {
+            result.extend_from_slice(line);
+        }
+        old_idx = start;
+
+        for line in hunk.lines.iter() {
+            match line.origin {
+                ' ' => {
+                    if let Some(old_line) = old_lines.get(old_idx) {
+                        result.extend_from_slice(old_line);
+                    }
+                    old_idx += 1;
+                }
+                '-' => old_idx += 1,
+                '+' => result.extend_from_slice(&line.content),
+                _ => {}
+            }
+        }
+    }
+    for line in old_lines.iter().skip(old_idx) {
+        result.extend_from_slice(line);
+    }
+    Ok(result)
+}
+
+/// Make an index entry for adding the blob `oid` at `path` to an in-memory
+/// index.
+pub fn make_index_entry(
+    path: &Path,
+    mode: u32,
+    oid: git2::Oid,
+) -> anyhow::Result<git2::IndexEntry> {
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => anyhow::bail!("Invalid UTF-8 path: {:?}", path),
+    };
+    Ok(git2::IndexEntry {
+        ctime: git2::IndexTime::new(0, 0),
+        mtime: git2::IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id: oid,
+        flags: 0,
+        flags_extended: 0,
+        path: path.as_bytes().to_vec(),
+    })
+}
+
+/// Apply the selected hunks to `old_tree`.
+///
+/// Args:
+/// * `old_tree`: The tree which the hunks were computed against (see
+///   `get_hunks`).
+/// * `file_hunks`: The changes to apply.
+/// * `is_hunk_selected`: Given the index of a file in `file_hunks` and the
+///   index of a hunk in that file, returns whether the hunk should be applied.
+///
+/// Returns: The OID of the resulting tree.
+#[context("Applying selected hunks")]
+pub fn apply_hunks(
+    repo: &git2::Repository,
+    old_tree: Option<&git2::Tree>,
+    file_hunks: &[FileHunks],
+    is_hunk_selected: impl Fn(usize, usize) -> bool,
+) -> anyhow::Result<git2::Oid> {
+    let mut index = git2::Index::new().map_err(wrap_git_error)?;
+    if let Some(old_tree) = old_tree {
+        index.read_tree(old_tree).map_err(wrap_git_error)?;
+    }
+
+    for (file_idx, file) in file_hunks.iter().enumerate() {
+        let num_selected = (0..file.hunks.len())
+            .filter(|hunk_idx| is_hunk_selected(file_idx, *hunk_idx))
+            .count();
+        if num_selected == 0 {
+            continue;
+        }
+
+        let (mode, oid) = if num_selected == file.hunks.len() {
+            match file.new_oid {
+                Some(new_oid) => (file.new_mode, new_oid),
+                None => {
+                    index.remove_path(&file.path).map_err(wrap_git_error)?;
+                    continue;
+                }
+            }
+        } else {
+            let old_content = match file.old_oid {
+                Some(old_oid) => repo
+                    .find_blob(old_oid)
+                    .map_err(wrap_git_error)?
+                    .content()
+                    .to_vec(),
+                None => Vec::new(),
+            };
+            let new_content = apply_file_hunks(&old_content, &file.hunks, |hunk_idx| {
+                is_hunk_selected(file_idx, hunk_idx)
+            })
+            .with_context(|| format!("Applying hunks to file: {:?}", file.path))?;
+            let mode = if file.old_oid.is_some() {
+                file.old_mode
+            } else {
+                file.new_mode
+            };
+            (mode, repo.blob(&new_content).map_err(wrap_git_error)?)
+        };
+        index
+            .add(&make_index_entry(&file.path, mode, oid)?)
+            .map_err(wrap_git_error)?;
+    }
+
+    let tree_oid = index.write_tree_to(repo).map_err(wrap_git_error)?;
+    Ok(tree_oid)
+}
+
+/// Apply hunks to the files in `tree`. Unlike `apply_hunks`, the hunks may have
+/// been computed against a different version of each file, as long as their
+/// line numbers have been adjusted to match the version in `tree`.
+///
+/// Args:
+/// * `tree`: The tree to apply the hunks to. It must contain each of the
+///   files being changed.
+/// * `file_hunks`: The paths of the files to change, each paired with the
+///   hunks to apply to it, sorted by `old_start`.
+///
+/// Returns: The OID of the resulting tree.
+#[context("Applying hunks to tree {:?}", tree.id())]
+pub fn apply_hunks_to_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    file_hunks: &[(PathBuf, Vec<Hunk>)],
+) -> anyhow::Result<git2::Oid> {
+    let mut index = git2::Index::new().map_err(wrap_git_error)?;
+    index.read_tree(tree).map_err(wrap_git_error)?;
+    for (path, hunks) in file_hunks {
+        let entry = tree
+            .get_path(path)
+            .with_context(|| format!("Looking up file in tree: {:?}", path))?;
+        let old_content = repo
+            .find_blob(entry.id())
+            .map_err(wrap_git_error)?
+            .content()
+            .to_vec();
+        let new_content = apply_file_hunks(&old_content, hunks, |_hunk_idx| true)
+            .with_context(|| format!("Applying hunks to file: {:?}", path))?;
+        let oid = repo.blob(&new_content).map_err(wrap_git_error)?;
+        let mode = u32::try_from(entry.filemode())?;
+        index
+            .add(&make_index_entry(path, mode, oid)?)
+            .map_err(wrap_git_error)?;
+    }
+
+    let tree_oid = index.write_tree_to(repo).map_err(wrap_git_error)?;
+    Ok(tree_oid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_hunk(old_start: usize, old_lines: usize, lines: &[&str]) -> Hunk {
+        Hunk {
+            header: String::new(),
+            lines: lines
+                .iter()
+                .map(|line| HunkLine {
+                    origin: line.chars().next().unwrap(),
+                    content: line[1..].as_bytes().to_vec(),
+                })
+                .collect(),
+            old_start,
+            old_lines,
+        }
+    }
+
+    #[test]
+    fn test_apply_file_hunks() -> anyhow::Result<()> {
+        let old_content = b"a\nb\nc\nd\ne\n";
+        let hunks = vec![
+            make_hunk(1, 1, &["-a\n", "+A\n"]),
+            make_hunk(3, 0, &["+inserted\n"]),
+            make_hunk(5, 1, &["-e\n"]),
+        ];
+
+        let apply = |selected: &[usize]| -> anyhow::Result<String> {
+            let content =
+                apply_file_hunks(old_content, &hunks, |hunk_idx| selected.contains(&hunk_idx))?;
+            Ok(String::from_utf8(content)?)
+        };
+        assert_eq!(apply(&[])?, "a\nb\nc\nd\ne\n");
+        assert_eq!(apply(&[0])?, "A\nb\nc\nd\ne\n");
+        assert_eq!(apply(&[1])?, "a\nb\nc\ninserted\nd\ne\n");
+        assert_eq!(apply(&[0, 2])?, "A\nb\nc\nd\n");
+        assert_eq!(apply(&[0, 1, 2])?, "A\nb\nc\ninserted\nd\n");
+
+        Ok(())
+    }
+}
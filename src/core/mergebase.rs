@@ -14,6 +14,9 @@
 //! take a while. It can also happen when simply checking out an old commit to
 //! examine it.
 
+use std::convert::TryInto;
+use std::time::SystemTime;
+
 use anyhow::Context;
 use fn_error_context::context;
 use rusqlite::OptionalExtension;
@@ -33,15 +36,45 @@ CREATE TABLE IF NOT EXISTS merge_base_oids (
     lhs_oid TEXT NOT NULL,
     rhs_oid TEXT NOT NULL,
     merge_base_oid TEXT,
+    last_accessed REAL,
     UNIQUE (lhs_oid, rhs_oid)
 )
 ",
         rusqlite::params![],
     )
     .context("Creating tables")?;
+
+    // Databases created by older versions don't have the `last_accessed`
+    // column. Entries without an access time are evicted first.
+    let has_last_accessed_column = conn
+        .prepare("PRAGMA table_info(merge_base_oids)")?
+        .query_map(rusqlite::params![], |row| row.get::<_, String>("name"))?
+        .collect::<rusqlite::Result<Vec<String>>>()?
+        .iter()
+        .any(|column_name| column_name == "last_accessed");
+    if !has_last_accessed_column {
+        conn.execute(
+            "ALTER TABLE merge_base_oids ADD COLUMN last_accessed REAL",
+            rusqlite::params![],
+        )
+        .context("Adding `last_accessed` column")?;
+    }
     Ok(())
 }
 
+/// How old an entry's access time must be before a cache hit updates it. The
+/// access time is only used to decide which entries to evict, so it doesn't
+/// need to be precise.
+const LAST_ACCESSED_UPDATE_INTERVAL_SECS: f64 = 24.0 * 60.0 * 60.0;
+
+fn get_now_timestamp() -> anyhow::Result<f64> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .context("Calculating current timestamp")?
+        .as_secs_f64();
+    Ok(timestamp)
+}
+
 impl<'conn> MergeBaseDb<'conn> {
     /// Constructor.
     #[context("Constructing `MergeBaseDb`")]
@@ -75,11 +108,11 @@ impl<'conn> MergeBaseDb<'conn> {
             (rhs_oid, lhs_oid)
         };
 
-        let merge_base_oid: Option<Option<String>> = self
+        let cached: Option<(Option<String>, Option<f64>)> = self
             .conn
             .query_row_named(
                 "
-SELECT merge_base_oid
+SELECT merge_base_oid, last_accessed
 FROM merge_base_oids
 WHERE lhs_oid = :lhs_oid
   AND rhs_oid = :rhs_oid
@@ -88,11 +121,42 @@ WHERE lhs_oid = :lhs_oid
                     ":lhs_oid": lhs_oid.to_string(),
                     ":rhs_oid": rhs_oid.to_string(),
                 },
-                |row| row.get("merge_base_oid"),
+                |row| Ok((row.get("merge_base_oid")?, row.get("last_accessed")?)),
             )
             .optional()
             .context("Querying merge-base DB")?;
 
+        // Only update the access time if it's stale, so that cache hits don't
+        // each require a write to the database.
+        let merge_base_oid = match cached {
+            Some((merge_base_oid, last_accessed)) => {
+                let now = get_now_timestamp()?;
+                let is_stale = match last_accessed {
+                    Some(last_accessed) => now - last_accessed > LAST_ACCESSED_UPDATE_INTERVAL_SECS,
+                    None => true,
+                };
+                if is_stale {
+                    self.conn
+                        .execute_named(
+                            "
+UPDATE merge_base_oids
+SET last_accessed = :last_accessed
+WHERE lhs_oid = :lhs_oid
+  AND rhs_oid = :rhs_oid
+",
+                            rusqlite::named_params! {
+                                ":lhs_oid": lhs_oid.to_string(),
+                                ":rhs_oid": rhs_oid.to_string(),
+                                ":last_accessed": now,
+                            },
+                        )
+                        .context("Updating merge-base access time")?;
+                }
+                Some(merge_base_oid)
+            }
+            None => None,
+        };
+
         match merge_base_oid {
             // Cached and non-NULL.
             Some(Some(merge_base_oid)) => {
@@ -122,15 +186,16 @@ WHERE lhs_oid = :lhs_oid
                 self.conn
                     .execute_named(
                         "
-INSERT INTO merge_base_oids VALUES (
-    :lhs_oid,
-    :rhs_oid,
-    :merge_base_oid
-)",
+INSERT INTO merge_base_oids
+    (lhs_oid, rhs_oid, merge_base_oid, last_accessed)
+VALUES
+    (:lhs_oid, :rhs_oid, :merge_base_oid, :last_accessed)
+",
                         rusqlite::named_params! {
                             ":lhs_oid": &lhs_oid.to_string(),
                             ":rhs_oid": &rhs_oid.to_string(),
                             ":merge_base_oid": &merge_base_oid.map(|oid| oid.to_string()),
+                            ":last_accessed": get_now_timestamp()?,
                         },
                     )
                     .context("Caching merge-base OID")?;
@@ -139,4 +204,142 @@ INSERT INTO merge_base_oids VALUES (
             }
         }
     }
+
+    /// Get the number of merge-base queries stored in the cache.
+    #[context("Counting entries in `MergeBaseDb`")]
+    pub fn get_num_entries(&self) -> anyhow::Result<usize> {
+        let num_entries: i64 = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM merge_base_oids",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .context("Querying merge-base DB")?;
+        let num_entries = num_entries.try_into()?;
+        Ok(num_entries)
+    }
+
+    /// Find the cached merge-base queries which refer to commits that no
+    /// longer exist in the repository.
+    fn find_missing_entries(
+        &self,
+        repo: &git2::Repository,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let odb = repo.odb().map_err(wrap_git_error)?;
+        let oid_exists = |oid: &str| match git2::Oid::from_str(oid) {
+            Ok(oid) => odb.exists(oid),
+            Err(_) => false,
+        };
+
+        let entries: Vec<(String, String, Option<String>)> = self
+            .conn
+            .prepare("SELECT lhs_oid, rhs_oid, merge_base_oid FROM merge_base_oids")?
+            .query_map(rusqlite::params![], |row| {
+                Ok((
+                    row.get("lhs_oid")?,
+                    row.get("rhs_oid")?,
+                    row.get("merge_base_oid")?,
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .context("Querying merge-base DB")?;
+
+        let missing_entries = entries
+            .into_iter()
+            .filter(|(lhs_oid, rhs_oid, merge_base_oid)| {
+                let is_merge_base_missing = match merge_base_oid {
+                    Some(merge_base_oid) => !oid_exists(merge_base_oid),
+                    None => false,
+                };
+                !oid_exists(lhs_oid) || !oid_exists(rhs_oid) || is_merge_base_missing
+            })
+            .map(|(lhs_oid, rhs_oid, _merge_base_oid)| (lhs_oid, rhs_oid))
+            .collect();
+        Ok(missing_entries)
+    }
+
+    /// Get the number of cached merge-base queries which refer to commits that
+    /// no longer exist in the repository, without removing them.
+    ///
+    /// Args:
+    /// * `repo`: The Git repo.
+    #[context("Counting entries for missing commits in `MergeBaseDb`")]
+    pub fn get_num_missing_entries(&self, repo: &git2::Repository) -> anyhow::Result<usize> {
+        Ok(self.find_missing_entries(repo)?.len())
+    }
+
+    /// Remove cached merge-base queries which refer to commits that no longer
+    /// exist in the repository (e.g. because they were garbage-collected).
+    ///
+    /// Args:
+    /// * `repo`: The Git repo.
+    ///
+    /// Returns: The number of entries which were removed.
+    #[context("Removing entries for missing commits from `MergeBaseDb`")]
+    pub fn remove_missing_entries(&self, repo: &git2::Repository) -> anyhow::Result<usize> {
+        let missing_entries = self.find_missing_entries(repo)?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut num_removed_entries = 0;
+        for (lhs_oid, rhs_oid) in missing_entries {
+            num_removed_entries += tx
+                .execute_named(
+                    "
+DELETE FROM merge_base_oids
+WHERE lhs_oid = :lhs_oid
+  AND rhs_oid = :rhs_oid
+",
+                    rusqlite::named_params! {
+                        ":lhs_oid": lhs_oid,
+                        ":rhs_oid": rhs_oid,
+                    },
+                )
+                .context("Deleting merge-base entry")?;
+        }
+        tx.commit()?;
+        Ok(num_removed_entries)
+    }
+
+    /// Evict the least-recently-used cached merge-base queries until at most
+    /// `max_entries` entries remain.
+    ///
+    /// Returns: The number of entries which were removed.
+    #[context("Evicting least-recently-used entries from `MergeBaseDb`")]
+    pub fn evict_least_recently_used(&self, max_entries: usize) -> anyhow::Result<usize> {
+        let num_entries = self.get_num_entries()?;
+        if num_entries <= max_entries {
+            return Ok(0);
+        }
+        let num_entries_to_remove: i64 = (num_entries - max_entries).try_into()?;
+        let num_removed_entries = self
+            .conn
+            .execute_named(
+                "
+DELETE FROM merge_base_oids
+WHERE rowid IN (
+    SELECT rowid
+    FROM merge_base_oids
+    ORDER BY last_accessed IS NOT NULL, last_accessed ASC
+    LIMIT :num_entries
+)
+",
+                rusqlite::named_params! {
+                    ":num_entries": num_entries_to_remove,
+                },
+            )
+            .context("Deleting merge-base entries")?;
+        Ok(num_removed_entries)
+    }
+
+    /// Remove all cached merge-base queries.
+    ///
+    /// Returns: The number of entries which were removed.
+    #[context("Clearing `MergeBaseDb`")]
+    pub fn clear(&self) -> anyhow::Result<usize> {
+        let num_removed_entries = self
+            .conn
+            .execute("DELETE FROM merge_base_oids", rusqlite::params![])
+            .context("Deleting merge-base entries")?;
+        Ok(num_removed_entries)
+    }
 }
@@ -20,7 +20,7 @@ use crate::core::config::{
     get_commit_metadata_relative_time,
 };
 
-use super::eventlog::{Event, EventCursor, EventReplayer};
+use super::eventlog::{CommitOriginType, Event, EventCursor, EventReplayer};
 use super::formatting::StyledStringBuilder;
 use super::graph::CommitGraph;
 use super::rewrite::find_rewrite_target;
@@ -149,12 +149,64 @@ impl<'a> CommitMetadataProvider for HiddenExplanationProvider<'a> {
 
             Event::RefUpdateEvent { .. }
             | Event::CommitEvent { .. }
-            | Event::UnhideEvent { .. } => None,
+            | Event::UnhideEvent { .. }
+            | Event::WorkingCopySnapshotEvent { .. }
+            | Event::StashPushEvent { .. }
+            | Event::StashDropEvent { .. }
+            | Event::CommitOriginEvent { .. } => None,
         };
         Ok(result)
     }
 }
 
+/// Display the commits which a commit was cherry-picked from, reverted, or
+/// merged in.
+pub struct CommitOriginProvider<'a> {
+    event_replayer: &'a EventReplayer,
+    event_cursor: EventCursor,
+}
+
+impl<'a> CommitOriginProvider<'a> {
+    /// Constructor.
+    pub fn new(
+        event_replayer: &'a EventReplayer,
+        event_cursor: EventCursor,
+    ) -> anyhow::Result<Self> {
+        Ok(CommitOriginProvider {
+            event_replayer,
+            event_cursor,
+        })
+    }
+}
+
+impl<'a> CommitMetadataProvider for CommitOriginProvider<'a> {
+    #[context("Providing origin metadata for commit {:?}", commit.id())]
+    fn describe_commit(&mut self, commit: &git2::Commit) -> anyhow::Result<Option<StyledString>> {
+        let origins = self
+            .event_replayer
+            .get_cursor_commit_origins(self.event_cursor, commit.id());
+        if origins.is_empty() {
+            return Ok(None);
+        }
+
+        let descriptions: Vec<String> = origins
+            .into_iter()
+            .map(|(origin_type, source_oid)| {
+                let verb = match origin_type {
+                    CommitOriginType::CherryPick => "cherry-picked from",
+                    CommitOriginType::Revert => "reverts",
+                    CommitOriginType::Merge => "merges",
+                };
+                format!("{} {}", verb, &source_oid.to_string()[..8])
+            })
+            .collect();
+        Ok(Some(StyledString::styled(
+            format!("({})", descriptions.join(", ")),
+            BaseColor::Black.light(),
+        )))
+    }
+}
+
 /// Display branches that point to a given commit.
 pub struct BranchesProvider<'a> {
     is_enabled: bool,
@@ -0,0 +1,312 @@
+//! Share obsolescence markers between clones of a repository.
+//!
+//! Rewrites and hides are only recorded in the local event log, so someone who
+//! fetches our commits after we've amended them would see both the old and the
+//! new versions in their smartlog. To avoid this, the obsolescence markers for
+//! a set of commits can be written to a commit stored at `OBSMARKERS_REF_NAME`.
+//! That reference can be pushed and fetched like any other, and the markers it
+//! contains can then be ingested into the event log of another clone.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use fn_error_context::context;
+
+use crate::core::eventlog::{Event, EventCursor, EventReplayer, EventTransactionId};
+use crate::util::wrap_git_error;
+
+/// The name of the reference which stores the published obsolescence markers.
+pub const OBSMARKERS_REF_NAME: &str = "refs/branchless/obsmarkers";
+
+/// The prefix of the references which store obsolescence markers fetched from
+/// remotes. Markers fetched into a reference under this prefix are ingested
+/// into the event log automatically by the `reference-transaction` hook, so
+/// adding a fetch refspec such as
+/// `+refs/branchless/obsmarkers:refs/branchless/remote-obsmarkers/origin`
+/// causes `git fetch` to ingest them.
+pub const REMOTE_OBSMARKERS_REF_PREFIX: &str = "refs/branchless/remote-obsmarkers/";
+
+/// The name of the file in the obsolescence marker commit's tree which lists
+/// the markers, one per line.
+const OBSMARKERS_FILE_NAME: &str = "obsmarkers";
+
+/// A record that a commit is obsolete.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObsMarker {
+    /// The commit was rewritten into another commit.
+    Rewrite {
+        /// The OID of the obsolete commit.
+        old_commit_oid: git2::Oid,
+
+        /// The OID of the commit which replaced it.
+        new_commit_oid: git2::Oid,
+    },
+
+    /// The commit was hidden.
+    Hide {
+        /// The OID of the obsolete commit.
+        commit_oid: git2::Oid,
+    },
+}
+
+impl ObsMarker {
+    /// Get the OID of the commit which this marker makes obsolete.
+    pub fn get_obsolete_oid(&self) -> git2::Oid {
+        match self {
+            ObsMarker::Rewrite { old_commit_oid, .. } => *old_commit_oid,
+            ObsMarker::Hide { commit_oid } => *commit_oid,
+        }
+    }
+
+    /// Convert this marker into an event to be recorded in the event log.
+    pub fn into_event(self, timestamp: f64, event_tx_id: EventTransactionId) -> Event {
+        match self {
+            ObsMarker::Rewrite {
+                old_commit_oid,
+                new_commit_oid,
+            } => Event::RewriteEvent {
+                timestamp,
+                event_tx_id,
+                old_commit_oid,
+                new_commit_oid,
+            },
+            ObsMarker::Hide { commit_oid } => Event::HideEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+            },
+        }
+    }
+}
+
+impl Display for ObsMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObsMarker::Rewrite {
+                old_commit_oid,
+                new_commit_oid,
+            } => write!(f, "rewrite {} {}", old_commit_oid, new_commit_oid),
+            ObsMarker::Hide { commit_oid } => write!(f, "hide {}", commit_oid),
+        }
+    }
+}
+
+impl FromStr for ObsMarker {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let obsmarker = match fields.as_slice() {
+            ["rewrite", old_commit_oid, new_commit_oid] => ObsMarker::Rewrite {
+                old_commit_oid: old_commit_oid.parse()?,
+                new_commit_oid: new_commit_oid.parse()?,
+            },
+            ["hide", commit_oid] => ObsMarker::Hide {
+                commit_oid: commit_oid.parse()?,
+            },
+            _ => anyhow::bail!("Invalid obsolescence marker: {:?}", s),
+        };
+        Ok(obsmarker)
+    }
+}
+
+/// Get the obsolescence markers for the given commits, as of the cursor's
+/// point in time.
+///
+/// Args:
+/// * `commit_oids`: The commits to get markers for. Commits which aren't
+///   obsolete don't produce a marker.
+///
+/// Returns: The markers, in the same order as the provided commits.
+pub fn get_obsmarkers(
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    commit_oids: impl IntoIterator<Item = git2::Oid>,
+) -> Vec<ObsMarker> {
+    commit_oids
+        .into_iter()
+        .filter_map(|commit_oid| {
+            match event_replayer.get_cursor_commit_latest_event(event_cursor, commit_oid)? {
+                Event::RewriteEvent {
+                    timestamp: _,
+                    event_tx_id: _,
+                    old_commit_oid,
+                    new_commit_oid,
+                } if *old_commit_oid == commit_oid && *new_commit_oid != commit_oid => {
+                    Some(ObsMarker::Rewrite {
+                        old_commit_oid: *old_commit_oid,
+                        new_commit_oid: *new_commit_oid,
+                    })
+                }
+                Event::HideEvent { .. } => Some(ObsMarker::Hide { commit_oid }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Read the obsolescence markers stored in the given commit.
+#[context("Reading obsolescence markers from commit {:?}", commit.id())]
+pub fn read_obsmarkers(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+) -> anyhow::Result<Vec<ObsMarker>> {
+    let tree = commit.tree().map_err(wrap_git_error)?;
+    let tree_entry = match tree.get_name(OBSMARKERS_FILE_NAME) {
+        Some(tree_entry) => tree_entry,
+        None => return Ok(Vec::new()),
+    };
+    let blob = repo.find_blob(tree_entry.id()).map_err(wrap_git_error)?;
+    std::str::from_utf8(blob.content())?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(ObsMarker::from_str)
+        .collect()
+}
+
+/// Add the given obsolescence markers to the ones stored at
+/// `OBSMARKERS_REF_NAME`.
+///
+/// A new commit is created on top of the existing one (if any), so that the
+/// reference only ever moves forward, and can be pushed without forcing.
+///
+/// Returns: The number of markers which weren't already stored.
+#[context("Writing obsolescence markers")]
+pub fn write_obsmarkers(
+    repo: &git2::Repository,
+    obsmarkers: &[ObsMarker],
+) -> anyhow::Result<usize> {
+    let parent_commit = match repo.refname_to_id(OBSMARKERS_REF_NAME) {
+        Ok(oid) => Some(repo.find_commit(oid).map_err(wrap_git_error)?),
+        Err(_) => None,
+    };
+    let mut all_obsmarkers = match &parent_commit {
+        Some(parent_commit) => read_obsmarkers(repo, parent_commit)?,
+        None => Vec::new(),
+    };
+    let mut seen_obsmarkers: HashSet<ObsMarker> = all_obsmarkers.iter().copied().collect();
+    let num_existing_obsmarkers = all_obsmarkers.len();
+    for obsmarker in obsmarkers {
+        if seen_obsmarkers.insert(*obsmarker) {
+            all_obsmarkers.push(*obsmarker);
+        }
+    }
+    let num_new_obsmarkers = all_obsmarkers.len() - num_existing_obsmarkers;
+    if num_new_obsmarkers == 0 {
+        return Ok(0);
+    }
+
+    let contents: String = all_obsmarkers
+        .iter()
+        .map(|obsmarker| format!("{}\n", obsmarker))
+        .collect();
+    let blob_oid = repo.blob(contents.as_bytes()).map_err(wrap_git_error)?;
+    let mut tree_builder = repo.treebuilder(None).map_err(wrap_git_error)?;
+    tree_builder
+        .insert(OBSMARKERS_FILE_NAME, blob_oid, git2::FileMode::Blob.into())
+        .map_err(wrap_git_error)?;
+    let tree_oid = tree_builder.write().map_err(wrap_git_error)?;
+    let tree = repo.find_tree(tree_oid).map_err(wrap_git_error)?;
+
+    let signature = repo.signature().map_err(wrap_git_error)?;
+    let parents: Vec<&git2::Commit> = parent_commit.iter().collect();
+    repo.commit(
+        Some(OBSMARKERS_REF_NAME),
+        &signature,
+        &signature,
+        &format!(
+            "branchless: publish {} obsolescence markers",
+            num_new_obsmarkers
+        ),
+        &tree,
+        &parents,
+    )
+    .map_err(wrap_git_error)?;
+    Ok(num_new_obsmarkers)
+}
+
+/// The obsolescence markers which should be recorded in the event log, as
+/// determined by `find_new_obsmarkers`.
+#[derive(Debug)]
+pub struct NewObsMarkers {
+    /// The markers which aren't yet reflected in the event log.
+    pub obsmarkers: Vec<ObsMarker>,
+
+    /// The rewrite markers which were skipped because the commit they were
+    /// rewritten into doesn't exist in the repository. Recording them would
+    /// cause commits to be restacked onto a missing commit, so they should be
+    /// ingested again once that commit has been fetched.
+    pub missing_obsmarkers: Vec<ObsMarker>,
+}
+
+/// Find the obsolescence markers which aren't yet reflected in the event log.
+///
+/// Markers for commits which don't exist in the repository are skipped, since
+/// there's nothing to hide. Markers for commits which have ever been recorded
+/// as obsolete are also skipped, even if they've since been unhidden, so that
+/// ingesting the same markers again doesn't undo a local unhide or override a
+/// local rewrite.
+pub fn find_new_obsmarkers(
+    repo: &git2::Repository,
+    event_replayer: &EventReplayer,
+    event_cursor: EventCursor,
+    obsmarkers: Vec<ObsMarker>,
+) -> NewObsMarkers {
+    let recorded_oids: HashSet<git2::Oid> = event_replayer
+        .get_events_before_cursor(event_cursor)
+        .iter()
+        .filter_map(|event| match event {
+            Event::RewriteEvent {
+                old_commit_oid,
+                new_commit_oid,
+                ..
+            } if old_commit_oid != new_commit_oid => Some(*old_commit_oid),
+            Event::HideEvent { commit_oid, .. } => Some(*commit_oid),
+            _ => None,
+        })
+        .collect();
+    let (obsmarkers, missing_obsmarkers) = obsmarkers
+        .into_iter()
+        .filter(|obsmarker| {
+            let obsolete_oid = obsmarker.get_obsolete_oid();
+            repo.find_commit(obsolete_oid).is_ok() && !recorded_oids.contains(&obsolete_oid)
+        })
+        .partition(|obsmarker| match obsmarker {
+            ObsMarker::Rewrite {
+                old_commit_oid: _,
+                new_commit_oid,
+            } => repo.find_commit(*new_commit_oid).is_ok(),
+            ObsMarker::Hide { commit_oid: _ } => true,
+        });
+    NewObsMarkers {
+        obsmarkers,
+        missing_obsmarkers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obsmarker_round_trip() -> anyhow::Result<()> {
+        let obsmarkers = vec![
+            ObsMarker::Rewrite {
+                old_commit_oid: "62fc20d2a290daea0d52bdc2ed2ad4be6491010e".parse()?,
+                new_commit_oid: "96d1c37a3d4363611c49f7e52186e189a04c531f".parse()?,
+            },
+            ObsMarker::Hide {
+                commit_oid: "f777ecc9b0db5ed372b2615695191a8a17f79f24".parse()?,
+            },
+        ];
+        for obsmarker in obsmarkers {
+            assert_eq!(obsmarker.to_string().parse::<ObsMarker>()?, obsmarker);
+        }
+
+        assert!("rewrite 62fc20d2".parse::<ObsMarker>().is_err());
+        assert!("unhide 62fc20d2".parse::<ObsMarker>().is_err());
+
+        Ok(())
+    }
+}
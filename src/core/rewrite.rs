@@ -3,21 +3,34 @@
 
 use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::PathBuf;
 use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::time::SystemTime;
 
 use anyhow::Context;
 use cursive::utils::markup::StyledString;
 use fn_error_context::context;
 use indicatif::{ProgressBar, ProgressStyle};
 
+use crate::core::config::{
+    get_core_hooks_path, get_in_memory_hooks_policy, get_restack_preserve_timestamps,
+    InMemoryHooksPolicy,
+};
 use crate::core::formatting::printable_styled_string;
-use crate::util::{run_git, wrap_git_error, GitExecutable};
+use crate::util::{
+    get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid,
+    get_worktree_head_ref_name, run_git, wrap_git_error, GitExecutable,
+};
 
 use super::eventlog::{
-    Event, EventCursor, EventReplayer, EventTransactionId, BRANCHLESS_TRANSACTION_ID_ENV_VAR,
+    Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId,
+    BRANCHLESS_TRANSACTION_ID_ENV_VAR,
 };
 use super::formatting::Glyphs;
-use super::graph::{find_path_to_merge_base, CommitGraph, MainBranchOid};
+use super::graph::{
+    find_path_to_merge_base, make_graph, BranchOids, CommitGraph, HeadOid, MainBranchOid,
+};
 use super::mergebase::MergeBaseDb;
 use super::metadata::{render_commit_metadata, CommitMessageProvider, CommitOidProvider};
 
@@ -62,7 +75,11 @@ pub fn find_rewrite_target(
         Event::RefUpdateEvent { .. }
         | Event::CommitEvent { .. }
         | Event::HideEvent { .. }
-        | Event::UnhideEvent { .. } => None,
+        | Event::UnhideEvent { .. }
+        | Event::WorkingCopySnapshotEvent { .. }
+        | Event::StashPushEvent { .. }
+        | Event::StashDropEvent { .. }
+        | Event::CommitOriginEvent { .. } => None,
     }
 }
 
@@ -81,8 +98,12 @@ pub fn find_abandoned_children(
     // Adjacent main branch commits are not linked in the commit graph, but if
     // the user rewrote a main branch commit, then we may need to restack
     // subsequent main branch commits. Find the real set of children commits so
-    // that we can do this.
-    let mut real_children_oids = graph[&oid].children.clone();
+    // that we can do this. (The rewritten commit itself may not be in the
+    // graph if it's no longer visible.)
+    let mut real_children_oids = match graph.get(&oid) {
+        Some(node) => node.children.clone(),
+        None => Default::default(),
+    };
     let additional_children_oids: HashSet<git2::Oid> = graph
         .iter()
         .filter_map(|(possible_child_oid, possible_child_node)| {
@@ -123,35 +144,84 @@ fn make_label_name(repo: &git2::Repository, mut preferred_name: String) -> anyho
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum RebaseCommand {
     Label { label_name: String },
     Reset { label_name: String },
+    ResetToCommit { commit_oid: git2::Oid },
     Pick { commit_oid: git2::Oid },
 }
 
 /// Represents a sequence of commands that can be executed to carry out a rebase
 /// operation.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RebasePlan {
     commands: Vec<RebaseCommand>,
 }
 
+impl RebasePlan {
+    /// Pick a different commit in place of each commit which is a key in
+    /// `replacements`. This can be used to rebase a modified version of a
+    /// commit along with the rest of its subtree.
+    pub fn replace_picked_commits(&mut self, replacements: &HashMap<git2::Oid, git2::Oid>) {
+        for command in self.commands.iter_mut() {
+            if let RebaseCommand::Pick { commit_oid } = command {
+                if let Some(replacement_oid) = replacements.get(commit_oid) {
+                    *commit_oid = *replacement_oid;
+                }
+            }
+        }
+    }
+}
+
 impl ToString for RebaseCommand {
     fn to_string(&self) -> String {
         match self {
             RebaseCommand::Label { label_name } => format!("label {}", label_name),
             RebaseCommand::Reset { label_name } => format!("reset {}", label_name),
+            RebaseCommand::ResetToCommit { commit_oid } => format!("reset {}", commit_oid),
             RebaseCommand::Pick { commit_oid } => format!("pick {}", commit_oid),
         }
     }
 }
 
+impl FromStr for RebaseCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let command = match s.split_once(' ') {
+            Some(("label", label_name)) => RebaseCommand::Label {
+                label_name: label_name.to_string(),
+            },
+            Some(("reset", target)) => match git2::Oid::from_str(target) {
+                Ok(commit_oid) if target.len() == commit_oid.to_string().len() => {
+                    RebaseCommand::ResetToCommit { commit_oid }
+                }
+                _ => RebaseCommand::Reset {
+                    label_name: target.to_string(),
+                },
+            },
+            Some(("pick", commit_oid)) => RebaseCommand::Pick {
+                commit_oid: git2::Oid::from_str(commit_oid).map_err(wrap_git_error)?,
+            },
+            _ => anyhow::bail!("Could not parse rebase command: {:?}", s),
+        };
+        Ok(command)
+    }
+}
+
+/// Sort the given commits by commit time, for determinism.
+pub fn sort_commits(graph: &CommitGraph, mut oids: Vec<git2::Oid>) -> Vec<git2::Oid> {
+    oids.sort_by_key(|oid| (graph[oid].commit.time(), oid.to_string()));
+    oids
+}
+
 fn make_rebase_plan_for_current_commit(
     repo: &git2::Repository,
     graph: &CommitGraph,
     current_oid: git2::Oid,
     current_label: &str,
+    get_children: &dyn Fn(git2::Oid) -> Vec<git2::Oid>,
     mut acc: Vec<RebaseCommand>,
 ) -> anyhow::Result<Vec<RebaseCommand>> {
     let acc = {
@@ -160,22 +230,14 @@ fn make_rebase_plan_for_current_commit(
         });
         acc
     };
-    let current_node = match graph.get(&current_oid) {
-        Some(current_node) => current_node,
-        None => {
-            anyhow::bail!(format!(
-                "BUG: commit {} could not be found in the commit graph",
-                current_oid.to_string()
-            ))
-        }
-    };
+    if !graph.contains_key(&current_oid) {
+        anyhow::bail!(format!(
+            "BUG: commit {} could not be found in the commit graph",
+            current_oid.to_string()
+        ))
+    }
 
-    let children = {
-        // Sort for determinism.
-        let mut children = current_node.children.iter().copied().collect::<Vec<_>>();
-        children.sort_by_key(|child_oid| (graph[child_oid].commit.time(), child_oid.to_string()));
-        children
-    };
+    let children = sort_commits(graph, get_children(current_oid));
     match children.as_slice() {
         [] => Ok(acc),
         [only_child_oid] => {
@@ -184,6 +246,7 @@ fn make_rebase_plan_for_current_commit(
                 &graph,
                 *only_child_oid,
                 current_label,
+                get_children,
                 acc,
             )?;
             Ok(acc)
@@ -196,8 +259,14 @@ fn make_rebase_plan_for_current_commit(
                 label_name: label_name.clone(),
             });
             for child_oid in children {
-                acc =
-                    make_rebase_plan_for_current_commit(repo, graph, *child_oid, &label_name, acc)?;
+                acc = make_rebase_plan_for_current_commit(
+                    repo,
+                    graph,
+                    *child_oid,
+                    &label_name,
+                    get_children,
+                    acc,
+                )?;
                 acc.push(RebaseCommand::Reset {
                     label_name: label_name.clone(),
                 });
@@ -207,21 +276,30 @@ fn make_rebase_plan_for_current_commit(
     }
 }
 
-/// Generate a sequence of rebase steps that cause the subtree at `source_oid`
-/// to be rebased on top of the commit at `main_branch_oid`.
+/// Generate a sequence of rebase steps that cause the subtrees at each of
+/// `source_oids` to be rebased on top of the same destination commit.
 pub fn make_rebase_plan(
     repo: &git2::Repository,
     merge_base_db: &MergeBaseDb,
     graph: &CommitGraph,
     main_branch_oid: &MainBranchOid,
-    source_oid: git2::Oid,
+    source_oids: &[git2::Oid],
 ) -> anyhow::Result<RebasePlan> {
     let label_name = make_label_name(&repo, "onto".to_string())?;
-    let (source_oid, mut commands) = {
+    let mut commands = vec![RebaseCommand::Label {
+        label_name: label_name.clone(),
+    }];
+    for (i, source_oid) in source_oids.iter().copied().enumerate() {
+        if i > 0 {
+            commands.push(RebaseCommand::Reset {
+                label_name: label_name.clone(),
+            });
+        }
+
         let MainBranchOid(main_branch_oid) = main_branch_oid;
         let merge_base_oid =
             merge_base_db.get_merge_base_oid(&repo, *main_branch_oid, source_oid)?;
-        if merge_base_oid == Some(source_oid) {
+        let source_oid = if merge_base_oid == Some(source_oid) {
             // In this case, the `source` OID is an ancestor of the main branch.
             // This means that the user is trying to rewrite public history,
             // which is typically not recommended, but let's try to do it
@@ -229,29 +307,229 @@ pub fn make_rebase_plan(
             let path =
                 find_path_to_merge_base(&repo, &merge_base_db, *main_branch_oid, source_oid)?
                     .unwrap_or_default();
-            let commands: Vec<RebaseCommand> = path
-                .into_iter()
-                // Skip the first element, which is the main branch OID, since
-                // it'll be picked as part of the recursive rebase plan below.
-                .skip(1)
-                // Reverse the path, since it goes from main branch OID to
-                // source OID, but we want a path from source OID to main branch
-                // OID.
-                .rev()
-                .map(|main_branch_commit| RebaseCommand::Pick {
-                    commit_oid: main_branch_commit.id(),
-                })
-                .collect();
-            (*main_branch_oid, commands)
+            commands.extend(
+                path.into_iter()
+                    // Skip the first element, which is the main branch OID,
+                    // since it'll be picked as part of the recursive rebase
+                    // plan below.
+                    .skip(1)
+                    // Reverse the path, since it goes from main branch OID to
+                    // source OID, but we want a path from source OID to main
+                    // branch OID.
+                    .rev()
+                    .map(|main_branch_commit| RebaseCommand::Pick {
+                        commit_oid: main_branch_commit.id(),
+                    }),
+            );
+            *main_branch_oid
         } else {
-            (source_oid, Vec::new())
+            source_oid
+        };
+        commands = make_rebase_plan_for_current_commit(
+            repo,
+            graph,
+            source_oid,
+            &label_name,
+            &|oid| graph[&oid].children.iter().copied().collect(),
+            commands,
+        )?;
+    }
+    Ok(RebasePlan { commands })
+}
+
+/// Find the OIDs of the commits in the subtree rooted at `oid`, including
+/// `oid` itself.
+pub fn find_subtree_oids(graph: &CommitGraph, oid: git2::Oid) -> HashSet<git2::Oid> {
+    let mut result = HashSet::new();
+    let mut stack = vec![oid];
+    while let Some(oid) = stack.pop() {
+        if result.insert(oid) {
+            if let Some(node) = graph.get(&oid) {
+                stack.extend(node.children.iter().copied());
+            }
+        }
+    }
+    result
+}
+
+/// Generate a sequence of rebase steps that cause the subtree at `source_oid`
+/// to be inserted between the commit at `dest_oid` and its current children.
+///
+/// The subtree at `source_oid` is rebased on top of `dest_oid`, as with
+/// `make_rebase_plan`, and then the other children of `dest_oid` are rebased
+/// on top of the last commit of the subtree. The subtree must therefore be
+/// linear.
+pub fn make_insert_rebase_plan(
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    graph: &CommitGraph,
+    main_branch_oid: &MainBranchOid,
+    source_oid: git2::Oid,
+    dest_oid: git2::Oid,
+) -> anyhow::Result<RebasePlan> {
+    let RebasePlan { mut commands } =
+        make_rebase_plan(repo, merge_base_db, graph, main_branch_oid, &[source_oid])?;
+    let subtree_oids = find_subtree_oids(graph, source_oid);
+    let get_children = |oid: git2::Oid| -> Vec<git2::Oid> {
+        match graph.get(&oid) {
+            Some(node) => node
+                .children
+                .iter()
+                .filter(|child_oid| !subtree_oids.contains(child_oid))
+                .copied()
+                .collect(),
+            None => Vec::new(),
         }
     };
+    let dest_children = sort_commits(graph, get_children(dest_oid));
+
+    let label_name = make_label_name(repo, "insert".to_string())?;
     commands.push(RebaseCommand::Label {
         label_name: label_name.clone(),
     });
-    let commands =
-        make_rebase_plan_for_current_commit(repo, graph, source_oid, &label_name, commands)?;
+    for child_oid in dest_children {
+        commands = make_rebase_plan_for_current_commit(
+            repo,
+            graph,
+            child_oid,
+            &label_name,
+            &get_children,
+            commands,
+        )?;
+        commands.push(RebaseCommand::Reset {
+            label_name: label_name.clone(),
+        });
+    }
+    Ok(RebasePlan { commands })
+}
+
+/// Generate a sequence of rebase steps that cause exactly the commits in
+/// `exact_oids` to be rebased on top of the commit at `dest_oid`, without their
+/// descendants.
+///
+/// The moved commits keep their relative structure: each one is rebased on top
+/// of its nearest ancestor which is also being moved, or on top of `dest_oid`
+/// if there is no such ancestor. Any descendants of the moved commits which
+/// aren't being moved themselves are rebased on top of their nearest ancestor
+/// which isn't being moved.
+pub fn make_exact_rebase_plan(
+    repo: &git2::Repository,
+    graph: &CommitGraph,
+    exact_oids: &HashSet<git2::Oid>,
+    dest_oid: git2::Oid,
+) -> anyhow::Result<RebasePlan> {
+    // The children of a commit after the rebase, skipping over any commits
+    // which are (or aren't) being moved.
+    fn get_new_children(
+        graph: &CommitGraph,
+        exact_oids: &HashSet<git2::Oid>,
+        is_moved: bool,
+        oid: git2::Oid,
+    ) -> Vec<git2::Oid> {
+        let mut result = Vec::new();
+        if let Some(node) = graph.get(&oid) {
+            for child_oid in node.children.iter() {
+                if exact_oids.contains(child_oid) == is_moved {
+                    result.push(*child_oid);
+                } else {
+                    result.extend(get_new_children(graph, exact_oids, is_moved, *child_oid));
+                }
+            }
+        }
+        result
+    }
+
+    let affected_oids: HashSet<git2::Oid> = exact_oids
+        .iter()
+        .flat_map(|oid| find_subtree_oids(graph, *oid))
+        .collect();
+    let label_name = make_label_name(repo, "onto".to_string())?;
+
+    // First, rebase the descendants of the moved commits which aren't being
+    // moved themselves on top of their nearest unmoved ancestors.
+    let mut commands = Vec::new();
+    let unmoved_parent_oids = sort_commits(
+        graph,
+        exact_oids
+            .iter()
+            .filter_map(|oid| graph[oid].parent)
+            .filter(|parent_oid| !affected_oids.contains(parent_oid))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect(),
+    );
+    for parent_oid in unmoved_parent_oids {
+        let child_oids = graph[&parent_oid]
+            .children
+            .iter()
+            .filter(|child_oid| exact_oids.contains(child_oid))
+            .flat_map(|child_oid| get_new_children(graph, exact_oids, false, *child_oid))
+            .collect();
+        for child_oid in sort_commits(graph, child_oids) {
+            commands.push(RebaseCommand::ResetToCommit {
+                commit_oid: parent_oid,
+            });
+            commands = make_rebase_plan_for_current_commit(
+                repo,
+                graph,
+                child_oid,
+                &label_name,
+                &|oid| get_new_children(graph, exact_oids, false, oid),
+                commands,
+            )?;
+        }
+    }
+
+    // If the destination commit was itself rebased above, then the moved
+    // commits need to go on top of its rebased version.
+    match commands.iter().position(
+        |command| matches!(command, RebaseCommand::Pick { commit_oid } if *commit_oid == dest_oid),
+    ) {
+        Some(index) => commands.insert(
+            index + 1,
+            RebaseCommand::Label {
+                label_name: label_name.clone(),
+            },
+        ),
+        None => commands.insert(
+            0,
+            RebaseCommand::Label {
+                label_name: label_name.clone(),
+            },
+        ),
+    }
+
+    // Then rebase the moved commits on top of the destination commit.
+    let root_oids = sort_commits(
+        graph,
+        exact_oids
+            .iter()
+            .filter(|oid| {
+                let mut current_oid = graph[oid].parent;
+                while let Some(oid) = current_oid {
+                    if exact_oids.contains(&oid) {
+                        return false;
+                    }
+                    current_oid = graph.get(&oid).and_then(|node| node.parent);
+                }
+                true
+            })
+            .copied()
+            .collect(),
+    );
+    for root_oid in root_oids {
+        commands.push(RebaseCommand::Reset {
+            label_name: label_name.clone(),
+        });
+        commands = make_rebase_plan_for_current_commit(
+            repo,
+            graph,
+            root_oid,
+            &label_name,
+            &|oid| get_new_children(graph, exact_oids, true, oid),
+            commands,
+        )?;
+    }
     Ok(RebasePlan { commands })
 }
 
@@ -283,7 +561,9 @@ fn rebase_in_memory(
         .commands
         .iter()
         .filter(|command| match command {
-            RebaseCommand::Label { .. } | RebaseCommand::Reset { .. } => false,
+            RebaseCommand::Label { .. }
+            | RebaseCommand::Reset { .. }
+            | RebaseCommand::ResetToCommit { .. } => false,
             RebaseCommand::Pick { .. } => true,
         })
         .count();
@@ -299,6 +579,9 @@ fn rebase_in_memory(
                     None => anyhow::bail!("BUG: no associated OID for label: {}", label_name),
                 };
             }
+            RebaseCommand::ResetToCommit { commit_oid } => {
+                current_oid = *commit_oid;
+            }
             RebaseCommand::Pick { commit_oid } => {
                 let current_commit = repo
                     .find_commit(current_oid)
@@ -380,40 +663,118 @@ fn rebase_in_memory(
     Ok(RebaseInMemoryResult::Succeeded { rewritten_oids })
 }
 
+/// Environment variable set when running the `post-commit` hook for a commit
+/// created in memory. Since `HEAD` isn't moved to the new commit, this holds
+/// its OID instead. Our own `post-commit` hook skips any commits for which
+/// this is set, since they're already recorded as rewrites.
+pub const BRANCHLESS_IN_MEMORY_COMMIT_OID_ENV_VAR: &str = "BRANCHLESS_IN_MEMORY_COMMIT_OID";
+
+/// Environment variable set when running the `post-rewrite` hook for an
+/// in-memory rebase whose caller restacks any abandoned branches itself. Our
+/// own `post-rewrite` hook doesn't warn about abandoned commits or branches
+/// when this is set.
+pub const BRANCHLESS_SKIP_ABANDONED_WARNING_ENV_VAR: &str = "BRANCHLESS_SKIP_ABANDONED_WARNING";
+
+/// Report a failed hook to the user.
+///
+/// Returns: Whether or not the hook succeeded.
+fn check_hook_exit_status(hook_name: &str, exit_status: ExitStatus) -> bool {
+    if !exit_status.success() {
+        println!(
+            "branchless: the {} hook failed with {}",
+            hook_name, exit_status
+        );
+    }
+    exit_status.success()
+}
+
+/// Run the hooks for the commits created by an in-memory rebase, according to
+/// `policy`.
+///
+/// If `warn_abandoned` is false, our `post-rewrite` hook is told not to warn
+/// about abandoned commits or branches.
+///
+/// Returns: Exit code (0 denotes that all hooks succeeded).
 fn post_rebase_in_memory(
     repo: &git2::Repository,
+    policy: InMemoryHooksPolicy,
     rewritten_oids: &[(git2::Oid, git2::Oid)],
     event_tx_id: EventTransactionId,
-) -> anyhow::Result<()> {
-    let post_rewrite_hook_path = repo
-        .config()?
-        .get_path("core.hooksPath")
-        .unwrap_or_else(|_| repo.path().join("hooks"))
-        .join("post-rewrite");
-    if post_rewrite_hook_path.exists() {
-        let mut child = Command::new(post_rewrite_hook_path.as_path())
+    warn_abandoned: bool,
+) -> anyhow::Result<isize> {
+    let hooks_path = get_core_hooks_path(repo)?;
+    let mut all_hooks_succeeded = true;
+
+    if policy == InMemoryHooksPolicy::All {
+        let post_commit_hook_path = hooks_path.join("post-commit");
+        if post_commit_hook_path.exists() {
+            for (_old_oid, new_oid) in rewritten_oids {
+                let mut command = Command::new(post_commit_hook_path.as_path());
+                command
+                    .env(BRANCHLESS_TRANSACTION_ID_ENV_VAR, event_tx_id.to_string())
+                    .env(BRANCHLESS_IN_MEMORY_COMMIT_OID_ENV_VAR, new_oid.to_string());
+                if let Some(workdir) = repo.workdir() {
+                    command.current_dir(workdir);
+                }
+                let exit_status = command.status().with_context(|| {
+                    format!(
+                        "Invoking post-commit hook at: {:?}",
+                        post_commit_hook_path.as_path()
+                    )
+                })?;
+                all_hooks_succeeded &= check_hook_exit_status("post-commit", exit_status);
+            }
+        }
+    }
+
+    let post_rewrite_hook_path = hooks_path.join("post-rewrite");
+    if policy != InMemoryHooksPolicy::None && post_rewrite_hook_path.exists() {
+        let mut command = Command::new(post_rewrite_hook_path.as_path());
+        command
             .arg("rebase")
             .env(BRANCHLESS_TRANSACTION_ID_ENV_VAR, event_tx_id.to_string())
-            .stdin(Stdio::piped())
-            .spawn()
-            .with_context(|| {
-                format!(
-                    "Invoking post-rewrite hook at: {:?}",
-                    post_rewrite_hook_path.as_path()
-                )
-            })?;
+            .stdin(Stdio::piped());
+        if !warn_abandoned {
+            command.env(BRANCHLESS_SKIP_ABANDONED_WARNING_ENV_VAR, "1");
+        }
+        let mut child = command.spawn().with_context(|| {
+            format!(
+                "Invoking post-rewrite hook at: {:?}",
+                post_rewrite_hook_path.as_path()
+            )
+        })?;
 
         let stdin = child.stdin.as_mut().unwrap();
         for (old_oid, new_oid) in rewritten_oids {
             writeln!(stdin, "{} {}", old_oid.to_string(), new_oid.to_string())?;
         }
 
-        let _ignored: ExitStatus = child.wait()?;
+        let exit_status = child.wait()?;
+        all_hooks_succeeded &= check_hook_exit_status("post-rewrite", exit_status);
+    } else {
+        // Our own `post-rewrite` hook wasn't run to record the rewritten
+        // commits, so record them directly.
+        let conn = get_db_conn(repo)?;
+        let mut event_log_db = EventLogDb::new(&conn)?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs_f64();
+        event_log_db.add_events(
+            rewritten_oids
+                .iter()
+                .map(|(old_oid, new_oid)| Event::RewriteEvent {
+                    timestamp,
+                    event_tx_id,
+                    old_commit_oid: *old_oid,
+                    new_commit_oid: *new_oid,
+                })
+                .collect(),
+        )?;
     }
 
     // TODO: move any affected branches, to match the behavior of `git rebase`.
 
-    Ok(())
+    Ok(if all_hooks_succeeded { 0 } else { 1 })
 }
 
 #[context("Rebasing on disk from {} to {}", source_oid.to_string(), dest_oid.to_string())]
@@ -460,18 +821,218 @@ fn rebase_on_disk(
     )
     .with_context(|| format!("Writing `end` to: {:?}", end_file.as_path()))?;
 
+    let (head_name, onto, orig_head) = match get_rebase_in_progress_id(repo)? {
+        Some(rebase_id) => rebase_id,
+        None => anyhow::bail!("BUG: rebase was not initialized"),
+    };
+
     progress.set_message("Calling Git for on-disk rebase");
-    let result = run_git(
-        &git_executable,
-        Some(event_tx_id),
+    let result = drive_rebase_on_disk(
+        git_executable,
+        repo,
+        &RebaseState {
+            event_tx_id,
+            head_name,
+            onto,
+            orig_head,
+            rebase_plan: rebase_plan.clone(),
+        },
         &["rebase", "--continue"],
     )?;
 
     Ok(result)
 }
 
+/// The state of an on-disk rebase started by `git move` which stopped partway
+/// through, such as because of a merge conflict, so that it can later be
+/// resumed or aborted with `git move --continue` or `git move --abort`.
+#[derive(Debug)]
+pub struct RebaseState {
+    /// The transaction which the rebase's events are recorded under.
+    pub event_tx_id: EventTransactionId,
+
+    /// The contents of the rebase's `head-name` file.
+    pub head_name: String,
+
+    /// The contents of the rebase's `onto` file.
+    pub onto: String,
+
+    /// The contents of the rebase's `orig-head` file.
+    pub orig_head: String,
+
+    /// The plan which the rebase is executing.
+    pub rebase_plan: RebasePlan,
+}
+
+fn get_rebase_state_path(repo: &git2::Repository) -> PathBuf {
+    repo.path().join("branchless").join("rebase-state")
+}
+
+/// Whether or not Git has an on-disk rebase in progress in this worktree.
+pub fn is_rebase_in_progress(repo: &git2::Repository) -> bool {
+    repo.path().join("rebase-merge").exists()
+}
+
+/// Get the `head-name`, `onto` and `orig-head` of the on-disk rebase in
+/// progress, which together identify it, or `None` if there is no rebase in
+/// progress.
+#[context("Reading the state of the rebase in progress")]
+fn get_rebase_in_progress_id(
+    repo: &git2::Repository,
+) -> anyhow::Result<Option<(String, String, String)>> {
+    let read_file = |file_name: &str| -> anyhow::Result<Option<String>> {
+        let path = repo.path().join("rebase-merge").join(file_name);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(contents.trim().to_string())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("Reading {:?}", &path)),
+        }
+    };
+    match (
+        read_file("head-name")?,
+        read_file("onto")?,
+        read_file("orig-head")?,
+    ) {
+        (Some(head_name), Some(onto), Some(orig_head)) => Ok(Some((head_name, onto, orig_head))),
+        _ => Ok(None),
+    }
+}
+
+/// Whether the on-disk rebase in progress is the one described by
+/// `rebase_state`, rather than one which the user started separately after
+/// the state was saved.
+pub fn is_rebase_state_current(
+    repo: &git2::Repository,
+    rebase_state: &RebaseState,
+) -> anyhow::Result<bool> {
+    let is_current = match get_rebase_in_progress_id(repo)? {
+        Some((head_name, onto, orig_head)) => {
+            head_name == rebase_state.head_name
+                && onto == rebase_state.onto
+                && orig_head == rebase_state.orig_head
+        }
+        None => false,
+    };
+    Ok(is_current)
+}
+
+#[context("Saving rebase state")]
+fn save_rebase_state(repo: &git2::Repository, rebase_state: &RebaseState) -> anyhow::Result<()> {
+    let RebaseState {
+        event_tx_id,
+        head_name,
+        onto,
+        orig_head,
+        rebase_plan,
+    } = rebase_state;
+    let path = get_rebase_state_path(repo);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| "Creating .git/branchless dir")?;
+    }
+    let contents = vec![
+        event_tx_id.to_string(),
+        head_name.clone(),
+        onto.clone(),
+        orig_head.clone(),
+    ]
+    .into_iter()
+    .chain(
+        rebase_plan
+            .commands
+            .iter()
+            .map(|command| command.to_string()),
+    )
+    .map(|line| format!("{}\n", line))
+    .collect::<String>();
+    std::fs::write(&path, contents).with_context(|| format!("Writing {:?}", &path))?;
+    Ok(())
+}
+
+/// Load the state of the on-disk rebase which is currently in progress, if
+/// any.
+#[context("Loading rebase state")]
+pub fn load_rebase_state(repo: &git2::Repository) -> anyhow::Result<Option<RebaseState>> {
+    let path = get_rebase_state_path(repo);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Reading {:?}", &path)),
+    };
+    let mut lines = contents.lines();
+    let event_tx_id = match lines.next() {
+        Some(line) => EventTransactionId::from_str(line)
+            .with_context(|| format!("Parsing event transaction ID: {:?}", line))?,
+        None => anyhow::bail!("Rebase state file was empty: {:?}", &path),
+    };
+    let (head_name, onto, orig_head) = match (lines.next(), lines.next(), lines.next()) {
+        (Some(head_name), Some(onto), Some(orig_head)) => (
+            head_name.to_string(),
+            onto.to_string(),
+            orig_head.to_string(),
+        ),
+        _ => anyhow::bail!("Rebase state file was truncated: {:?}", &path),
+    };
+    let commands = lines
+        .map(RebaseCommand::from_str)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(Some(RebaseState {
+        event_tx_id,
+        head_name,
+        onto,
+        orig_head,
+        rebase_plan: RebasePlan { commands },
+    }))
+}
+
+/// Delete the saved state of the on-disk rebase, if any.
+#[context("Clearing rebase state")]
+pub fn clear_rebase_state(repo: &git2::Repository) -> anyhow::Result<()> {
+    let path = get_rebase_state_path(repo);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("Removing {:?}", &path)),
+    }
+}
+
+/// Get the names of the labels which the given rebase plan creates.
+pub fn get_rebase_plan_label_names(rebase_plan: &RebasePlan) -> Vec<&str> {
+    rebase_plan
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            RebaseCommand::Label { label_name } => Some(label_name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Run a Git command which drives the on-disk rebase forward, and keep track
+/// of the rebase's state if it stops partway through again.
+///
+/// Returns: Exit code (0 denotes successful exit).
+pub fn drive_rebase_on_disk(
+    git_executable: &GitExecutable,
+    repo: &git2::Repository,
+    rebase_state: &RebaseState,
+    args: &[&str],
+) -> anyhow::Result<isize> {
+    save_rebase_state(repo, rebase_state)?;
+    let result = run_git(git_executable, Some(rebase_state.event_tx_id), args)?;
+    if is_rebase_in_progress(repo) {
+        println!(
+            "The rebase was interrupted. Resolve any merge conflicts, then run `git move --continue` to resume it, or `git move --abort` to cancel it."
+        );
+    } else {
+        clear_rebase_state(repo)?;
+    }
+    Ok(result)
+}
+
+/// Render a one-line description of the given commit, consisting of its
+/// abbreviated OID and the first line of its message.
 #[context("Describing commit {}", commit_oid.to_string())]
-fn friendly_describe_commit(
+pub fn friendly_describe_commit(
     repo: &git2::Repository,
     commit_oid: git2::Oid,
 ) -> anyhow::Result<StyledString> {
@@ -488,8 +1049,44 @@ fn friendly_describe_commit(
     Ok(description)
 }
 
-/// Execute the provided rebase plan. Returns the exit status (zero indicates
-/// success).
+/// The result of executing a rebase plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecuteRebasePlanResult {
+    /// The rebase succeeded.
+    Succeeded,
+
+    /// The commits were rebased in memory and the rewrites were recorded, but
+    /// one or more hooks failed afterwards.
+    HookFailed,
+
+    /// The rebase failed or was interrupted, such as by a merge conflict.
+    Failed {
+        /// The exit code to exit with.
+        exit_code: isize,
+    },
+}
+
+impl ExecuteRebasePlanResult {
+    /// Get the exit code to exit with (0 denotes success).
+    pub fn exit_code(self) -> isize {
+        match self {
+            ExecuteRebasePlanResult::Succeeded => 0,
+            ExecuteRebasePlanResult::HookFailed => 1,
+            ExecuteRebasePlanResult::Failed { exit_code } => exit_code,
+        }
+    }
+}
+
+/// Execute the provided rebase plan.
+///
+/// Args:
+/// * `force_on_disk`: Skip attempting an in-memory rebase.
+/// * `force_in_memory`: Don't fall back to an on-disk rebase if there is a
+///   merge conflict. Instead, nothing is rewritten and
+///   `ExecuteRebasePlanResult::Failed` is returned.
+/// * `warn_abandoned`: Whether the `post-rewrite` hook should warn about
+///   commits or branches abandoned by an in-memory rebase. Callers which
+///   restack branches afterwards should pass `false`.
 pub fn execute_rebase_plan(
     glyphs: &Glyphs,
     git_executable: &GitExecutable,
@@ -499,21 +1096,44 @@ pub fn execute_rebase_plan(
     source_oid: git2::Oid,
     dest_oid: git2::Oid,
     force_on_disk: bool,
-) -> anyhow::Result<isize> {
+    force_in_memory: bool,
+    warn_abandoned: bool,
+) -> anyhow::Result<ExecuteRebasePlanResult> {
     if !force_on_disk {
+        // Read the hook policy before rebasing, so that an invalid setting
+        // doesn't leave behind rewritten commits which were never recorded.
+        let hooks_policy = get_in_memory_hooks_policy(repo)?;
         println!("Attempting rebase in-memory...");
-        match rebase_in_memory(glyphs, &repo, &rebase_plan, dest_oid)? {
+        match rebase_in_memory(glyphs, repo, rebase_plan, dest_oid)? {
             RebaseInMemoryResult::Succeeded { rewritten_oids } => {
-                post_rebase_in_memory(&repo, &rewritten_oids, event_tx_id)?;
-                println!("In-memory rebase succeeded.");
-                return Ok(0);
+                let exit_code = post_rebase_in_memory(
+                    repo,
+                    hooks_policy,
+                    &rewritten_oids,
+                    event_tx_id,
+                    warn_abandoned,
+                )?;
+                if exit_code == 0 {
+                    println!("In-memory rebase succeeded.");
+                    return Ok(ExecuteRebasePlanResult::Succeeded);
+                } else {
+                    println!("In-memory rebase succeeded, but one or more hooks failed.");
+                    return Ok(ExecuteRebasePlanResult::HookFailed);
+                }
             }
             RebaseInMemoryResult::CannotRebaseMergeCommit { commit_oid } => {
                 println!(
                     "Merge commits currently can't be rebased with `git move`. The merge commit was: {}",
                     printable_styled_string(glyphs, friendly_describe_commit(repo, commit_oid)?)?
                 );
-                return Ok(1);
+                return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
+            }
+            RebaseInMemoryResult::MergeConflict { commit_oid } if force_in_memory => {
+                println!(
+                    "Merge conflict. The conflicting commit was: {}",
+                    printable_styled_string(glyphs, friendly_describe_commit(repo, commit_oid)?)?,
+                );
+                return Ok(ExecuteRebasePlanResult::Failed { exit_code: 1 });
             }
             RebaseInMemoryResult::MergeConflict { commit_oid } => {
                 println!(
@@ -524,24 +1144,286 @@ pub fn execute_rebase_plan(
         }
     }
 
-    let result = rebase_on_disk(
+    let exit_code = rebase_on_disk(
         git_executable,
         repo,
-        &rebase_plan,
+        rebase_plan,
         source_oid,
         dest_oid,
         event_tx_id,
     )?;
+    if exit_code == 0 {
+        Ok(ExecuteRebasePlanResult::Succeeded)
+    } else {
+        Ok(ExecuteRebasePlanResult::Failed { exit_code })
+    }
+}
+
+/// Get the committer signature to use when rewriting the given commit in
+/// memory.
+///
+/// The committer is the current user. The commit time is the current time,
+/// unless `branchless.restack.preserveTimestamps` is set, in which case the
+/// author time of the original commit is used.
+#[context("Getting committer signature for rewriting {:?}", commit.id())]
+pub fn make_rewritten_committer(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+) -> anyhow::Result<git2::Signature<'static>> {
+    let committer = repo.signature().map_err(wrap_git_error)?;
+    if get_restack_preserve_timestamps(repo)? {
+        let committer = git2::Signature::new(
+            committer.name().unwrap_or_default(),
+            committer.email().unwrap_or_default(),
+            &commit.author().when(),
+        )
+        .map_err(wrap_git_error)?;
+        Ok(committer)
+    } else {
+        Ok(committer)
+    }
+}
+
+/// Point `HEAD` at `new_oid` without touching the working copy or the index.
+///
+/// If `HEAD` is attached to a branch, the branch is moved instead. Since
+/// Git's `reference-transaction` hook isn't invoked for reference updates
+/// made with libgit2, the corresponding events are added to the event log
+/// directly. This should only be used when the working copy already matches
+/// `new_oid`, such as after amending the `HEAD` commit.
+#[context("Moving HEAD to {:?}", new_oid)]
+pub fn move_head_in_memory(
+    repo: &git2::Repository,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+    new_oid: git2::Oid,
+) -> anyhow::Result<()> {
+    let head_ref = repo.head().map_err(wrap_git_error)?;
+    let old_oid = head_ref.peel_to_commit().map_err(wrap_git_error)?.id();
+    let branch_ref_name = if head_ref.is_branch() {
+        head_ref.name().map(|ref_name| ref_name.to_owned())
+    } else {
+        None
+    };
+    match &branch_ref_name {
+        Some(branch_ref_name) => {
+            repo.reference(branch_ref_name, new_oid, true, "branchless: rewrite")
+                .map_err(wrap_git_error)?;
+        }
+        None => {
+            repo.set_head_detached(new_oid).map_err(wrap_git_error)?;
+        }
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs_f64();
+    let make_event = |ref_name: String| Event::RefUpdateEvent {
+        timestamp,
+        event_tx_id,
+        ref_name,
+        old_ref: Some(old_oid.to_string()),
+        new_ref: Some(new_oid.to_string()),
+        message: None,
+    };
+    let mut events = vec![make_event(get_worktree_head_ref_name(repo)?)];
+    events.extend(branch_ref_name.map(make_event));
+    event_log_db.add_events(events)?;
+    Ok(())
+}
+
+/// Make the commit graph for the current state of the repository.
+pub fn make_current_graph<'repo>(
+    repo: &'repo git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_replayer: &EventReplayer,
+) -> anyhow::Result<CommitGraph<'repo>> {
+    let head_oid = get_head_oid(repo)?;
+    let main_branch_oid = get_main_branch_oid(repo)?;
+    let branch_oid_to_names = get_branch_oid_to_names(repo)?;
+    make_graph(
+        repo,
+        merge_base_db,
+        event_replayer,
+        event_replayer.make_default_cursor(),
+        &HeadOid(head_oid),
+        &MainBranchOid(main_branch_oid),
+        &BranchOids(branch_oid_to_names.keys().copied().collect()),
+        true,
+    )
+}
+
+/// Find the newest version of the given commit, according to the current
+/// state of the event log.
+///
+/// Returns: The newest version of the commit, or the commit itself if it
+/// hasn't been rewritten.
+#[context("Finding latest rewrite target of {:?}", oid)]
+pub fn find_latest_rewrite_target(
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_log_db: &EventLogDb,
+    oid: git2::Oid,
+) -> anyhow::Result<git2::Oid> {
+    let event_replayer = EventReplayer::from_event_log_db(repo, event_log_db)?;
+    let graph = make_current_graph(repo, merge_base_db, &event_replayer)?;
+    let target_oid = find_rewrite_target(
+        &graph,
+        &event_replayer,
+        event_replayer.make_default_cursor(),
+        oid,
+    )
+    .unwrap_or(oid);
+    Ok(target_oid)
+}
+
+/// Rebase the visible children of a commit which has been rewritten onto the
+/// newest version of that commit.
+///
+/// The children are only rebased in memory. An interrupted on-disk rebase
+/// could only be resumed with `git move --continue`, which wouldn't carry out
+/// the rest of the calling command, so the user is asked to run `git restack`
+/// instead if there's a merge conflict.
+///
+/// Unlike `restack_descendants`, this doesn't update `HEAD`, even if it was
+/// one of the rebased commits. Branches aren't moved either, so callers are
+/// expected to call `restack_branches` afterwards; the `post-rewrite` hook
+/// therefore doesn't warn about abandoned branches.
+///
+/// Args:
+/// * `old_oid`: The commit which was rewritten. The corresponding
+///   `RewriteEvent` must already have been added to `event_log_db`.
+///
+/// Returns: The result of the rebases. If a hook failed, the remaining children
+/// are still rebased.
+#[context("Rebasing abandoned children of {:?}", old_oid)]
+pub fn rebase_abandoned_children(
+    glyphs: &Glyphs,
+    git_executable: &GitExecutable,
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_log_db: &EventLogDb,
+    event_tx_id: EventTransactionId,
+    old_oid: git2::Oid,
+) -> anyhow::Result<ExecuteRebasePlanResult> {
+    let event_replayer = EventReplayer::from_event_log_db(repo, event_log_db)?;
+    let event_cursor = event_replayer.make_default_cursor();
+    let main_branch_oid = get_main_branch_oid(repo)?;
+    let graph = make_current_graph(repo, merge_base_db, &event_replayer)?;
+
+    let (new_oid, abandoned_child_oids) =
+        match find_abandoned_children(&graph, &event_replayer, event_cursor, old_oid) {
+            Some(result) => result,
+            None => return Ok(ExecuteRebasePlanResult::Succeeded),
+        };
+    let mut result = ExecuteRebasePlanResult::Succeeded;
+    for child_oid in abandoned_child_oids {
+        let rebase_plan = make_rebase_plan(
+            repo,
+            merge_base_db,
+            &graph,
+            &MainBranchOid(main_branch_oid),
+            &[child_oid],
+        )?;
+        match execute_rebase_plan(
+            glyphs,
+            git_executable,
+            repo,
+            event_tx_id,
+            &rebase_plan,
+            child_oid,
+            new_oid,
+            false,
+            true,
+            false,
+        )? {
+            ExecuteRebasePlanResult::Succeeded => {}
+            ExecuteRebasePlanResult::HookFailed => {
+                result = ExecuteRebasePlanResult::HookFailed;
+            }
+            result @ ExecuteRebasePlanResult::Failed { .. } => {
+                println!(
+                    "branchless: run 'git restack' to restack the remaining abandoned commits"
+                );
+                return Ok(result);
+            }
+        }
+    }
     Ok(result)
 }
 
+/// Rebase the descendants of a commit which has been rewritten onto the newest
+/// version of that commit, using in-memory rebases.
+///
+/// If `HEAD` was one of the rebased commits, it's moved to the rebased version.
+/// The working copy is left untouched if the rebased version has the same
+/// tree, and the rebased version is checked out otherwise.
+///
+/// Args:
+/// * `old_oid`: The commit which was rewritten. The corresponding
+///   `RewriteEvent` must already have been added to `event_log_db`.
+///
+/// Returns: The result of restacking. If only a hook failed, the descendants
+/// were still restacked and `HEAD` was still moved, so callers should carry on
+/// and restack branches.
+#[context("Restacking descendants of {:?}", old_oid)]
+pub fn restack_descendants(
+    glyphs: &Glyphs,
+    git_executable: &GitExecutable,
+    repo: &git2::Repository,
+    merge_base_db: &MergeBaseDb,
+    event_log_db: &mut EventLogDb,
+    event_tx_id: EventTransactionId,
+    old_oid: git2::Oid,
+) -> anyhow::Result<ExecuteRebasePlanResult> {
+    let head_oid = get_head_oid(repo)?;
+    let result = rebase_abandoned_children(
+        glyphs,
+        git_executable,
+        repo,
+        merge_base_db,
+        event_log_db,
+        event_tx_id,
+        old_oid,
+    )?;
+    if let ExecuteRebasePlanResult::Failed { .. } = result {
+        return Ok(result);
+    }
+
+    let head_oid = match head_oid {
+        Some(head_oid) => head_oid,
+        None => return Ok(result),
+    };
+    let target_oid = find_latest_rewrite_target(repo, merge_base_db, event_log_db, head_oid)?;
+    let current_head_oid = get_head_oid(repo)?;
+    if current_head_oid == Some(target_oid) {
+        return Ok(result);
+    }
+
+    let is_same_tree =
+        repo.find_commit(head_oid)?.tree_id() == repo.find_commit(target_oid)?.tree_id();
+    if current_head_oid == Some(head_oid) && is_same_tree {
+        move_head_in_memory(repo, event_log_db, event_tx_id, target_oid)?;
+        Ok(result)
+    } else {
+        let exit_code = run_git(
+            git_executable,
+            Some(event_tx_id),
+            &["checkout", &target_oid.to_string()],
+        )?;
+        if exit_code == 0 {
+            Ok(result)
+        } else {
+            Ok(ExecuteRebasePlanResult::Failed { exit_code })
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::core::eventlog::EventLogDb;
-    use crate::core::graph::{make_graph, BranchOids, HeadOid, MainBranchOid};
     use crate::core::mergebase::MergeBaseDb;
     use crate::testing::{with_git, Git, GitRunOptions};
-    use crate::util::{get_branch_oid_to_names, get_db_conn, get_head_oid, get_main_branch_oid};
+    use crate::util::get_db_conn;
 
     use super::*;
 
@@ -550,7 +1432,7 @@ mod tests {
         let conn = get_db_conn(&repo)?;
         let merge_base_db = MergeBaseDb::new(&conn)?;
         let event_log_db = EventLogDb::new(&conn)?;
-        let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+        let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
         let event_cursor = event_replayer.make_default_cursor();
         let head_oid = get_head_oid(&repo)?;
         let main_branch_oid = get_main_branch_oid(&repo)?;
@@ -0,0 +1,232 @@
+//! Track the entries in the stash list (as shown by `git stash list`).
+//!
+//! Git only updates `refs/stash` when an entry is pushed, or when the last
+//! remaining entry is dropped. Dropping any other entry only rewrites the
+//! reflog, which doesn't invoke any hooks. So rather than observing updates to
+//! `refs/stash`, we compare the stash list against the entries recorded in the
+//! event log, and record the difference as `StashPushEvent`s and
+//! `StashDropEvent`s.
+
+use fn_error_context::context;
+
+use crate::core::eventlog::{Event, EventTransactionId};
+use crate::util::wrap_git_error;
+
+/// The name of the reference whose reflog stores the stash list.
+pub const STASH_REF_NAME: &str = "refs/stash";
+
+/// An entry in the stash list.
+#[derive(Clone, Debug)]
+pub struct StashEntry {
+    /// The index of the entry in the stash list, as in `stash@{<index>}`. The
+    /// most recent entry has index 0.
+    pub index: usize,
+
+    /// The OID of the stash commit.
+    pub commit_oid: git2::Oid,
+
+    /// The OID of the commit which was checked out when the entry was made,
+    /// if the stash commit could be found.
+    pub base_oid: Option<git2::Oid>,
+
+    /// The message of the entry, as shown in `git stash list`.
+    pub message: String,
+}
+
+/// Get the entries in the stash list, from most recent to least recent.
+#[context("Reading stash entries")]
+pub fn get_stash_entries(repo: &git2::Repository) -> anyhow::Result<Vec<StashEntry>> {
+    let reflog = repo.reflog(STASH_REF_NAME).map_err(wrap_git_error)?;
+    let stash_entries = reflog
+        .iter()
+        .enumerate()
+        .map(|(index, reflog_entry)| {
+            let commit_oid = reflog_entry.id_new();
+            let base_oid = repo
+                .find_commit(commit_oid)
+                .ok()
+                .and_then(|commit| commit.parent_id(0).ok());
+            StashEntry {
+                index,
+                commit_oid,
+                base_oid,
+                message: reflog_entry.message().unwrap_or_default().to_string(),
+            }
+        })
+        .collect();
+    Ok(stash_entries)
+}
+
+/// A difference between the stash list recorded in the event log and the
+/// actual stash list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StashChange {
+    /// An entry was added to the stash list.
+    Pushed {
+        /// The OID of the stash commit.
+        commit_oid: git2::Oid,
+
+        /// The message of the stash entry.
+        message: String,
+    },
+
+    /// An entry was removed from the stash list.
+    Dropped {
+        /// The OID of the stash commit.
+        commit_oid: git2::Oid,
+
+        /// The message of the stash entry.
+        message: String,
+    },
+}
+
+impl StashChange {
+    /// Convert this change into an event to be recorded in the event log.
+    pub fn into_event(self, timestamp: f64, event_tx_id: EventTransactionId) -> Event {
+        match self {
+            StashChange::Pushed {
+                commit_oid,
+                message,
+            } => Event::StashPushEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+                message,
+            },
+            StashChange::Dropped {
+                commit_oid,
+                message,
+            } => Event::StashDropEvent {
+                timestamp,
+                event_tx_id,
+                commit_oid,
+                message,
+            },
+        }
+    }
+}
+
+/// Compare the stash list recorded in the event log with the actual stash
+/// list.
+///
+/// Args:
+/// * `events`: All the events in the event log, from oldest to newest.
+/// * `stash_entries`: The current entries in the stash list, as returned by
+///   `get_stash_entries`.
+///
+/// Returns: A `StashChange::Dropped` for each recorded entry which is no longer
+/// in the stash list, followed by a `StashChange::Pushed` for each entry in the
+/// stash list which hasn't been recorded yet (from least recent to most
+/// recent).
+pub fn find_stash_changes(events: &[Event], stash_entries: &[StashEntry]) -> Vec<StashChange> {
+    let mut recorded_entries: Vec<(git2::Oid, &str)> = Vec::new();
+    for event in events {
+        match event {
+            Event::StashPushEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                message,
+            } => recorded_entries.push((*commit_oid, message)),
+            Event::StashDropEvent {
+                timestamp: _,
+                event_tx_id: _,
+                commit_oid,
+                message: _,
+            } => recorded_entries.retain(|(oid, _message)| oid != commit_oid),
+            _ => {}
+        }
+    }
+
+    let dropped = recorded_entries
+        .iter()
+        .filter(|(oid, _message)| {
+            !stash_entries
+                .iter()
+                .any(|stash_entry| stash_entry.commit_oid == *oid)
+        })
+        .map(|(commit_oid, message)| StashChange::Dropped {
+            commit_oid: *commit_oid,
+            message: message.to_string(),
+        });
+
+    let pushed = stash_entries
+        .iter()
+        .rev()
+        .filter(|stash_entry| {
+            !recorded_entries
+                .iter()
+                .any(|(oid, _message)| *oid == stash_entry.commit_oid)
+        })
+        .map(|stash_entry| StashChange::Pushed {
+            commit_oid: stash_entry.commit_oid,
+            message: stash_entry.message.clone(),
+        });
+
+    dropped.chain(pushed).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::eventlog::testing::make_dummy_transaction_id;
+
+    fn make_stash_entry(index: usize, commit_oid: &str) -> anyhow::Result<StashEntry> {
+        Ok(StashEntry {
+            index,
+            commit_oid: commit_oid.parse()?,
+            base_oid: None,
+            message: format!("message {}", commit_oid),
+        })
+    }
+
+    #[test]
+    fn test_find_stash_changes() -> anyhow::Result<()> {
+        let event_tx_id = make_dummy_transaction_id(123);
+        let events = vec![
+            Event::StashPushEvent {
+                timestamp: 1.0,
+                event_tx_id,
+                commit_oid: "1".parse()?,
+                message: "message 1".to_string(),
+            },
+            Event::StashPushEvent {
+                timestamp: 2.0,
+                event_tx_id,
+                commit_oid: "2".parse()?,
+                message: "message 2".to_string(),
+            },
+            Event::StashDropEvent {
+                timestamp: 3.0,
+                event_tx_id,
+                commit_oid: "1".parse()?,
+                message: "message 1".to_string(),
+            },
+        ];
+        let stash_entries = vec![make_stash_entry(0, "4")?, make_stash_entry(1, "3")?];
+
+        assert_eq!(
+            find_stash_changes(&events, &stash_entries),
+            vec![
+                StashChange::Dropped {
+                    commit_oid: "2".parse()?,
+                    message: "message 2".to_string(),
+                },
+                StashChange::Pushed {
+                    commit_oid: "3".parse()?,
+                    message: "message 3".to_string(),
+                },
+                StashChange::Pushed {
+                    commit_oid: "4".parse()?,
+                    message: "message 4".to_string(),
+                },
+            ]
+        );
+
+        let stash_entries = vec![make_stash_entry(0, "2")?];
+        assert_eq!(find_stash_changes(&events, &stash_entries), vec![]);
+
+        Ok(())
+    }
+}
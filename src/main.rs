@@ -75,18 +75,28 @@ enum Opts {
     ///
     /// By default, `git move` attempts to rebase all commits in-memory. If you
     /// want to force an on-disk rebase, pass the `--on-disk` flag. Note that
-    /// `post-commit` hooks are not called during in-memory rebases.
+    /// only the `post-rewrite` hook is called during in-memory rebases by
+    /// default. Set `branchless.inMemory.hooks` to `all` to also call the
+    /// `post-commit` hook for each rebased commit, or to `none` to call no
+    /// hooks.
     Move {
         /// The source commit to move. This commit, and all of its descendants,
-        /// will be moved. If not provided, defaults to the current commit.
-        #[structopt(short = "-s", long = "--source")]
-        source: Option<String>,
+        /// will be moved. If not provided, defaults to the current commit. May
+        /// be passed multiple times to move several subtrees at once.
+        #[structopt(short = "-s", long = "--source", number_of_values = 1)]
+        source: Vec<String>,
 
         /// A commit inside a subtree to move. The entire subtree, starting from
         /// the main branch, will be moved, not just the commits descending from
-        /// this commit.
-        #[structopt(short = "-b", long = "--base", conflicts_with = "source")]
-        base: Option<String>,
+        /// this commit. May be passed multiple times to move several subtrees
+        /// at once.
+        #[structopt(
+            short = "-b",
+            long = "--base",
+            conflicts_with = "source",
+            number_of_values = 1
+        )]
+        base: Vec<String>,
 
         /// The destination commit to move all source commits onto. If not
         /// provided, defaults to the current commit.
@@ -97,16 +107,157 @@ enum Opts {
         /// on-disk rebase directly.
         #[structopt(long = "--on-disk")]
         force_on_disk: bool,
+
+        /// Insert the moved commits between the destination commit and its
+        /// existing children, rather than making them siblings of those
+        /// children. The moved commits must form a linear stack.
+        #[structopt(short = "-I", long = "--insert")]
+        insert: bool,
+
+        /// Move only the given commits, without their descendants. Any
+        /// descendants which aren't also given are moved onto their nearest
+        /// ancestor which isn't being moved. May be passed multiple times.
+        #[structopt(
+            short = "-x",
+            long = "--exact",
+            conflicts_with_all(&["source", "base", "insert"]),
+            number_of_values = 1
+        )]
+        exact: Vec<String>,
+
+        /// Resume a move whose on-disk rebase was interrupted by a merge
+        /// conflict, after the conflict has been resolved.
+        #[structopt(
+            long = "--continue",
+            conflicts_with_all(&["source", "base", "dest", "exact", "insert", "abort"])
+        )]
+        r#continue: bool,
+
+        /// Cancel a move whose on-disk rebase was interrupted by a merge
+        /// conflict, restoring the commits to their state before the move.
+        #[structopt(
+            long = "--abort",
+            conflicts_with_all(&["source", "base", "dest", "exact", "insert"])
+        )]
+        abort: bool,
     },
 
     /// Fix up commits abandoned by a previous rewrite operation.
     Restack,
 
+    /// Amend the current commit with the changes to tracked files in the
+    /// working copy, and restack its descendants on top of it.
+    ///
+    /// Descendants are rebased in-memory where possible, so the working copy
+    /// is left untouched.
+    Amend,
+
+    /// Change the message of a commit, and restack its descendants on top of
+    /// it.
+    Reword {
+        /// The commit to reword. If not provided, defaults to the current
+        /// commit.
+        ///
+        /// Can either be a hash, like `abc123`, or a ref-spec, like `HEAD^`.
+        commit: Option<String>,
+
+        /// The new commit message. If not provided, opens an editor to edit the
+        /// existing message.
+        #[structopt(short = "-m", long = "--message")]
+        message: Option<String>,
+    },
+
+    /// Amend the staged changes into the commits in the current stack which
+    /// last touched the same lines, and restack their descendants.
+    ///
+    /// Changes which can't be attributed to a single commit in the stack are
+    /// left staged.
+    Absorb,
+
+    /// Combine a linear range of commits into a single commit, and restack
+    /// their descendants on top of it.
+    Fold {
+        /// The commits to fold. Either a range like `abc123..def456`, which
+        /// folds the commits after `abc123` up to and including `def456`, or a
+        /// single commit, which folds that commit and its descendants up to and
+        /// including the current commit.
+        range: String,
+
+        /// Use only the message of the first commit in the range, rather than
+        /// concatenating the messages of all the folded commits.
+        #[structopt(long = "--first-message")]
+        first_message: bool,
+    },
+
+    /// Interactively split a commit into two commits, and restack its
+    /// descendants on top of the second commit.
+    Split {
+        /// The commit to split. If not provided, defaults to the current
+        /// commit.
+        ///
+        /// Can either be a hash, like `abc123`, or a ref-spec, like `HEAD^`.
+        commit: Option<String>,
+
+        /// The message for the first commit. If not provided, opens an editor
+        /// to edit the original commit's message. The second commit keeps the
+        /// original message.
+        #[structopt(short = "-m", long = "--message")]
+        message: Option<String>,
+    },
+
     /// Browse or return to a previous state of the repository.
-    Undo,
+    Undo {
+        /// Undo only the events in the given transaction, leaving later
+        /// transactions in place.
+        #[structopt(long = "--transaction")]
+        transaction: Option<branchless::core::eventlog::EventTransactionId>,
+
+        /// Stash any uncommitted changes before undoing, instead of refusing
+        /// to proceed.
+        #[structopt(long = "--stash")]
+        stash: bool,
+    },
+
+    /// Record which of the provided commits were rewritten or hidden, so that
+    /// they can be hidden in other clones of the repository.
+    ///
+    /// The obsolescence markers are stored at `refs/branchless/obsmarkers`,
+    /// which can then be pushed to a remote.
+    PublishObsmarkers {
+        /// Zero or more commits to publish obsolescence markers for. If none
+        /// are provided, publishes markers for all obsolete commits.
+        ///
+        /// Can either be hashes, like `abc123`, or ref-specs, like `HEAD^`.
+        commits: Vec<String>,
+    },
+
+    /// Hide the commits which were rewritten or hidden in another clone of
+    /// the repository.
+    ///
+    /// Run `git fetch <remote> refs/branchless/obsmarkers` first to fetch the
+    /// obsolescence markers published with `publish-obsmarkers`.
+    ///
+    /// To ingest markers automatically whenever fetching from a remote, add
+    /// the fetch refspec
+    /// `+refs/branchless/obsmarkers:refs/branchless/remote-obsmarkers/<remote>`
+    /// to the remote's configuration.
+    IngestObsmarkers {
+        /// The commit storing the obsolescence markers.
+        #[structopt(default_value = "FETCH_HEAD")]
+        source: String,
+    },
 
     /// Run internal garbage collection.
-    Gc,
+    Gc {
+        /// Report what would be collected, without deleting any references,
+        /// compacting any events, or pruning the merge-base cache.
+        #[structopt(long = "--dry-run")]
+        dry_run: bool,
+
+        /// Remove all entries from the merge-base cache.
+        #[structopt(long = "--clear-cache")]
+        clear_cache: bool,
+    },
 
     /// Wrap a Git command inside a branchless transaction.
     Wrap {
@@ -187,20 +338,69 @@ fn main() -> anyhow::Result<()> {
             dest,
             base,
             force_on_disk,
-        } => branchless::commands::r#move::r#move(
-            &git_executable,
-            source,
-            dest,
-            base,
-            force_on_disk,
-        )?,
+            insert,
+            exact,
+            r#continue,
+            abort,
+        } => {
+            if r#continue {
+                branchless::commands::r#move::move_continue(&git_executable)?
+            } else if abort {
+                branchless::commands::r#move::move_abort(&git_executable)?
+            } else {
+                branchless::commands::r#move::r#move(
+                    &git_executable,
+                    source,
+                    dest,
+                    base,
+                    force_on_disk,
+                    insert,
+                    exact,
+                )?
+            }
+        }
 
         Opts::Restack => branchless::commands::restack::restack(&git_executable)?,
 
-        Opts::Undo => branchless::commands::undo::undo(&git_executable)?,
+        Opts::Amend => branchless::commands::amend::amend(&git_executable)?,
+
+        Opts::Reword { commit, message } => {
+            branchless::commands::reword::reword(&git_executable, commit, message)?
+        }
+
+        Opts::Absorb => branchless::commands::absorb::absorb(&git_executable)?,
+
+        Opts::Fold {
+            range,
+            first_message,
+        } => branchless::commands::fold::fold(&git_executable, range, first_message)?,
+
+        Opts::Split { commit, message } => {
+            branchless::commands::split::split(&git_executable, commit, message)?
+        }
+
+        Opts::Undo { transaction, stash } => {
+            branchless::commands::undo::undo(&git_executable, transaction, stash)?
+        }
+
+        Opts::PublishObsmarkers { commits } => {
+            branchless::commands::obsmarkers::publish_obsmarkers(commits)?
+        }
+
+        Opts::IngestObsmarkers { source } => {
+            branchless::commands::obsmarkers::ingest_obsmarkers(source)?
+        }
+
+        Opts::Gc {
+            dry_run,
+            clear_cache,
+        } => {
+            branchless::commands::gc::gc(dry_run, clear_cache, true)?;
+            0
+        }
 
-        Opts::Gc | Opts::HookPreAutoGc => {
-            branchless::commands::gc::gc()?;
+        Opts::HookPreAutoGc => {
+            branchless::commands::gc::gc(false, false, false)?;
             0
         }
 
@@ -6,6 +6,7 @@ use std::io::{stderr, stdout, Write};
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::Context;
 use fn_error_context::context;
@@ -123,17 +124,155 @@ pub fn get_repo() -> anyhow::Result<git2::Repository> {
     Ok(repository)
 }
 
+/// Get the directory which is shared between all the worktrees of the
+/// repository.
+///
+/// For a linked worktree, `repo.path()` is the worktree's own directory (such
+/// as `.git/worktrees/<name>`), which contains a `commondir` file pointing to
+/// the shared directory. For the main worktree, this is just `repo.path()`.
+#[context("Getting common directory for repo")]
+pub fn get_repo_common_dir(repo: &git2::Repository) -> anyhow::Result<PathBuf> {
+    let commondir_path = repo.path().join("commondir");
+    if !commondir_path.exists() {
+        return Ok(repo.path().to_path_buf());
+    }
+    let common_dir = std::fs::read_to_string(&commondir_path)
+        .with_context(|| format!("Reading {:?}", &commondir_path))?;
+    let common_dir = repo.path().join(common_dir.trim_end());
+    let common_dir = std::fs::canonicalize(&common_dir)
+        .with_context(|| format!("Resolving common directory {:?}", &common_dir))?;
+    Ok(common_dir)
+}
+
+/// Get the name of the reference under which updates to `HEAD` in the current
+/// worktree are recorded in the event log.
+///
+/// This is `HEAD` for the main worktree, and `worktrees/<name>/HEAD` for a
+/// linked worktree, which is also how Git refers to the `HEAD` of another
+/// worktree.
+#[context("Getting name of HEAD reference for worktree")]
+pub fn get_worktree_head_ref_name(repo: &git2::Repository) -> anyhow::Result<String> {
+    if !repo.is_worktree() {
+        return Ok("HEAD".to_string());
+    }
+    let worktree_name = repo
+        .path()
+        .file_name()
+        .and_then(|worktree_name| worktree_name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Could not get worktree name from {:?}", repo.path()))?;
+    Ok(format!("worktrees/{}/HEAD", worktree_name))
+}
+
+/// Get the OIDs that the `HEAD`s of the repository's other worktrees point to.
+///
+/// Worktrees which have been deleted without being pruned, or whose `HEAD` is
+/// unborn, are skipped.
+#[context("Getting HEAD OIDs of other worktrees")]
+pub fn get_other_worktree_head_oids(repo: &git2::Repository) -> anyhow::Result<HashSet<git2::Oid>> {
+    let mut result = HashSet::new();
+    if repo.is_worktree() {
+        let main_repo = git2::Repository::open(get_repo_common_dir(repo)?)?;
+        if !main_repo.is_bare() {
+            result.extend(get_head_oid(&main_repo)?);
+        }
+    }
+
+    let head_ref_name = get_worktree_head_ref_name(repo)?;
+    for worktree_name in repo.worktrees()?.iter().flatten() {
+        if format!("worktrees/{}/HEAD", worktree_name) == head_ref_name {
+            continue;
+        }
+        let worktree = repo.find_worktree(worktree_name)?;
+        if worktree.validate().is_err() {
+            continue;
+        }
+        let worktree_repo = git2::Repository::open_from_worktree(&worktree)?;
+        result.extend(get_head_oid(&worktree_repo)?);
+    }
+    Ok(result)
+}
+
+/// How long to wait for another process to release its lock on the database
+/// before giving up on a query.
+const DB_BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times to retry a database operation which failed because the
+/// database was busy.
+const DB_BUSY_MAX_RETRIES: u32 = 5;
+
 /// Get the connection to the SQLite database for this repository.
 #[context("Getting connection to SQLite database for repo")]
 pub fn get_db_conn(repo: &git2::Repository) -> anyhow::Result<rusqlite::Connection> {
-    let dir = repo.path().join("branchless");
+    // Share the database between all worktrees, so that they see the same
+    // history.
+    let dir = get_repo_common_dir(repo)?.join("branchless");
     std::fs::create_dir_all(&dir).with_context(|| "Creating .git/branchless dir")?;
     let path = dir.join("db.sqlite3");
     let conn = rusqlite::Connection::open(&path)
         .with_context(|| format!("Opening database connection at {:?}", &path))?;
+
+    // Hooks may run concurrently (e.g. during a rebase, or when an IDE
+    // fetches in the background), so wait for other processes to release
+    // their locks rather than failing immediately. Write-ahead logging
+    // additionally lets readers proceed while another process is writing.
+    conn.busy_timeout(DB_BUSY_TIMEOUT)
+        .with_context(|| "Setting database busy timeout")?;
+    retry_on_db_busy(|| {
+        let _journal_mode: String =
+            conn.query_row("PRAGMA journal_mode = WAL", rusqlite::params![], |row| {
+                row.get(0)
+            })?;
+        Ok(())
+    })
+    .with_context(|| "Enabling write-ahead logging")?;
+
     Ok(conn)
 }
 
+fn is_db_busy_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseBusy,
+                    ..
+                } | rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseLocked,
+                    ..
+                },
+                _,
+            ))
+        )
+    })
+}
+
+/// Run a database operation, retrying it with backoff if it fails because
+/// another process is holding a lock on the database.
+///
+/// The busy timeout set in `get_db_conn` handles most contention, but SQLite
+/// returns a busy error immediately (without waiting) in cases where waiting
+/// could deadlock, so the whole operation has to be retried.
+///
+/// Args:
+/// * `f`: The database operation to run. It should be safe to run more than
+///   once (e.g. by doing its work in a single transaction).
+///
+/// Returns: The result of the first successful attempt, or the error from the
+/// last attempt.
+pub fn retry_on_db_busy<T>(mut f: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let mut num_retries = 0;
+    loop {
+        match f() {
+            Err(err) if num_retries < DB_BUSY_MAX_RETRIES && is_db_busy_error(&err) => {
+                num_retries += 1;
+                std::thread::sleep(Duration::from_millis(50 * 2_u64.pow(num_retries)));
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Path to the `git` executable on disk to be executed.
 #[derive(Clone, Debug)]
 pub struct GitExecutable(pub PathBuf);
@@ -0,0 +1,170 @@
+use branchless::testing::{with_git, GitRunOptions};
+
+#[test]
+fn test_absorb_into_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file_with_contents("test1", 1, "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n")?;
+        git.run(&["checkout", "-b", "foo"])?;
+        git.commit_file("test2", 2)?;
+        git.commit_file_with_contents("test1", 3, "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n")?;
+
+        git.write_file("test1", "0\n1\ntwo\n3\n4\n5\n6\n7\n8\n9\nTEN\n")?;
+        git.write_file("test3", "test3 contents\n")?;
+        git.run(&["add", "."])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["absorb"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Absorbing test1.txt @@ -3 +3 @@ into: 0206d7ba create test1.txt
+            Absorbing test1.txt @@ -11 +11 @@ into: fc957326 create test1.txt
+            Could not absorb test3.txt @@ -0,0 +1 @@, leaving it staged.
+            Attempting rebase in-memory...
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o eb8f5b6f create test1.txt
+            |
+            o f8f213da create test2.txt
+            |
+            @ 235b6e55 (foo) create test1.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["show", "--format=%s", "HEAD~2"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            create test1.txt
+
+            diff --git a/test1.txt b/test1.txt
+            new file mode 100644
+            index 0000000..ed75e4e
+            --- /dev/null
+            +++ b/test1.txt
+            @@ -0,0 +1,10 @@
+            +1
+            +two
+            +3
+            +4
+            +5
+            +6
+            +7
+            +8
+            +9
+            +10
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["show", "--format=%s", "HEAD"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            create test1.txt
+
+            diff --git a/test1.txt b/test1.txt
+            index ed75e4e..b672927 100644
+            --- a/test1.txt
+            +++ b/test1.txt
+            @@ -1,3 +1,4 @@
+            +0
+             1
+             two
+             3
+            @@ -7,4 +8,4 @@ two
+             7
+             8
+             9
+            -10
+            +TEN
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            ## foo
+            A  test3.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_absorb_merge_conflict() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+
+        git.detach_head()?;
+        git.commit_file_with_contents("test1", 1, "1\n2\n3\n4\n5\n")?;
+        git.commit_file_with_contents("test1", 2, "1\n2\nthree\n4\n5\n")?;
+
+        git.write_file("test1", "1\n2\nthree\nfour\n5\n")?;
+        git.run(&["add", "."])?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["absorb"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Absorbing test1.txt @@ -4 +4 @@ three into: e1f3d655 create test1.txt
+            Attempting rebase in-memory...
+            Merge conflict. The conflicting commit was: ab19edc7 create test1.txt
+            Could not absorb the staged changes, so no commits were changed.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o e1f3d655 create test1.txt
+            |
+            @ ab19edc7 create test1.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            M  test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_absorb_no_staged_changes() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("test1", "unstaged contents\n")?;
+
+        {
+            let (stdout, _stderr) = git.run(&["absorb"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            There are no staged changes to absorb.
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,169 @@
+use branchless::testing::{with_git, GitRunOptions};
+
+#[test]
+fn test_amend_with_descendants() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.write_file("test1", "amended contents\n")?;
+
+        {
+            let (stdout, _stderr) = git.run(&["amend"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Amended as: 88d3ff26 create test1.txt
+            Attempting rebase in-memory...
+            branchless: processing 1 rewritten commit
+            In-memory rebase succeeded.
+            branchless: moved branch foo from 96d1c37a3d4363611c49f7e52186e189a04c531f to 353605ff96049321ae72a70a8b41c72c35836cd3
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            @ 88d3ff26 create test1.txt
+            |
+            o 353605ff (foo) create test2.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short"])?;
+            insta::assert_snapshot!(stdout, @"");
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_amend_with_descendants_hook_failure() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+        let hook_path = git
+            .repo_path
+            .join(".git")
+            .join("hooks")
+            .join("post-rewrite");
+        let hook_contents = std::fs::read_to_string(&hook_path)?;
+        std::fs::write(&hook_path, format!("{}exit 1\n", hook_contents))?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.write_file("test1", "amended contents\n")?;
+
+        {
+            // The descendants were still rebased, so the branches are still
+            // restacked, even though the command fails.
+            let (stdout, _stderr) = git.run_with_options(
+                &["amend"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Amended as: 88d3ff26 create test1.txt
+            Attempting rebase in-memory...
+            branchless: processing 1 rewritten commit
+            branchless: the post-rewrite hook failed with exit status: 1
+            In-memory rebase succeeded, but one or more hooks failed.
+            branchless: moved branch foo from 96d1c37a3d4363611c49f7e52186e189a04c531f to 353605ff96049321ae72a70a8b41c72c35836cd3
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            @ 88d3ff26 create test1.txt
+            |
+            o 353605ff (foo) create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_amend_with_conflicting_descendants() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file_with_contents("test1", 2, "test1 contents v2\n")?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.write_file("test1", "amended contents\n")?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["amend"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Amended as: 88d3ff26 create test1.txt
+            Attempting rebase in-memory...
+            Merge conflict. The conflicting commit was: fe3cfac1 create test1.txt
+            branchless: run 'git restack' to restack the remaining abandoned commits
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | x 62fc20d2 (rewritten as 88d3ff26) create test1.txt
+            | |
+            | o fe3cfac1 create test1.txt
+            |
+            @ 88d3ff26 create test1.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short"])?;
+            insta::assert_snapshot!(stdout, @"");
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_amend_no_changes() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["amend"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            There are no changes to tracked files to amend.
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,209 @@
+use branchless::testing::{with_git, GitRunOptions};
+
+#[test]
+fn test_fold_mid_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "-b", "foo"])?;
+        git.commit_file("test3", 3)?;
+        git.commit_file("test4", 4)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["fold", "HEAD~3..HEAD~1"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Folded 2 commits into: 88439b08 create test2.txt
+            Attempting rebase in-memory...
+            branchless: processing 1 rewritten commit
+            In-memory rebase succeeded.
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 62fc20d2 create test1.txt
+            |
+            o 88439b08 create test2.txt
+            |
+            @ c8978073 (foo) create test4.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["show", "--name-status", "--format=%B", "HEAD~"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            create test2.txt
+
+            create test3.txt
+
+
+            A	test2.txt
+            A	test3.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            ## foo
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fold_to_head_first_message() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["fold", "--first-message", "HEAD~"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Folded 2 commits into: e9f8a2b1 create test1.txt
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            @ e9f8a2b1 create test1.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short"])?;
+            insta::assert_snapshot!(stdout, @"");
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fold_rejects_invalid_commits() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        git.commit_file("test2", 2)?;
+        let test3_oid = git.commit_file("test3", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test4", 4)?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["fold", "HEAD~2..HEAD"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commits on the main branch cannot be folded: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["fold", &format!("{}..{}", test3_oid, test1_oid)],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            The commits in 70deb1e28791d8e7dd5a1f0c871a51b91282562f..62fc20d2a290daea0d52bdc2ed2ad4be6491010e do not form a linear range
+            "###);
+        }
+
+        git.run(&["checkout", &test3_oid.to_string()])?;
+        git.run(&["hide", &test3_oid.to_string()])?;
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["fold", "HEAD^"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commit is not visible, so it cannot be folded: 70deb1e28791d8e7dd5a1f0c871a51b91282562f
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 62fc20d2 create test1.txt
+            |\
+            | o 96d1c37a create test2.txt
+            | |
+            | % 70deb1e2 (manually hidden) create test3.txt
+            |
+            O bf0d52a6 (master) create test4.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_fold_rejects_commit_not_in_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        git.commit_file("test3", 3)?;
+        git.commit_file("test4", 4)?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["fold", &test2_oid.to_string()],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            The commits in fe65c1fe15584744e649b2c79d4cf9b0d878f92e do not form a linear range
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | o fe65c1fe create test2.txt
+            |
+            o 98b9119d create test3.txt
+            |
+            @ 2b633ed7 create test4.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -70,6 +70,11 @@ fn test_old_git_version_warning() -> anyhow::Result<()> {
             Installing alias (non-global): git restack -> git branchless restack
             Installing alias (non-global): git undo -> git branchless undo
             Installing alias (non-global): git move -> git branchless move
+            Installing alias (non-global): git amend -> git branchless amend
+            Installing alias (non-global): git reword -> git branchless reword
+            Installing alias (non-global): git split -> git branchless split
+            Installing alias (non-global): git fold -> git branchless fold
+            Installing alias (non-global): git absorb -> git branchless absorb
             Warning: the branchless workflow's `git undo` command requires Git
             v2.29 or later, but your Git version is: <git version output>
 
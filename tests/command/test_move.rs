@@ -309,6 +309,7 @@ fn test_move_merge_conflict() -> anyhow::Result<()> {
             branchless: <git-executable> rebase --continue
             CONFLICT (add/add): Merge conflict in conflict.txt
             Auto-merging conflict.txt
+            The rebase was interrupted. Resolve any merge conflicts, then run `git move --continue` to resume it, or `git move --abort` to cancel it.
             "###);
         }
 
@@ -377,3 +378,692 @@ fn test_move_base() -> anyhow::Result<()> {
 // TODO: if on a rewritten commit before rebase, check out the new commit afterwards.
 // TODO: move branches after in-memory rebase. Make sure to call reference-transaction hook.
 // TODO: don't re-apply already-applied commits
+
+#[test]
+fn test_move_insert_in_memory() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        let test4_oid = git.commit_file("test4", 4)?;
+
+        {
+            let (stdout, _stderr) = git.run(&[
+                "move",
+                "--insert",
+                "-s",
+                &test4_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Attempting rebase in-memory...
+            branchless: processing 3 rewritten commits
+            In-memory rebase succeeded.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | o 62fc20d2 create test1.txt
+            | |
+            | o bf0d52a6 create test4.txt
+            | |
+            | o 44352d00 create test2.txt
+            | |
+            | o cf5eb244 create test3.txt
+            |
+            % 8f7aef57 (rewritten as bf0d52a6) create test4.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_insert_on_disk() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        let test4_oid = git.commit_file("test4", 4)?;
+
+        git.run(&[
+            "move",
+            "--on-disk",
+            "--insert",
+            "-s",
+            &test4_oid.to_string(),
+            "-d",
+            &test1_oid.to_string(),
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 62fc20d2 create test1.txt
+            |
+            @ ce1db9ed create test4.txt
+            |
+            o 4d5bb4f8 create test2.txt
+            |
+            o 50fdf2de create test3.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_insert_requires_linear_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", &test1_oid.to_string()])?;
+        git.commit_file("test3", 3)?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &[
+                    "move",
+                    "--insert",
+                    "-s",
+                    &test1_oid.to_string(),
+                    "-d",
+                    "master",
+                ],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            The --insert option can only be used to move a linear stack of commits.
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_exact_mid_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        let test4_oid = git.commit_file("test4", 4)?;
+
+        git.run(&[
+            "move",
+            "--exact",
+            &test2_oid.to_string(),
+            "-d",
+            &test4_oid.to_string(),
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 62fc20d2 create test1.txt
+            |\
+            | x 96d1c37a (rewritten as 5a436edd) create test2.txt
+            | |
+            | x 70deb1e2 (rewritten as 4838e49b) create test3.txt
+            | |
+            | % 355e173b (rewritten as a2482074) create test4.txt
+            |
+            o 4838e49b create test3.txt
+            |
+            o a2482074 create test4.txt
+            |
+            o 5a436edd create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_exact_non_contiguous() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        let test4_oid = git.commit_file("test4", 4)?;
+        git.commit_file("test5", 5)?;
+
+        git.run(&[
+            "move",
+            "-x",
+            &test2_oid.to_string(),
+            "-x",
+            &test4_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | o 62fc20d2 create test1.txt
+            | |\
+            | | x 96d1c37a (rewritten as fe65c1fe) create test2.txt
+            | | |
+            | | x 70deb1e2 (rewritten as 4838e49b) create test3.txt
+            | | |
+            | | x 355e173b (rewritten as 4d4b1fe5) create test4.txt
+            | | |
+            | | % f81d55c0 (rewritten as b1f9efa0) create test5.txt
+            | |
+            | o 4838e49b create test3.txt
+            | |
+            | o b1f9efa0 create test5.txt
+            |
+            o fe65c1fe create test2.txt
+            |
+            o 4d4b1fe5 create test4.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_exact_on_disk() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+        let test4_oid = git.commit_file("test4", 4)?;
+
+        git.run(&[
+            "move",
+            "--on-disk",
+            "--exact",
+            &test2_oid.to_string(),
+            "-d",
+            &test4_oid.to_string(),
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 62fc20d2 create test1.txt
+            |
+            o cade1d30 create test3.txt
+            |
+            o 5bb72580 create test4.txt
+            |
+            @ e9d2e578 create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_multiple_sources() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test4", 4)?;
+
+        git.run(&[
+            "move",
+            "-s",
+            &test1_oid.to_string(),
+            "-s",
+            &test3_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            @ 8f7aef57 (master) create test4.txt
+            |\
+            | o 6c398dae create test1.txt
+            | |
+            | o d166405f create test2.txt
+            |
+            o 9799dd98 create test3.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_multiple_bases() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test4", 4)?;
+        git.detach_head()?;
+
+        git.run(&[
+            "move",
+            "--on-disk",
+            "-b",
+            &test2_oid.to_string(),
+            "-b",
+            &test3_oid.to_string(),
+            "-d",
+            "master",
+        ])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 8f7aef57 (master) create test4.txt
+            |\
+            | @ 362ac2c2 create test3.txt
+            |
+            o ebd33ec8 create test1.txt
+            |
+            o 3359247b create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_continue() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        let base_oid = git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        let other_oid = git.commit_file("test2", 2)?;
+        git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+        git.run(&["checkout", &base_oid.to_string()])?;
+        git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+        git.run_with_options(
+            &["move", "-s", &other_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+
+        git.resolve_file("conflict", "resolved")?;
+        {
+            let (stdout, _stderr) = git.run(&["move", "--continue"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            branchless: <git-executable> rebase --continue
+            [detached HEAD c494c9c] create conflict.txt
+             1 file changed, 1 insertion(+), 1 deletion(-)
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 62fc20d2 (master) create test1.txt
+            |
+            o 60f7e990 create conflict.txt
+            |
+            o 6a025cbb create test2.txt
+            |
+            @ c494c9cc create conflict.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["move", "--continue"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            There is no interrupted move to continue.
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_abort() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        let base_oid = git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        let other_oid = git.commit_file("test2", 2)?;
+        git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+        git.run(&["checkout", &base_oid.to_string()])?;
+        git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+        git.run_with_options(
+            &["move", "-s", &other_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+
+        {
+            let (stdout, _stderr) = git.run(&["move", "--abort"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            branchless: <git-executable> rebase --abort
+            Aborted the move.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 62fc20d2 (master) create test1.txt
+            |\
+            | o 96d1c37a create test2.txt
+            | |
+            | o c6f40a7e create conflict.txt
+            |
+            @ 60f7e990 create conflict.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["for-each-ref", "refs/rewritten/"])?;
+            insta::assert_snapshot!(stdout, @"");
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_abort_ignores_unrelated_rebase() -> anyhow::Result<()> {
+    with_git(|git| {
+        if has_git_v2_24_bug(&git)? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        let base_oid = git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        let other_oid = git.commit_file("test2", 2)?;
+        let conflict1_oid = git.commit_file_with_contents("conflict", 3, "conflict 1\n")?;
+        git.run(&["checkout", &base_oid.to_string()])?;
+        let conflict2_oid = git.commit_file_with_contents("conflict", 3, "conflict 2\n")?;
+
+        git.run_with_options(
+            &["move", "-s", &other_oid.to_string()],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        git.run(&["rebase", "--abort"])?;
+
+        git.run_with_options(
+            &[
+                "rebase",
+                &conflict2_oid.to_string(),
+                &conflict1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["move", "--abort"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            There is no interrupted move to abort.
+            "###);
+        }
+        assert!(git.repo_path.join(".git").join("rebase-merge").exists());
+
+        Ok(())
+    })
+}
+
+fn append_to_hook(git: &Git, hook_name: &str, contents: &str) -> anyhow::Result<()> {
+    let hook_path = git.repo_path.join(".git").join("hooks").join(hook_name);
+    let hook_contents = std::fs::read_to_string(&hook_path)?;
+    std::fs::write(&hook_path, format!("{}{}\n", hook_contents, contents))?;
+    Ok(())
+}
+
+#[test]
+fn test_move_in_memory_hooks_all() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.inMemory.hooks", "all"])?;
+        append_to_hook(
+            &git,
+            "post-commit",
+            r#"echo "user post-commit hook: $BRANCHLESS_IN_MEMORY_COMMIT_OID""#,
+        )?;
+
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+
+        {
+            let (stdout, _stderr) = git.run(&[
+                "move",
+                "-s",
+                &test3_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Attempting rebase in-memory...
+            user post-commit hook: 4838e49b08954becdd17c0900c1179c2c654c627
+            branchless: processing 1 rewritten commit
+            In-memory rebase succeeded.
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_in_memory_hooks_none() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.inMemory.hooks", "none"])?;
+
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+
+        {
+            let (stdout, _stderr) = git.run(&[
+                "move",
+                "-s",
+                &test3_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Attempting rebase in-memory...
+            In-memory rebase succeeded.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 62fc20d2 create test1.txt
+            |\
+            | o 4838e49b create test3.txt
+            |
+            O 96d1c37a (master) create test2.txt
+            |
+            % 70deb1e2 (rewritten as 4838e49b) create test3.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_in_memory_hook_failure() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        append_to_hook(&git, "post-rewrite", "exit 1")?;
+
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &[
+                    "move",
+                    "-s",
+                    &test3_oid.to_string(),
+                    "-d",
+                    &test1_oid.to_string(),
+                ],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Attempting rebase in-memory...
+            branchless: processing 1 rewritten commit
+            branchless: the post-rewrite hook failed with exit status: 1
+            In-memory rebase succeeded, but one or more hooks failed.
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_move_in_memory_hooks_invalid_policy() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.inMemory.hooks", "sometimes"])?;
+
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.detach_head()?;
+        let test3_oid = git.commit_file("test3", 3)?;
+
+        let (_stdout, stderr) = git.run_with_options(
+            &[
+                "move",
+                "-s",
+                &test3_oid.to_string(),
+                "-d",
+                &test1_oid.to_string(),
+            ],
+            &GitRunOptions {
+                expected_exit_code: 1,
+                ..Default::default()
+            },
+        )?;
+        assert!(stderr.contains("Invalid value for branchless.inMemory.hooks"));
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            O 96d1c37a (master) create test2.txt
+            |
+            @ 70deb1e2 create test3.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,263 @@
+use branchless::testing::{get_git_executable, Git, GitInitOptions, GitRunOptions};
+use branchless::util::GitExecutable;
+
+#[test]
+fn test_share_obsmarkers() -> anyhow::Result<()> {
+    let git_executable = get_git_executable()?;
+    let git_executable = GitExecutable(git_executable);
+    let temp_dir = tempfile::tempdir()?;
+    let original_repo_path = temp_dir.path().join("original");
+    std::fs::create_dir(&original_repo_path)?;
+    let original_repo = Git::new(original_repo_path, git_executable.clone());
+    let cloned_repo_path = temp_dir.path().join("cloned");
+    let cloned_repo = Git::new(cloned_repo_path, git_executable);
+
+    {
+        std::env::set_current_dir(&original_repo.repo_path)?;
+        let git = original_repo.clone();
+        git.init_repo()?;
+        git.run(&["checkout", "-b", "feature"])?;
+        git.commit_file("test1", 1)?;
+        git.run_with_options(
+            &[
+                "clone",
+                original_repo.repo_path.to_str().unwrap(),
+                cloned_repo.repo_path.to_str().unwrap(),
+            ],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    {
+        std::env::set_current_dir(&cloned_repo.repo_path)?;
+        let git = cloned_repo.clone();
+        git.init_repo_with_options(&GitInitOptions {
+            make_initial_commit: false,
+            ..Default::default()
+        })?;
+        git.run(&["checkout", "master"])?;
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (feature) create test1.txt
+        "###);
+    }
+
+    {
+        std::env::set_current_dir(&original_repo.repo_path)?;
+        let git = original_repo.clone();
+        git.run(&["commit", "--amend", "-m", "amend test1"])?;
+        let (stdout, _stderr) = git.run(&["branchless", "publish-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Published 1 new obsolescence marker to refs/branchless/obsmarkers.
+        To share them, run: git push <remote> refs/branchless/obsmarkers
+        "###);
+
+        // Publishing the same markers again doesn't add anything.
+        let (stdout, _stderr) = git.run(&["branchless", "publish-obsmarkers", "HEAD@{1}"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Published 0 new obsolescence markers to refs/branchless/obsmarkers.
+        To share them, run: git push <remote> refs/branchless/obsmarkers
+        "###);
+    }
+
+    {
+        std::env::set_current_dir(&cloned_repo.repo_path)?;
+        let git = cloned_repo.clone();
+        git.run_with_options(
+            &["fetch", "origin", "refs/branchless/obsmarkers"],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+        // The amended commit hasn't been fetched yet, so the marker can't be
+        // recorded.
+        let (stdout, _stderr) = git.run(&["branchless", "ingest-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ingested 0 new obsolescence markers from FETCH_HEAD.
+        Skipped 1 obsolescence marker for commits rewritten into commits which haven't been fetched. Fetch them, then run: git branchless ingest-obsmarkers
+        "###);
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (feature) create test1.txt
+        "###);
+
+        git.run_with_options(
+            &["fetch", "origin", "refs/branchless/obsmarkers", "feature"],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+        let (stdout, _stderr) = git.run(&["branchless", "ingest-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ingested 1 new obsolescence marker from FETCH_HEAD.
+        "###);
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |\
+        | o 9e8dbe91 amend test1
+        |
+        x 62fc20d2 (rewritten as 9e8dbe91) (feature) create test1.txt
+        "###);
+
+        let (stdout, _stderr) = git.run(&["branchless", "ingest-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ingested 0 new obsolescence markers from FETCH_HEAD.
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ingest_obsmarkers_keeps_local_unhide() -> anyhow::Result<()> {
+    let git_executable = get_git_executable()?;
+    let git_executable = GitExecutable(git_executable);
+    let temp_dir = tempfile::tempdir()?;
+    let original_repo_path = temp_dir.path().join("original");
+    std::fs::create_dir(&original_repo_path)?;
+    let original_repo = Git::new(original_repo_path, git_executable.clone());
+    let cloned_repo_path = temp_dir.path().join("cloned");
+    let cloned_repo = Git::new(cloned_repo_path, git_executable);
+
+    {
+        std::env::set_current_dir(&original_repo.repo_path)?;
+        let git = original_repo.clone();
+        git.init_repo()?;
+        git.run(&["checkout", "-b", "feature"])?;
+        git.commit_file("test1", 1)?;
+        git.run_with_options(
+            &[
+                "clone",
+                original_repo.repo_path.to_str().unwrap(),
+                cloned_repo.repo_path.to_str().unwrap(),
+            ],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["branch", "-D", "feature"])?;
+        git.run(&["hide", "62fc20d2"])?;
+        git.run(&["branchless", "publish-obsmarkers", "62fc20d2"])?;
+    }
+
+    {
+        std::env::set_current_dir(&cloned_repo.repo_path)?;
+        let git = cloned_repo.clone();
+        git.init_repo_with_options(&GitInitOptions {
+            make_initial_commit: false,
+            ..Default::default()
+        })?;
+        git.run(&["checkout", "master"])?;
+        git.run_with_options(
+            &["fetch", "origin", "refs/branchless/obsmarkers"],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+        let (stdout, _stderr) = git.run(&["branchless", "ingest-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ingested 1 new obsolescence marker from FETCH_HEAD.
+        "###);
+
+        git.run(&["unhide", "62fc20d2"])?;
+        let (stdout, _stderr) = git.run(&["branchless", "ingest-obsmarkers"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        Ingested 0 new obsolescence markers from FETCH_HEAD.
+        "###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |
+        o 62fc20d2 (feature) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ingest_obsmarkers_on_fetch() -> anyhow::Result<()> {
+    let git_executable = get_git_executable()?;
+    let git_executable = GitExecutable(git_executable);
+    let temp_dir = tempfile::tempdir()?;
+    let original_repo_path = temp_dir.path().join("original");
+    std::fs::create_dir(&original_repo_path)?;
+    let original_repo = Git::new(original_repo_path, git_executable.clone());
+    let cloned_repo_path = temp_dir.path().join("cloned");
+    let cloned_repo = Git::new(cloned_repo_path, git_executable);
+
+    {
+        std::env::set_current_dir(&original_repo.repo_path)?;
+        let git = original_repo.clone();
+        git.init_repo()?;
+        git.run(&["checkout", "-b", "feature"])?;
+        git.commit_file("test1", 1)?;
+        git.run_with_options(
+            &[
+                "clone",
+                original_repo.repo_path.to_str().unwrap(),
+                cloned_repo.repo_path.to_str().unwrap(),
+            ],
+            &GitRunOptions {
+                use_system_git: true,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    {
+        std::env::set_current_dir(&cloned_repo.repo_path)?;
+        let git = cloned_repo.clone();
+        git.init_repo_with_options(&GitInitOptions {
+            make_initial_commit: false,
+            ..Default::default()
+        })?;
+        git.run(&["checkout", "master"])?;
+        git.run(&[
+            "config",
+            "--add",
+            "remote.origin.fetch",
+            "+refs/branchless/obsmarkers:refs/branchless/remote-obsmarkers/origin",
+        ])?;
+    }
+
+    {
+        std::env::set_current_dir(&original_repo.repo_path)?;
+        let git = original_repo.clone();
+        git.run(&["commit", "--amend", "-m", "amend test1"])?;
+        git.run(&["branchless", "publish-obsmarkers"])?;
+    }
+
+    {
+        std::env::set_current_dir(&cloned_repo.repo_path)?;
+        let git = cloned_repo.clone();
+        let (_stdout, stderr) = git.run(&["fetch", "origin"])?;
+        assert!(stderr.contains(
+            "branchless: ingested 1 obsolescence marker from refs/branchless/remote-obsmarkers/origin"
+        ));
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+        @ f777ecc9 (master) create initial.txt
+        |\
+        | o 9e8dbe91 amend test1
+        |
+        x 62fc20d2 (rewritten as 9e8dbe91) (feature) create test1.txt
+        "###);
+    }
+
+    Ok(())
+}
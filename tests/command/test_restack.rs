@@ -1,4 +1,6 @@
+use branchless::core::eventlog::{Event, EventLogDb};
 use branchless::testing::{with_git, GitRunOptions};
+use branchless::util::get_db_conn;
 
 /// Remove some of the output from `git rebase`, as it seems to be
 /// non-deterministic as to whether or not it appears.
@@ -114,7 +116,7 @@ fn test_move_abandoned_branch() -> anyhow::Result<()> {
             let stdout = remove_rebase_lines(stdout);
             insta::assert_snapshot!(stdout, @r###"
             branchless: no more abandoned commits to restack
-            branchless: <git-executable> branch -f master 662b451fb905b92404787e024af717ced49e3045
+            branchless: moved branch master from 62fc20d2a290daea0d52bdc2ed2ad4be6491010e to 662b451fb905b92404787e024af717ced49e3045
             branchless: no more abandoned branches to restack
             branchless: <git-executable> checkout 662b451fb905b92404787e024af717ced49e3045
             :
@@ -153,7 +155,7 @@ fn test_amended_initial_commit() -> anyhow::Result<()> {
             insta::assert_snapshot!(stdout, @r###"
             branchless: <git-executable> rebase f777ecc9b0db5ed372b2615695191a8a17f79f24 62fc20d2a290daea0d52bdc2ed2ad4be6491010e --onto 9a9f929a0d4f052ff5d58bedd97b2f761120f8ed --committer-date-is-author-date
             branchless: no more abandoned commits to restack
-            branchless: <git-executable> branch -f master 6d85943be6d6e5941d5479f1059d02ebf1c8e307
+            branchless: moved branch master from 62fc20d2a290daea0d52bdc2ed2ad4be6491010e to 6d85943be6d6e5941d5479f1059d02ebf1c8e307
             branchless: no more abandoned branches to restack
             branchless: <git-executable> checkout 9a9f929a0d4f052ff5d58bedd97b2f761120f8ed
             @ 9a9f929a new initial commit
@@ -184,7 +186,7 @@ fn test_restack_amended_master() -> anyhow::Result<()> {
             insta::assert_snapshot!(stdout, @r###"
             branchless: <git-executable> rebase 62fc20d2a290daea0d52bdc2ed2ad4be6491010e 96d1c37a3d4363611c49f7e52186e189a04c531f --onto ae94dc2a748bc0965c88fcf3edac2e30074ff7e2 --committer-date-is-author-date
             branchless: no more abandoned commits to restack
-            branchless: <git-executable> branch -f master 51452b55e09488387e59770a9f44d999eba27864
+            branchless: moved branch master from 96d1c37a3d4363611c49f7e52186e189a04c531f to 51452b55e09488387e59770a9f44d999eba27864
             branchless: no more abandoned branches to restack
             branchless: <git-executable> checkout ae94dc2a748bc0965c88fcf3edac2e30074ff7e2
             :
@@ -221,3 +223,105 @@ fn test_restack_aborts_during_rebase_conflict() -> anyhow::Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_restack_multiple_branches() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["branch", "bar"])?;
+        git.run(&["commit", "--amend", "-m", "amend test1.txt"])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["restack"])?;
+            let stdout = remove_rebase_lines(stdout);
+            insta::assert_snapshot!(stdout, @r###"
+            branchless: no more abandoned commits to restack
+            branchless: moved branch bar from 62fc20d2a290daea0d52bdc2ed2ad4be6491010e to 024c35ce32dae6b12e981963465ee8a62b7eff9b
+            branchless: moved branch foo from 62fc20d2a290daea0d52bdc2ed2ad4be6491010e to 024c35ce32dae6b12e981963465ee8a62b7eff9b
+            branchless: no more abandoned branches to restack
+            branchless: <git-executable> checkout 024c35ce32dae6b12e981963465ee8a62b7eff9b
+            O f777ecc9 (master) create initial.txt
+            |
+            @ 024c35ce (bar, foo) amend test1.txt
+            "###);
+        }
+
+        {
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let events = event_log_db.get_events()?;
+            let restack_event_tx_id = match events.last() {
+                Some(Event::RefUpdateEvent { event_tx_id, .. }) => *event_tx_id,
+                event => anyhow::bail!("Expected a ref update event, got: {:?}", event),
+            };
+            let ref_names: Vec<&str> = events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::RefUpdateEvent {
+                        event_tx_id,
+                        ref_name,
+                        ..
+                    } if *event_tx_id == restack_event_tx_id
+                        && ref_name.starts_with("refs/heads/") =>
+                    {
+                        Some(ref_name.as_str())
+                    }
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(ref_names, vec!["refs/heads/bar", "refs/heads/foo"]);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_restack_skips_checked_out_branch() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.run(&["branch", "bar"])?;
+        git.run(&["commit", "--amend", "-m", "amend test1.txt"])?;
+        git.run(&["checkout", "foo"])?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["restack"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            let stdout = remove_rebase_lines(stdout);
+            insta::assert_snapshot!(stdout, @r###"
+            branchless: no more abandoned commits to restack
+            branchless: cannot move checked-out branch foo, check out another commit and run 'git restack' again
+            branchless: moved branch bar from 62fc20d2a290daea0d52bdc2ed2ad4be6491010e to 024c35ce32dae6b12e981963465ee8a62b7eff9b
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | o 024c35ce (bar) amend test1.txt
+            |
+            % 62fc20d2 (rewritten as 024c35ce) (foo) create test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
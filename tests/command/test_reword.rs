@@ -0,0 +1,115 @@
+use branchless::testing::{with_git, GitRunOptions};
+
+#[test]
+fn test_reword_mid_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["checkout", "-b", "foo"])?;
+        git.commit_file("test2", 2)?;
+        git.commit_file("test3", 3)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["reword", "HEAD~2", "-m", "reworded test1"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            Reworded as: d93f3da6 reworded test1
+            Attempting rebase in-memory...
+            branchless: processing 2 rewritten commits
+            In-memory rebase succeeded.
+            branchless: no more abandoned branches to restack
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o d93f3da6 reworded test1
+            |
+            o 01e347ef create test2.txt
+            |
+            @ 6e21b891 (foo) create test3.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            ## foo
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reword_unchanged_message() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+
+        {
+            // The test editor accepts the existing message as-is.
+            let (stdout, _stderr) = git.run(&["reword"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            The commit message is unchanged, so there is nothing to reword.
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_reword_rejects_invalid_commits() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["hide", &test2_oid.to_string()])?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["reword", "HEAD", "-m", "reworded test1"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commits on the main branch cannot be reworded: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["reword", &test2_oid.to_string(), "-m", "reworded test2"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commit is not visible, so it cannot be reworded: 96d1c37a3d4363611c49f7e52186e189a04c531f
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            @ 62fc20d2 (master) create test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
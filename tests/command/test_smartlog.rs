@@ -401,3 +401,107 @@ fn test_show_rewritten_commit_hash() -> anyhow::Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_show_stash_entries() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("test1", "stashed change\n")?;
+        git.run(&["stash", "push", "--message", "my stash"])?;
+        git.commit_file("test2", 2)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 62fc20d2 create test1.txt
+            |\
+            | s 37796cc7 stash@{0}: On (no branch): my stash
+            |
+            @ 96d1c37a create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_show_commit_origins() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.run(&["checkout", "--detach", "master"])?;
+        git.run(&["cherry-pick", &test1_oid.to_string()])?;
+        git.run(&["revert", "--no-edit", "HEAD"])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |\
+            | o 047b7ad7 (cherry-picked from 62fc20d2) create test1.txt
+            | |
+            | @ 30dab5a3 (reverts 047b7ad7) Revert "create test1.txt"
+            |
+            o 62fc20d2 create test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_show_other_worktree_heads() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+
+        let worktree_dir = tempfile::tempdir()?;
+        let worktree_path = worktree_dir.path().to_str().unwrap();
+        git.run(&["worktree", "add", "--detach", worktree_path, "HEAD"])?;
+        let worktree_git = Git::new(
+            worktree_dir.path().to_path_buf(),
+            GitExecutable(git.git_executable.clone()),
+        );
+        worktree_git.commit_file("test2", 2)?;
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            @ 62fc20d2 create test1.txt
+            |
+            w 96d1c37a create test2.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = worktree_git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            w 62fc20d2 create test1.txt
+            |
+            @ 96d1c37a create test2.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
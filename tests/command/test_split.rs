@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use branchless::commands::split::testing::{select_hunks, split_commit};
+use branchless::core::eventlog::EventLogDb;
+use branchless::core::formatting::Glyphs;
+use branchless::core::hunks::get_hunks;
+use branchless::core::mergebase::MergeBaseDb;
+use branchless::core::tui::testing::{
+    screen_to_string, CursiveTestingBackend, CursiveTestingEvent,
+};
+use branchless::testing::{with_git, Git, GitRunOptions};
+use branchless::util::{get_db_conn, GitExecutable};
+
+use cursive::event::Key;
+use cursive::CursiveRunnable;
+
+fn run_split(
+    git: &Git,
+    hash: &str,
+    first_message: Option<&str>,
+    events: Vec<CursiveTestingEvent>,
+) -> anyhow::Result<isize> {
+    let glyphs = Glyphs::text();
+    let repo = git.get_repo()?;
+    let commit = repo.revparse_single(hash)?.peel_to_commit()?;
+    let parent_tree = commit.parent(0)?.tree()?;
+    let file_hunks = get_hunks(&repo, Some(&parent_tree), &commit.tree()?, 3)?;
+
+    let siv = CursiveRunnable::new::<Infallible, _>(move || {
+        Ok(CursiveTestingBackend::init(events.clone()))
+    });
+    let selected: HashSet<(usize, usize)> = match select_hunks(siv.into_runner(), &file_hunks)? {
+        Some(selected) => selected,
+        None => return Ok(0),
+    };
+
+    // Ensure that nested calls to `git` are run under the correct environment.
+    std::env::set_current_dir(repo.workdir().unwrap())?;
+    std::env::set_var("PATH", git.get_path_for_env());
+
+    let conn = get_db_conn(&repo)?;
+    let merge_base_db = MergeBaseDb::new(&conn)?;
+    let mut event_log_db = EventLogDb::new(&conn)?;
+    split_commit(
+        &glyphs,
+        &GitExecutable(git.git_executable.clone()),
+        &repo,
+        &merge_base_db,
+        &mut event_log_db,
+        &commit,
+        &file_hunks,
+        &selected,
+        first_message.map(String::from),
+    )
+}
+
+#[test]
+fn test_split_mid_stack() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.restack.preserveTimestamps", "true"])?;
+
+        git.detach_head()?;
+        git.write_file("test1", "test1 contents\n")?;
+        git.write_file("test2", "test2 contents\n")?;
+        git.run(&["add", "."])?;
+        git.run(&["commit", "-m", "create test1.txt and test2.txt"])?;
+        git.run(&["checkout", "-b", "foo"])?;
+        git.commit_file("test3", 3)?;
+
+        {
+            let screenshot1 = Default::default();
+            let exit_code = run_split(
+                &git,
+                "HEAD~",
+                Some("create test1.txt"),
+                vec![
+                    CursiveTestingEvent::Event(' '.into()),
+                    CursiveTestingEvent::Event('j'.into()),
+                    CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+                    CursiveTestingEvent::Event(Key::Enter.into()),
+                ],
+            )?;
+            assert_eq!(exit_code, 0);
+            insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+            test1.txt
+            [x] @@ -0,0 +1 @@
+            +test1 contents
+            test2.txt
+            > [ ] @@ -0,0 +1 @@
+            +test2 contents
+            Select the changes to keep in the first commit (1 of 2 selected). Press 'h' for help, 'q' to quit.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            O f777ecc9 (master) create initial.txt
+            |
+            o 7eea5b77 create test1.txt
+            |
+            o 5e88cc10 create test1.txt and test2.txt
+            |
+            @ 05ad56ec (foo) create test3.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["show", "--name-status", "--format=%s", "HEAD~2"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            create test1.txt
+
+            A	test1.txt
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["status", "--short", "--branch"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            ## foo
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_split_requires_partial_selection() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+
+        let exit_code = run_split(
+            &git,
+            "HEAD",
+            None,
+            vec![
+                CursiveTestingEvent::Event(' '.into()),
+                CursiveTestingEvent::Event(Key::Enter.into()),
+            ],
+        )?;
+        assert_eq!(exit_code, 1);
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            @ 62fc20d2 (master) create test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_split_rejects_invalid_commits() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.detach_head()?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["hide", &test2_oid.to_string()])?;
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["split", "HEAD"],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commits on the main branch cannot be split: 62fc20d2a290daea0d52bdc2ed2ad4be6491010e
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run_with_options(
+                &["split", &test2_oid.to_string()],
+                &GitRunOptions {
+                    expected_exit_code: 1,
+                    ..Default::default()
+                },
+            )?;
+            insta::assert_snapshot!(stdout, @r###"
+            Commit is not visible, so it cannot be split: 96d1c37a3d4363611c49f7e52186e189a04c531f
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["smartlog"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            :
+            @ 62fc20d2 (master) create test1.txt
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -1,9 +1,12 @@
-use std::convert::Infallible;
+use std::convert::{Infallible, TryInto};
 
 use std::rc::Rc;
 
-use branchless::commands::undo::testing::{select_past_event, undo_events};
-use branchless::core::eventlog::{EventCursor, EventLogDb, EventReplayer};
+use branchless::commands::snapshot::record_stash_changes;
+use branchless::commands::undo::testing::{select_past_event, undo_events, undo_transaction};
+use branchless::core::eventlog::{
+    Event, EventCursor, EventLogDb, EventReplayer, EventTransactionId,
+};
 use branchless::core::formatting::Glyphs;
 use branchless::core::mergebase::MergeBaseDb;
 use branchless::core::tui::testing::{
@@ -23,7 +26,8 @@ fn run_select_past_event(
     let conn = get_db_conn(&repo)?;
     let merge_base_db = MergeBaseDb::new(&conn)?;
     let event_log_db: EventLogDb = EventLogDb::new(&conn)?;
-    let mut event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let mut event_replayer = EventReplayer::from_event_log_db(repo, &event_log_db)?;
+    let transaction_messages = event_log_db.get_transaction_messages()?;
     let siv = CursiveRunnable::new::<Infallible, _>(move || {
         Ok(CursiveTestingBackend::init(events.clone()))
     });
@@ -33,15 +37,26 @@ fn run_select_past_event(
         &repo,
         &merge_base_db,
         &mut event_replayer,
+        &transaction_messages,
     )
 }
 
 fn run_undo_events(git: &Git, event_cursor: EventCursor) -> anyhow::Result<String> {
+    let (exit_code, stdout) = run_undo_events_with_stash(git, event_cursor, false)?;
+    assert_eq!(exit_code, 0);
+    Ok(stdout)
+}
+
+fn run_undo_events_with_stash(
+    git: &Git,
+    event_cursor: EventCursor,
+    stash: bool,
+) -> anyhow::Result<(isize, String)> {
     let glyphs = Glyphs::text();
     let repo = git.get_repo()?;
     let conn = get_db_conn(&repo)?;
     let mut event_log_db: EventLogDb = EventLogDb::new(&conn)?;
-    let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
     let input = "y";
     let mut in_ = input.as_bytes();
     let mut out = Vec::new();
@@ -52,7 +67,7 @@ fn run_undo_events(git: &Git, event_cursor: EventCursor) -> anyhow::Result<Strin
     std::env::set_current_dir(repo.workdir().unwrap())?;
     std::env::set_var("PATH", git.get_path_for_env());
 
-    let result = undo_events(
+    let exit_code = undo_events(
         &mut in_,
         &mut out,
         &glyphs,
@@ -61,13 +76,53 @@ fn run_undo_events(git: &Git, event_cursor: EventCursor) -> anyhow::Result<Strin
         &mut event_log_db,
         &event_replayer,
         event_cursor,
+        stash,
+    )?;
+
+    let out = String::from_utf8(out)?;
+    let out = git.preprocess_stdout(out)?;
+    let out = trim_lines(out);
+    Ok((exit_code, out))
+}
+
+fn run_undo_transaction(
+    git: &Git,
+    find_transaction_id: impl Fn(&Event) -> bool,
+) -> anyhow::Result<(isize, String)> {
+    let glyphs = Glyphs::text();
+    let repo = git.get_repo()?;
+    let conn = get_db_conn(&repo)?;
+    let mut event_log_db: EventLogDb = EventLogDb::new(&conn)?;
+    let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+    let transaction_id: EventTransactionId = event_replayer
+        .get_events_since_cursor(event_replayer.make_cursor(0))
+        .iter()
+        .find(|event| find_transaction_id(event))
+        .expect("Should have found an event for the transaction")
+        .get_event_tx_id();
+    let input = "y";
+    let mut in_ = input.as_bytes();
+    let mut out = Vec::new();
+
+    std::env::set_current_dir(repo.workdir().unwrap())?;
+    std::env::set_var("PATH", git.get_path_for_env());
+
+    let exit_code = undo_transaction(
+        &mut in_,
+        &mut out,
+        &glyphs,
+        &repo,
+        &GitExecutable(git.git_executable.clone()),
+        &mut event_log_db,
+        &event_replayer,
+        transaction_id,
+        false,
     )?;
-    assert_eq!(result, 0);
 
     let out = String::from_utf8(out)?;
     let out = git.preprocess_stdout(out)?;
     let out = trim_lines(out);
-    Ok(out)
+    Ok((exit_code, out))
 }
 
 fn trim_lines(output: String) -> String {
@@ -100,6 +155,9 @@ fn test_undo_help() -> anyhow::Result<()> {
             │ q: Quit.                                                                                           │
             │ p/n or <left>/<right>: View next/previous state.                                                   │
             │ g: Go to a provided event ID.                                                                      │
+            │ /: Search backwards for a transaction by message, ref name, commit message, or commit hash.        │
+            │ t: Only show transactions of the given types when using p/n (e.g. `move, restack, rebase`).        │
+            │ d: Show/hide the changes that reverting to the given state would make.                             │
             │ <enter>: Revert the repository to the given state (requires confirmation).                         │
             │                                                                                                    │
             │ You can also copy a commit hash from the past and manually run `git unhide` or `git rebase` on it. │
@@ -147,7 +205,7 @@ fn test_undo_navigate() -> anyhow::Result<()> {
             insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
             :
             @ 96d1c37a (master) create test2.txt
-            Repo after transaction 3 (event 4). Press 'h' for help, 'q' to quit.
+            Repo after transaction 3 [reference-transaction] (event 4). Press 'h' for help, 'q' to quit.
             1. Check out from 62fc20d2 create test1.txt
             to 96d1c37a create test2.txt
             2. Move branch master from 62fc20d2 create test1.txt
@@ -156,7 +214,7 @@ fn test_undo_navigate() -> anyhow::Result<()> {
             insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
             :
             @ 96d1c37a (master) create test2.txt
-            Repo after transaction 4 (event 6). Press 'h' for help, 'q' to quit.
+            Repo after transaction 4 [hook-post-commit] (event 6). Press 'h' for help, 'q' to quit.
             1. Commit 96d1c37a create test2.txt
             "###);
         };
@@ -193,7 +251,7 @@ fn test_go_to_event() -> anyhow::Result<()> {
         insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
         :
         @ 96d1c37a (master) create test2.txt
-        Repo after transaction 4 (event 6). Press 'h' for help, 'q' to quit.
+        Repo after transaction 4 [hook-post-commit] (event 6). Press 'h' for help, 'q' to quit.
         1. Commit 96d1c37a create test2.txt
         "###);
         insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
@@ -201,7 +259,7 @@ fn test_go_to_event() -> anyhow::Result<()> {
         @ 62fc20d2 create test1.txt
         |
         O 96d1c37a (master) create test2.txt
-        Repo after transaction 1 (event 1). Press 'h' for help, 'q' to quit.
+        Repo after transaction 1 [reference-transaction] (event 1). Press 'h' for help, 'q' to quit.
         1. Check out from f777ecc9 create initial.txt
         to 62fc20d2 create test1.txt
         "###);
@@ -261,6 +319,8 @@ fn test_undo_hide() -> anyhow::Result<()> {
 
             2. Unhide commit 62fc20d2 create test1.txt
 
+            These references will move:
+              branch test1: (none) -> 62fc20d2
             Confirm? [yN] Applied 2 inverse events.
             "###);
         }
@@ -319,6 +379,9 @@ fn test_undo_move_refs() -> anyhow::Result<()> {
 
             3. Move branch master from 96d1c37a create test2.txt
                                     to 62fc20d2 create test1.txt
+            These references will move:
+              HEAD: 96d1c37a -> 62fc20d2
+              branch master: 96d1c37a -> 62fc20d2
             Confirm? [yN] Applied 3 inverse events.
             "###);
         }
@@ -358,13 +421,13 @@ fn test_historical_smartlog_visibility() -> anyhow::Result<()> {
             insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
         :
         % 62fc20d2 (manually hidden) (master) create test1.txt
-        Repo after transaction 3 (event 4). Press 'h' for help, 'q' to quit.
+        Repo after transaction 3 [hide] (event 4). Press 'h' for help, 'q' to quit.
         1. Hide commit 62fc20d2 create test1.txt
         "###);
             insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
         :
         @ 62fc20d2 (master) create test1.txt
-        Repo after transaction 2 (event 3). Press 'h' for help, 'q' to quit.
+        Repo after transaction 2 [hook-post-commit] (event 3). Press 'h' for help, 'q' to quit.
         1. Commit 62fc20d2 create test1.txt
         "###);
         } else {
@@ -441,6 +504,11 @@ fn test_undo_doesnt_make_working_dir_dirty() -> anyhow::Result<()> {
                                     to f777ecc9 create initial.txt
             5. Delete branch foo at f777ecc9 create initial.txt
 
+            These references will move:
+              HEAD: 62fc20d2 -> f777ecc9
+              branch bar: 62fc20d2 -> (none)
+              branch master: 62fc20d2 -> f777ecc9
+              branch foo: f777ecc9 -> (none)
             Confirm? [yN] Applied 5 inverse events.
             "###);
         }
@@ -452,3 +520,451 @@ fn test_undo_doesnt_make_working_dir_dirty() -> anyhow::Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_undo_restores_working_copy_snapshot() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("initial", "uncommitted change\n")?;
+        git.run(&["prev"])?;
+        git.write_file("initial", "change made after prev\n")?;
+
+        let event_cursor = {
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+            let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+            let snapshot_event_id = events
+                .iter()
+                .position(|event| matches!(event, Event::WorkingCopySnapshotEvent { .. }))
+                .expect("Should have taken a working copy snapshot");
+            event_replayer.make_cursor(snapshot_event_id.try_into()?)
+        };
+
+        let (exit_code, stdout) = run_undo_events_with_stash(&git, event_cursor, true)?;
+        assert_eq!(exit_code, 0);
+        assert!(stdout.contains("Restore working copy from snapshot"));
+        assert!(stdout.contains("Restored working copy from snapshot"));
+
+        let repo = git.get_repo()?;
+        assert_eq!(
+            repo.head()?.peel_to_commit()?.id(),
+            repo.revparse_single("master")?.id()
+        );
+        let contents = std::fs::read_to_string(repo.workdir().unwrap().join("initial.txt"))?;
+        assert_eq!(contents, "uncommitted change\n");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_restores_untracked_files_from_snapshot() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("untracked", "untracked contents\n")?;
+        git.run(&["prev"])?;
+        git.write_file("untracked", "untracked contents after prev\n")?;
+
+        let event_cursor = {
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+            let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+            let snapshot_event_id = events
+                .iter()
+                .position(|event| matches!(event, Event::WorkingCopySnapshotEvent { .. }))
+                .expect("Should have taken a working copy snapshot");
+            event_replayer.make_cursor(snapshot_event_id.try_into()?)
+        };
+
+        {
+            // The untracked file present now would be clobbered by restoring
+            // the snapshot, so it counts as an uncommitted change.
+            let (exit_code, stdout) = run_undo_events_with_stash(&git, event_cursor, false)?;
+            assert_eq!(exit_code, 1);
+            assert!(stdout.contains("The working copy has uncommitted changes"));
+            let contents = std::fs::read_to_string(git.repo_path.join("untracked.txt"))?;
+            assert_eq!(contents, "untracked contents after prev\n");
+        }
+
+        let (exit_code, stdout) = run_undo_events_with_stash(&git, event_cursor, true)?;
+        assert_eq!(exit_code, 0);
+        assert!(stdout.contains("Restored working copy from snapshot"));
+        let (stdout, _stderr) = git.run(&["show", "stash@{0}^3:untracked.txt"])?;
+        assert_eq!(stdout, "untracked contents after prev\n");
+
+        let repo = git.get_repo()?;
+        assert_eq!(
+            repo.head()?.peel_to_commit()?.id(),
+            repo.revparse_single("master")?.id()
+        );
+        let contents = std::fs::read_to_string(repo.workdir().unwrap().join("untracked.txt"))?;
+        assert_eq!(contents, "untracked contents\n");
+        let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+        assert_eq!(stdout, "?? untracked.txt\n");
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_refuses_dirty_working_copy() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("test1", "uncommitted change\n")?;
+
+        let event_cursor = {
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+            event_replayer.make_cursor(0)
+        };
+
+        {
+            let (exit_code, stdout) = run_undo_events_with_stash(&git, event_cursor, false)?;
+            assert_eq!(exit_code, 1);
+            insta::assert_snapshot!(stdout, @r###"
+            The working copy has uncommitted changes, which could be overwritten by this undo.
+            Commit or stash your changes first, or re-run with --stash to stash them automatically.
+            "###);
+            let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+            assert_eq!(stdout, " M test1.txt\n");
+
+            // The refused undo shouldn't be recorded as a transaction.
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            let transaction_messages = event_log_db.get_transaction_messages()?;
+            assert!(!transaction_messages
+                .values()
+                .any(|message| message == "undo"));
+        }
+
+        {
+            let (exit_code, _stdout) = run_undo_events_with_stash(&git, event_cursor, true)?;
+            assert_eq!(exit_code, 0);
+            let (stdout, _stderr) = git.run(&["status", "--porcelain"])?;
+            assert_eq!(stdout, "");
+            let (stdout, _stderr) = git.run(&["stash", "list"])?;
+            assert!(stdout.contains("branchless: stash before undo"));
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_search() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["branch", "foo"])?;
+
+        let screenshot1 = Default::default();
+        let screenshot2 = Default::default();
+        let mut events = vec![CursiveTestingEvent::Event('/'.into())];
+        events.extend(
+            "test1"
+                .chars()
+                .map(|c| CursiveTestingEvent::Event(c.into())),
+        );
+        events.extend(vec![
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+            CursiveTestingEvent::Event('/'.into()),
+        ]);
+        events.extend(
+            "nonexistent"
+                .chars()
+                .map(|c| CursiveTestingEvent::Event(c.into())),
+        );
+        events.extend(vec![
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot2)),
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::Event('q'.into()),
+        ]);
+        run_select_past_event(&git.get_repo()?, events)?;
+
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+:
+@ 96d1c37a (master) create test2.txt
+Repo after transaction 3 [reference-transaction] (event 4). Press 'h' for help, 'q' to quit.
+1. Check out from 62fc20d2 create test1.txt
+to 96d1c37a create test2.txt
+2. Move branch master from 62fc20d2 create test1.txt
+to 96d1c37a create test2.txt
+"###);
+        insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
+:
+@ 96d1c37a (master) cr┌─────────────────────────────────────────────┐
+Repo after transaction│ No earlier transaction matches: nonexistent │ for help, 'q' to quit.
+1. Check out from 62fc│                                             │
+to 96d1│                                        <Ok> │
+2. Move branch master └─────────────────────────────────────────────┘
+"###);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_filter_transactions() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.commit_file("test2", 2)?;
+        git.run(&["branch", "bar"])?;
+
+        let screenshot1 = Default::default();
+        let screenshot2 = Default::default();
+        let mut events = vec![CursiveTestingEvent::Event('t'.into())];
+        events.extend(
+            "hook-post-commit"
+                .chars()
+                .map(|c| CursiveTestingEvent::Event(c.into())),
+        );
+        events.extend(vec![
+            CursiveTestingEvent::Event(Key::Enter.into()),
+            CursiveTestingEvent::Event('p'.into()),
+            CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+            CursiveTestingEvent::Event('p'.into()),
+            CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot2)),
+            CursiveTestingEvent::Event('q'.into()),
+        ]);
+        run_select_past_event(&git.get_repo()?, events)?;
+
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+:
+O 62fc20d2 (foo) create test1.txt
+|
+@ 96d1c37a (master) create test2.txt
+Repo after transaction 5 [hook-post-commit] (event 7). Press 'h' for help, 'q' to quit. Showing only: hook-post-
+commit.
+1. Commit 96d1c37a create test2.txt
+"###);
+        insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
+:
+@ 62fc20d2 (master) create test1.txt
+Repo after transaction 2 [hook-post-commit] (event 3). Press 'h' for help, 'q' to quit. Showing only: hook-post-
+commit.
+1. Commit 62fc20d2 create test1.txt
+"###);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_show_diff() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.commit_file("test2", 2)?;
+        git.run(&["hide", "HEAD"])?;
+
+        let screenshot1 = Default::default();
+        let screenshot2 = Default::default();
+        run_select_past_event(
+            &git.get_repo()?,
+            vec![
+                CursiveTestingEvent::Event('d'.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot1)),
+                CursiveTestingEvent::Event('g'.into()),
+                CursiveTestingEvent::Event('1'.into()),
+                CursiveTestingEvent::Event(Key::Enter.into()),
+                CursiveTestingEvent::TakeScreenshot(Rc::clone(&screenshot2)),
+                CursiveTestingEvent::Event('q'.into()),
+            ],
+        )?;
+
+        insta::assert_snapshot!(screen_to_string(&screenshot1), @r###"
+:
+O 62fc20d2 (foo) create test1.txt
+|
+% 96d1c37a (manually hidden) (master) create test2.txt
+Repo after transaction 6 [hide] (event 8). Press 'h' for help, 'q' to quit.
+1. Hide commit 96d1c37a create test2.txt
+
+
+Reverting to this state would make no changes.
+"###);
+        insta::assert_snapshot!(screen_to_string(&screenshot2), @r###"
+:
+@ 62fc20d2 create test1.txt
+|
+O 96d1c37a (master) create test2.txt
+Repo after transaction 1 [reference-transaction] (event 1). Press 'h' for help, 'q' to quit.
+1. Check out from f777ecc9 create initial.txt
+to 62fc20d2 create test1.txt
+
+Reverting to this state would make these changes:
+Move HEAD from 96d1c37a create test2.txt to 62fc20d2 create test1.txt
+Move branch foo from 62fc20d2 create test1.txt to (none)
+Hide commit 62fc20d2 create test1.txt
+"###);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_transaction() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.run(&["checkout", "--detach"])?;
+        git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.run(&["hide", &test2_oid.to_string()])?;
+        git.run(&["branch", "foo"])?;
+
+        let (exit_code, stdout) =
+            run_undo_transaction(&git, |event| matches!(event, Event::HideEvent { .. }))?;
+        assert_eq!(exit_code, 0);
+        insta::assert_snapshot!(stdout, @r###"
+Will apply these actions:
+1. Unhide commit 96d1c37a create test2.txt
+
+Confirm? [yN] Applied 1 inverse event.
+"###);
+
+        let (stdout, _stderr) = git.run(&["smartlog"])?;
+        insta::assert_snapshot!(stdout, @r###"
+O f777ecc9 (master) create initial.txt
+|
+@ 62fc20d2 (foo) create test1.txt
+|
+o 96d1c37a create test2.txt
+"###);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_transaction_conflict() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["branch", "foo"])?;
+        git.commit_file("test2", 2)?;
+        git.run(&["branch", "-f", "foo", "HEAD"])?;
+
+        let (exit_code, stdout) = run_undo_transaction(&git, |event| {
+            matches!(
+                event,
+                Event::RefUpdateEvent {
+                    ref_name,
+                    old_ref: None,
+                    ..
+                } if ref_name == "refs/heads/foo"
+            )
+        })?;
+        assert_eq!(exit_code, 1);
+        insta::assert_snapshot!(stdout, @r###"
+Cannot undo transaction 3 by itself, because later transactions also changed:
+  branch foo (changed again in transaction 6)
+Run `git undo` without --transaction to undo those transactions as well.
+"###);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_undo_restores_dropped_stash() -> anyhow::Result<()> {
+    with_git(|git| {
+        if !git.supports_reference_transactions()? {
+            return Ok(());
+        }
+
+        git.init_repo()?;
+        git.commit_file("test1", 1)?;
+        git.write_file("test1", "first stashed change\n")?;
+        git.run(&["stash", "push", "--message", "first stash"])?;
+        git.write_file("test1", "second stashed change\n")?;
+        git.run(&["stash", "push", "--message", "second stash"])?;
+
+        // Dropping an entry other than the most recent one doesn't update
+        // `refs/stash`, so it's only recorded once `git undo` reconciles the
+        // stash list.
+        git.run(&["stash", "drop", "stash@{1}"])?;
+
+        let event_cursor = {
+            let repo = git.get_repo()?;
+            let conn = get_db_conn(&repo)?;
+            let mut event_log_db = EventLogDb::new(&conn)?;
+            record_stash_changes(&repo, &mut event_log_db, None)?;
+            let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+            let events = event_replayer.get_events_since_cursor(event_replayer.make_cursor(0));
+            let drop_event_id = events
+                .iter()
+                .position(|event| matches!(event, Event::StashDropEvent { .. }))
+                .expect("Should have recorded the stash drop");
+            event_replayer.make_cursor(drop_event_id.try_into()?)
+        };
+
+        {
+            let stdout = run_undo_events(&git, event_cursor)?;
+            insta::assert_snapshot!(stdout, @r###"
+            Will apply these actions:
+            1. Push stash 14edf479 On master: first stash
+
+            Confirm? [yN] Applied 1 inverse event.
+            "###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["stash", "list"])?;
+            insta::assert_snapshot!(stdout, @r###"
+            stash@{0}: On master: first stash
+            stash@{1}: On master: second stash
+            "###);
+        }
+
+        Ok(())
+    })
+}
@@ -1,3 +1,6 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
 use branchless::core::eventlog::testing::{get_event_replayer_events, redact_event_timestamp};
 use branchless::core::eventlog::{Event, EventLogDb, EventReplayer};
 use branchless::testing::with_git;
@@ -18,9 +21,10 @@ fn test_git_v2_31_events() -> anyhow::Result<()> {
         git.run(&["hide", "test1"])?;
         git.run(&["branch", "-D", "test1"])?;
 
-        let conn = get_db_conn(&git.get_repo()?)?;
+        let repo = git.get_repo()?;
+        let conn = get_db_conn(&repo)?;
         let event_log_db = EventLogDb::new(&conn)?;
-        let event_replayer = EventReplayer::from_event_log_db(&event_log_db)?;
+        let event_replayer = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
         let events: Vec<Event> = get_event_replayer_events(&event_replayer)
             .iter()
             .cloned()
@@ -161,3 +165,73 @@ fn test_git_v2_31_events() -> anyhow::Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_compact_events_preserves_replay() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.run(&["config", "branchless.gc.compactEventsAfter", "10000"])?;
+        git.run(&["checkout", "-b", "foo"])?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        git.run(&["checkout", "master"])?;
+        git.commit_file("test3", 3)?;
+        git.run(&["branch", "-f", "foo", &test1_oid.to_string()])?;
+        git.run(&["hide", &test2_oid.to_string()])?;
+        git.run(&["branchless", "gc"])?;
+        git.run(&["gc", "--prune=now"])?;
+
+        let repo = git.get_repo()?;
+        assert!(repo.find_commit(test2_oid).is_err());
+
+        let conn = get_db_conn(&repo)?;
+        let mut event_log_db = EventLogDb::new(&conn)?;
+        let event_replayer_before = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+        let num_events_before = event_log_db.get_events()?.len();
+
+        // Compact every event in the event log.
+        let num_removed_events =
+            event_log_db.compact(&repo, SystemTime::now() + Duration::from_secs(24 * 60 * 60))?;
+        let event_replayer_after = EventReplayer::from_event_log_db(&repo, &event_log_db)?;
+        assert!(num_removed_events > 0);
+        assert_eq!(
+            event_log_db.get_events()?.len(),
+            num_events_before - num_removed_events
+        );
+
+        let cursor_before = event_replayer_before.make_default_cursor();
+        let cursor_after = event_replayer_after.make_default_cursor();
+        let active_oids: HashSet<git2::Oid> = event_replayer_before
+            .get_cursor_active_oids(cursor_before)
+            .into_iter()
+            .chain(event_replayer_after.get_cursor_active_oids(cursor_after))
+            .filter(|oid| repo.find_commit(*oid).is_ok())
+            .collect();
+        assert!(active_oids.contains(&test1_oid));
+        for oid in active_oids {
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    event_replayer_before.get_cursor_commit_visibility(cursor_before, oid)
+                ),
+                format!(
+                    "{:?}",
+                    event_replayer_after.get_cursor_commit_visibility(cursor_after, oid)
+                ),
+                "Visibility differs for commit {:?}",
+                oid
+            );
+        }
+
+        assert_eq!(
+            event_replayer_before.get_cursor_branch_oid_to_names(cursor_before, &repo)?,
+            event_replayer_after.get_cursor_branch_oid_to_names(cursor_after, &repo)?,
+        );
+        assert_eq!(
+            event_replayer_before.get_cursor_head_oid(cursor_before),
+            event_replayer_after.get_cursor_head_oid(cursor_after),
+        );
+
+        Ok(())
+    })
+}
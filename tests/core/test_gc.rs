@@ -22,10 +22,23 @@ o 62fc20d2 create test1.txt
         }
 
         git.run(&["hide", "62fc20d2"])?;
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc", "--dry-run"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage (dry run)
+branchless: would delete 1 reference, keep 0 references
+branchless: would compact 0 events, leaving 9 events in the event log
+branchless: would remove 0 entries, leaving 2 entries in the merge-base cache
+"###);
+        }
+
         {
             let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
             insta::assert_snapshot!(stdout, @r###"
 branchless: collecting garbage
+branchless: deleted 1 reference, kept 0 references
+branchless: compacted 0 events, leaving 9 events in the event log
+branchless: removed 0 entries, leaving 2 entries in the merge-base cache
 "###);
         }
 
@@ -45,3 +58,213 @@ branchless: collecting garbage
         Ok(())
     })
 }
+
+#[test]
+fn test_gc_dry_run() -> anyhow::Result<()> {
+    branchless::testing::with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.commit_file("test2", 2)?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.run(&["hide", "HEAD@{1}"])?;
+
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc", "--dry-run"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage (dry run)
+branchless: would delete 1 reference, keep 1 reference
+branchless: would compact 0 events, leaving 9 events in the event log
+branchless: would remove 0 entries, leaving 3 entries in the merge-base cache
+branchless: oldest retained commit: 62fc20d2 create test1.txt
+"###);
+        }
+
+        {
+            let repo = git.get_repo()?;
+            assert!(repo
+                .find_reference("refs/branchless/96d1c37a3d4363611c49f7e52186e189a04c531f")
+                .is_ok());
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc_retain_hidden_for() -> anyhow::Result<()> {
+    branchless::testing::with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.run(&["hide", "62fc20d2"])?;
+
+        git.run(&["config", "branchless.gc.retainHiddenFor", "1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage
+branchless: deleted 0 references, kept 1 reference
+branchless: compacted 0 events, leaving 7 events in the event log
+branchless: removed 0 entries, leaving 2 entries in the merge-base cache
+branchless: oldest retained commit: 62fc20d2 create test1.txt
+"###);
+        }
+
+        git.run(&["gc", "--prune=now"])?;
+        {
+            let repo = git.get_repo()?;
+            assert!(matches!(repo.revparse_single("62fc20d2"), Ok(_)));
+        }
+
+        git.run(&["config", "branchless.gc.retainHiddenFor", "0"])?;
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage
+branchless: deleted 1 reference, kept 0 references
+branchless: compacted 0 events, leaving 9 events in the event log
+branchless: removed 0 entries, leaving 2 entries in the merge-base cache
+"###);
+        }
+
+        git.run(&["gc", "--prune=now"])?;
+        {
+            let repo = git.get_repo()?;
+            assert!(matches!(repo.revparse_single("62fc20d2"), Err(_)));
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc_prune_merge_base_cache() -> anyhow::Result<()> {
+    branchless::testing::with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        git.commit_file("test1", 1)?;
+        git.run(&["checkout", "HEAD^"])?;
+        git.commit_file("test2", 2)?;
+        git.run(&["smartlog"])?;
+
+        git.run(&["hide", "62fc20d2"])?;
+        git.run(&["branchless", "gc"])?;
+        git.run(&["gc", "--prune=now"])?;
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage
+branchless: deleted 0 references, kept 1 reference
+branchless: compacted 1 event, leaving 10 events in the event log
+branchless: removed 1 entry, leaving 2 entries in the merge-base cache
+branchless: oldest retained commit: fe65c1fe create test2.txt
+"###);
+        }
+
+        git.run(&["config", "branchless.gc.mergeBaseCacheSize", "1"])?;
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc", "--dry-run"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage (dry run)
+branchless: would delete 0 references, keep 1 reference
+branchless: would compact 0 events, leaving 10 events in the event log
+branchless: would remove 1 entry, leaving 1 entry in the merge-base cache
+branchless: oldest retained commit: fe65c1fe create test2.txt
+"###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage
+branchless: deleted 0 references, kept 1 reference
+branchless: compacted 0 events, leaving 10 events in the event log
+branchless: removed 1 entry, leaving 1 entry in the merge-base cache
+branchless: oldest retained commit: fe65c1fe create test2.txt
+"###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc", "--clear-cache", "--dry-run"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage (dry run)
+branchless: would delete 0 references, keep 1 reference
+branchless: would compact 0 events, leaving 10 events in the event log
+branchless: would remove 2 entries from the merge-base cache
+branchless: oldest retained commit: fe65c1fe create test2.txt
+"###);
+        }
+
+        {
+            let (stdout, _stderr) = git.run(&["branchless", "gc", "--clear-cache"])?;
+            insta::assert_snapshot!(stdout, @r###"
+branchless: collecting garbage
+branchless: deleted 0 references, kept 1 reference
+branchless: compacted 0 events, leaving 10 events in the event log
+branchless: removed 2 entries, leaving 0 entries in the merge-base cache
+branchless: oldest retained commit: fe65c1fe create test2.txt
+"###);
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_gc_merge_base_cache_eviction_order() -> anyhow::Result<()> {
+    branchless::testing::with_git(|git| {
+        git.init_repo()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        let test2_oid = git.commit_file("test2", 2)?;
+        let test3_oid = git.commit_file("test3", 3)?;
+
+        let repo = git.get_repo()?;
+        let conn = branchless::util::get_db_conn(&repo)?;
+        let merge_base_db = branchless::core::mergebase::MergeBaseDb::new(&conn)?;
+        merge_base_db.clear()?;
+        merge_base_db.get_merge_base_oid(&repo, test1_oid, test2_oid)?;
+        merge_base_db.get_merge_base_oid(&repo, test2_oid, test3_oid)?;
+        merge_base_db.get_merge_base_oid(&repo, test1_oid, test3_oid)?;
+
+        let entry = |lhs_oid: git2::Oid, rhs_oid: git2::Oid| {
+            let (lhs_oid, rhs_oid) = if lhs_oid < rhs_oid {
+                (lhs_oid, rhs_oid)
+            } else {
+                (rhs_oid, lhs_oid)
+            };
+            (lhs_oid.to_string(), rhs_oid.to_string())
+        };
+
+        // Entries without an access time (written by older versions) should be
+        // evicted before any others.
+        let (lhs_oid, rhs_oid) = entry(test2_oid, test3_oid);
+        conn.execute(
+            "UPDATE merge_base_oids SET last_accessed = NULL WHERE lhs_oid = ?1 AND rhs_oid = ?2",
+            rusqlite::params![lhs_oid, rhs_oid],
+        )?;
+
+        let get_entries = || -> anyhow::Result<Vec<(String, String)>> {
+            let entries = conn
+                .prepare("SELECT lhs_oid, rhs_oid FROM merge_base_oids ORDER BY rowid")?
+                .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(entries)
+        };
+
+        assert_eq!(merge_base_db.evict_least_recently_used(2)?, 1);
+        assert_eq!(
+            get_entries()?,
+            vec![entry(test1_oid, test2_oid), entry(test1_oid, test3_oid)]
+        );
+
+        assert_eq!(merge_base_db.evict_least_recently_used(1)?, 1);
+        assert_eq!(get_entries()?, vec![entry(test1_oid, test3_oid)]);
+
+        assert_eq!(merge_base_db.evict_least_recently_used(1)?, 0);
+        assert_eq!(get_entries()?, vec![entry(test1_oid, test3_oid)]);
+
+        Ok(())
+    })
+}
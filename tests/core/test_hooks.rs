@@ -1,5 +1,9 @@
+use std::collections::HashSet;
+use std::process::{Command, Stdio};
+
+use branchless::core::eventlog::{CommitOriginType, Event, EventLogDb};
 use branchless::testing::with_git;
-use std::process::Command;
+use branchless::util::get_db_conn;
 
 fn preprocess_stderr(stderr: String) -> String {
     stderr
@@ -188,3 +192,115 @@ fn test_pre_auto_gc() -> anyhow::Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_concurrent_hooks() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+
+        let repo = git.get_repo()?;
+        let num_events_before = {
+            let conn = get_db_conn(&repo)?;
+            let event_log_db = EventLogDb::new(&conn)?;
+            event_log_db.get_events()?.len()
+        };
+
+        // Invoke the hook directly many times in parallel, as happens when
+        // several Git processes run at once.
+        let num_processes = 32;
+        let head_oid = repo.head()?.peel_to_commit()?.id().to_string();
+        let children = (0..num_processes)
+            .map(|_| {
+                Command::new(&git.git_executable)
+                    .args(&[
+                        "branchless",
+                        "hook-post-checkout",
+                        &head_oid,
+                        &head_oid,
+                        "1",
+                    ])
+                    .current_dir(&git.repo_path)
+                    .env_clear()
+                    .env("PATH", git.get_path_for_env())
+                    .env("PATH_TO_GIT", &git.git_executable)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for child in children {
+            let output = child.wait_with_output()?;
+            assert!(
+                output.status.success(),
+                "Hook failed with exit code {:?}:
+                Stderr:
+                {}",
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr),
+            );
+        }
+
+        let conn = get_db_conn(&repo)?;
+        let journal_mode: String =
+            conn.query_row("PRAGMA journal_mode", rusqlite::params![], |row| row.get(0))?;
+        assert_eq!(journal_mode, "wal");
+
+        let event_log_db = EventLogDb::new(&conn)?;
+        let events = event_log_db.get_events()?;
+        assert_eq!(events.len(), num_events_before + num_processes);
+        let event_tx_ids: HashSet<String> = events[num_events_before..]
+            .iter()
+            .map(|event| event.get_event_tx_id().to_string())
+            .collect();
+        assert_eq!(event_tx_ids.len(), num_processes);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_commit_origin_events() -> anyhow::Result<()> {
+    with_git(|git| {
+        git.init_repo()?;
+        git.detach_head()?;
+        let test1_oid = git.commit_file("test1", 1)?;
+        git.run(&["checkout", "master"])?;
+        git.run(&["cherry-pick", &test1_oid.to_string()])?;
+        git.run(&["revert", "--no-edit", "HEAD"])?;
+        // `git merge` doesn't run the `post-commit` hook, but committing the
+        // merge afterwards does.
+        git.run(&["merge", "--no-ff", "--no-commit", &test1_oid.to_string()])?;
+        git.run(&["commit", "--no-edit"])?;
+
+        let repo = git.get_repo()?;
+        let cherry_pick_oid = repo.revparse_single("HEAD~2")?.id();
+        let revert_oid = repo.revparse_single("HEAD^")?.id();
+        let merge_oid = repo.revparse_single("HEAD")?.id();
+        let conn = get_db_conn(&repo)?;
+        let event_log_db = EventLogDb::new(&conn)?;
+        let commit_origins: Vec<(git2::Oid, CommitOriginType, git2::Oid)> = event_log_db
+            .get_events()?
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::CommitOriginEvent {
+                    timestamp: _,
+                    event_tx_id: _,
+                    commit_oid,
+                    origin_type,
+                    source_oid,
+                } => Some((commit_oid, origin_type, source_oid)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            commit_origins,
+            vec![
+                (cherry_pick_oid, CommitOriginType::CherryPick, test1_oid),
+                (revert_oid, CommitOriginType::Revert, cherry_pick_oid),
+                (merge_oid, CommitOriginType::Merge, test1_oid),
+            ]
+        );
+
+        Ok(())
+    })
+}
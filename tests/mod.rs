@@ -5,11 +5,17 @@ mod core {
 }
 
 mod command {
+    mod test_absorb;
+    mod test_amend;
+    mod test_fold;
     mod test_hide;
     mod test_init;
     mod test_move;
     mod test_navigation;
+    mod test_obsmarkers;
     mod test_restack;
+    mod test_reword;
     mod test_smartlog;
+    mod test_split;
     mod test_undo;
 }